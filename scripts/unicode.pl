@@ -0,0 +1,260 @@
+#!/usr/bin/env perl
+# writes src/unicode.rs from the Unicode character database perl carries with it, which has to be
+# the 14.0 one so every table in there agrees with every other:
+#
+#     perl scripts/unicode.pl > src/unicode.rs
+use strict;
+use warnings;
+use Unicode::Normalize qw(NFC NFD);
+use Unicode::UCD qw(all_casefolds prop_invlist prop_invmap prop_value_aliases prop_values);
+
+my $version = Unicode::UCD::UnicodeVersion();
+die "perl has Unicode $version, not 14.0.0\n" unless $version eq "14.0.0";
+
+# the items, comma separated, as many to a line as fit in 104 columns
+sub pack_items {
+    my @items = @_;
+    my (@lines, $line);
+    $line = " " x 7;
+    for my $i (0..$#items) {
+        my $item = " " . $items[$i] . ($i < $#items ? "," : "");
+        if (length($line) + length($item) > 104) {
+            push @lines, $line;
+            $line = " " x 7;
+        }
+        $line .= $item;
+    }
+    push @lines, $line;
+    return join("\n", @lines) . "\n";
+}
+
+sub comment {
+    return join("", map { "    // $_\n" } @_);
+}
+
+sub ranges {
+    my @list = @_;
+    push @list, 0x110000 if @list % 2;
+    my @ranges;
+    for (my $i = 0; $i < @list; $i += 2) {
+        push @ranges, sprintf("(0x%X, 0x%X)", $list[$i], $list[$i + 1] - 1);
+    }
+    return @ranges;
+}
+
+# the union of some inversion lists, as one
+sub union {
+    my %members;
+    for my $list (@_) {
+        my @list = @$list;
+        push @list, 0x110000 if @list % 2;
+        for (my $i = 0; $i < @list; $i += 2) {
+            $members{$_} = 1 for $list[$i]..$list[$i + 1] - 1;
+        }
+    }
+    my @list;
+    for my $a (sort { $a <=> $b } keys %members) {
+        if (@list && $list[-1] == $a) {
+            $list[-1]++;
+        } else {
+            push @list, $a, $a + 1;
+        }
+    }
+    return @list;
+}
+
+sub table {
+    my ($name, $list, @comment) = @_;
+    return comment(@comment) . "    pub(crate) const $name: &[(u32, u32)] = &[\n" . pack_items(ranges(@$list)) . "    ];\n";
+}
+
+# a list of (long name, short name, ranges), the ranges wrapped under their entry
+sub named_tables {
+    my @entries = @_;
+    my $out = "";
+    for my $i (0..$#entries) {
+        my ($long, $short, $ranges) = @{$entries[$i]};
+        my $line = "        (\"$long\", \"$short\", &[";
+        my @lines;
+        for my $j (0..$#$ranges) {
+            my $item = $ranges->[$j] . ($j < $#$ranges ? "," : "])" . ($i < $#entries ? "," : ""));
+            if (length($line) + length($item) + 1 > 104) {
+                push @lines, $line;
+                $line = " " x 11;
+            }
+            $line .= ($line =~ /\[$/ ? "" : " ") . $item;
+        }
+        push @lines, $line;
+        $out .= join("\n", @lines) . "\n";
+    }
+    return $out;
+}
+
+my @sections;
+
+push @sections, table("DECIMAL_NUMBER", [prop_invlist("gc=Nd")], "general category Nd, what \\d matches");
+
+push @sections, table("WORD_CHARACTER", [union([prop_invlist("Alphabetic")], [prop_invlist("gc=M")], [prop_invlist("gc=Nd")], [prop_invlist("gc=Pc")],
+    [prop_invlist("Join_Control")])], "Alphabetic, the marks, Nd, Pc and the two joiners: what \\w matches");
+
+push @sections, table("WHITE_SPACE", [prop_invlist("White_Space")], "the White_Space chars, what \\s matches");
+
+# the binary properties \p knows besides the general categories, scripts and blocks
+for (["ALPHABETIC", "Alphabetic"], ["LOWERCASE", "Lowercase"], ["UPPERCASE", "Uppercase"]) {
+    my ($name, $property) = @$_;
+    push @sections, table($name, [prop_invlist($property)], "the $property chars, what \\p{$property} matches");
+}
+
+my @decompositions;
+for my $code (0..0x10FFFF) {
+    next if $code >= 0xD800 && $code < 0xE000 || $code >= 0xAC00 && $code <= 0xD7A3;
+    my $char = chr($code);
+    my $split = NFD($char);
+    next if $split eq $char || NFC($char) ne $char;
+    push @decompositions, sprintf("(0x%X, \"%s\")", $code, join("", map { sprintf("\\u{%X}", ord) } split(//, $split)));
+}
+push @sections, comment("every char NFC leaves alone but NFD splits up, with what NFD splits it into. Hangul syllables,",
+    "which decompose by arithmetic rather than by table, are left out")
+    . "    pub(crate) const CANONICAL_DECOMPOSITION: &[(u32, &str)] = &[\n" . pack_items(@decompositions) . "    ];\n";
+
+# chars sharing a simple case folding make an orbit, and each gets the rest of its orbit
+my %orbits;
+my $folds = all_casefolds();
+for my $code (keys %$folds) {
+    my $simple = $folds->{$code}{simple};
+    next unless length $simple;
+    push @{$orbits{hex $simple}}, $code;
+}
+my @folds;
+for my $folded (keys %orbits) {
+    my @orbit = sort { $a <=> $b } ($folded, @{$orbits{$folded}});
+    for my $code (@orbit) {
+        push @folds, [$code, [grep { $_ != $code } @orbit]];
+    }
+}
+@folds = map { sprintf("(0x%X, &[%s])", $_->[0], join(", ", map { sprintf("0x%X", $_) } @{$_->[1]})) } sort { $a->[0] <=> $b->[0] } @folds;
+push @sections, comment("simple case folding closed into orbits: every char with another case, with every char it",
+    "shares a folding with. Mappings to more than one char, like ß to ss, are left out.")
+    . "    pub(crate) const CASE_FOLDING: &[(u32, &[u32])] = &[\n" . pack_items(@folds) . "    ];\n";
+
+my @categories;
+for my $value (prop_values("gc")) {
+    my ($short, $long) = prop_value_aliases("gc", $value);
+    next unless length($short) == 2 && $short ne "LC";
+    push @categories, [$long, $short, [ranges(prop_invlist("gc=$short"))]];
+}
+@categories = sort { $a->[1] cmp $b->[1] } @categories;
+push @sections, comment("a general category's long name, its short one, and its chars")
+    . "    pub(crate) type Category = (&'static str, &'static str, &'static [(u32, u32)]);\n";
+push @sections, comment("every general category, long name then short, with its chars. The groups, like L for every",
+    "letter and LC for the cased ones, are put together from these")
+    . "    pub(crate) const GENERAL_CATEGORIES: &[Category] = &[\n" . named_tables(@categories) . "    ];\n";
+
+my %scripts;
+for my $value (prop_values("sc")) {
+    my ($short, $long) = prop_value_aliases("sc", $value);
+    $scripts{$long} = $short if defined $long;
+}
+sub scripts {
+    my ($property) = @_;
+    my @entries;
+    for my $long (sort keys %scripts) {
+        my @ranges = ranges(prop_invlist("$property=$scripts{$long}"));
+        push @entries, [$long, $scripts{$long}, \@ranges] if @ranges;
+    }
+    return named_tables(@entries);
+}
+push @sections, comment("a script's long name, its short one, and its chars")
+    . "    pub(crate) type Script = (&'static str, &'static str, &'static [(u32, u32)]);\n";
+push @sections, comment("every script, long name then short, with the chars whose Script property it is")
+    . "    pub(crate) const SCRIPTS: &[Script] = &[\n" . scripts("sc") . "    ];\n";
+push @sections, comment("every script with the chars used in it, counting those shared with other scripts: Script",
+    "gives a char one script, Script_Extensions every one it's used in")
+    . "    pub(crate) const SCRIPT_EXTENSIONS: &[Script] = &[\n" . scripts("scx") . "    ];\n";
+
+my @blocks;
+for my $value (prop_values("blk")) {
+    my ($name) = sort { length($b) <=> length($a) } prop_value_aliases("blk", $value);
+    next if $name eq "No_Block";
+    my @list = prop_invlist("blk=$value");
+    push @blocks, [$list[0], $list[1] - 1, $name];
+}
+@blocks = map { sprintf("(\"%s\", 0x%X, 0x%X)", $_->[2], $_->[0], $_->[1]) } sort { $a->[0] <=> $b->[0] } @blocks;
+push @sections, comment("every block, sorted by where it starts, with its first and last codepoint")
+    . "    pub(crate) const BLOCKS: &[(&str, u32, u32)] = &[\n" . pack_items(@blocks) . "    ];\n";
+
+# names spelled out one by one, and the runs named by a prefix and their number
+my ($starts, $names) = prop_invmap("na");
+my (@named, @numbered, @spelled);
+for my $i (0..$#$starts - 1) {
+    my ($start, $end, $name) = ($starts->[$i], $starts->[$i + 1] - 1, $names->[$i]);
+    next if $name eq "" || $name eq "<hangul syllable>";
+    if ($name =~ /^(.*)-<code point>$/) {
+        # the Tangut supplement is only called that in the ranges' labels, and named like the rest
+        (my $prefix = $1) =~ s/^TANGUT IDEOGRAPH SUPPLEMENT$/TANGUT IDEOGRAPH/;
+        push @numbered, sprintf("(\"%s\", 0x%X, 0x%X)", $prefix, $start, $end);
+        next;
+    }
+    die sprintf("%X has more than one name\n", $start) unless $start == $end;
+    if (@named && $named[-1][1] == $start - 1) {
+        $named[-1][1] = $start;
+    } else {
+        push @named, [$start, $start];
+    }
+    push @spelled, $name;
+}
+push @sections, comment("the codepoints NAMES names, in order, as inclusive ranges")
+    . "    pub(crate) const NAMED: &[(u32, u32)] = &[\n" . pack_items(ranges(map { ($_->[0], $_->[1] + 1) } @named)) . "    ];\n";
+push @sections, comment("codepoints named by a prefix and their own number in hex, like CJK UNIFIED IDEOGRAPH-4E00")
+    . "    pub(crate) const NUMBERED: &[(&str, u32, u32)] = &[\n" . pack_items(@numbered) . "    ];\n";
+push @sections, comment("the name of every codepoint in NAMED, one to a line. Hangul syllables, whose names are put",
+    "together from their jamo, and the codepoints in NUMBERED are left out")
+    . "    pub(crate) const NAMES: &str = \"\\\n" . join("\n", @spelled) . "\";\n";
+
+push @sections, <<'END';
+    // the parts a Hangul syllable's name is put together from: its leading consonant, its vowel
+    // and its trailing consonant, if any
+    pub(crate) const HANGUL_LEADING: &[&str] = &["G", "GG", "N", "D", "DD", "R", "M", "B", "BB", "S", "SS", "", "J", "JJ", "C", "K", "T", "P", "H"];
+    pub(crate) const HANGUL_VOWEL: &[&str] = &[
+        "A", "AE", "YA", "YAE", "EO", "E", "YEO", "YE", "O", "WA", "WAE", "OE", "YO", "U", "WEO", "WE", "WI", "YU", "EU", "YI", "I"
+    ];
+    pub(crate) const HANGUL_TRAILING: &[&str] = &[
+        "", "G", "GG", "GS", "N", "NJ", "NH", "D", "L", "LG", "LM", "LB", "LS", "LT", "LP", "LH", "M", "B", "BS", "S", "SS", "NG", "J",
+        "C", "K", "T", "P", "H"
+    ];
+END
+
+# the Word_Break values and what the enum calls them
+my @word_breaks = (["CR", "CR"], ["LF", "LF"], ["Newline", "Newline"], ["Extend", "Extend"], ["ZWJ", "Zwj"],
+    ["Regional_Indicator", "RegionalIndicator"], ["Format", "Format"], ["Katakana", "Katakana"], ["Hebrew_Letter", "HebrewLetter"],
+    ["ALetter", "ALetter"], ["Single_Quote", "SingleQuote"], ["Double_Quote", "DoubleQuote"], ["MidNumLet", "MidNumLet"],
+    ["MidLetter", "MidLetter"], ["MidNum", "MidNum"], ["Numeric", "Numeric"], ["ExtendNumLet", "ExtendNumLet"], ["WSegSpace", "WSegSpace"]);
+my @breaks;
+for (@word_breaks) {
+    my ($value, $variant) = @$_;
+    my @list = prop_invlist("Word_Break=$value");
+    for (my $i = 0; $i < @list; $i += 2) {
+        push @breaks, [$list[$i], $list[$i + 1] - 1, $variant];
+    }
+}
+@breaks = map { sprintf("(0x%X, 0x%X, WordBreak::%s)", @$_) } sort { $a->[0] <=> $b->[0] } @breaks;
+push @sections, <<'END'
+    // the Word_Break values UAX #29 segments words by, but for Other, which is everything else
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) enum WordBreak {
+        CR, LF, Newline, Extend, Zwj, RegionalIndicator, Format, Katakana, HebrewLetter, ALetter, SingleQuote, DoubleQuote,
+        MidNumLet, MidLetter, MidNum, Numeric, ExtendNumLet, WSegSpace
+    }
+END
+    . "\n" . comment("every char whose Word_Break isn't Other, with what it is")
+    . "    pub(crate) const WORD_BREAK: &[(u32, u32, WordBreak)] = &[\n" . pack_items(@breaks) . "    ];\n";
+
+push @sections, table("EXTENDED_PICTOGRAPHIC", [prop_invlist("Extended_Pictographic")],
+    "the Extended_Pictographic chars, which a ZWJ joins onto without a word break");
+
+print "pub mod unicode {\n";
+print "    // generated by scripts/unicode.pl from the Unicode 14.0 character database, which every class\n";
+print "    // goes by rather than std's char predicates, whose version moves with the toolchain; inclusive\n";
+print "    // codepoint ranges, sorted\n";
+print map { "\n$_" } @sections;
+print "}\n";
@@ -0,0 +1,461 @@
+pub mod ast {
+    #[cfg(feature = "unicode")]
+    use crate::normalize::normalize::{decomposition, nfc};
+    use crate::parser::parser::{CharClass, CharCost, Look};
+    #[cfg(feature = "unicode")]
+    use crate::unicode::unicode::CANONICAL_DECOMPOSITION;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Qualifier {
+        ZeroOrOne,
+        ZeroOrMore,
+        OneOrMore,
+        // {n,m} at least n times and at most m, with no most for {n,}
+        Counted(u32, Option<u32>)
+    }
+
+    impl Qualifier {
+        // the fewest times it lets what it's on match
+        pub fn min(&self) -> u32 {
+            match self {
+                Qualifier::ZeroOrOne | Qualifier::ZeroOrMore => 0,
+                Qualifier::OneOrMore => 1,
+                Qualifier::Counted(min, _) => *min
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Ast {
+        Empty,
+        Cost(CharCost),
+        Concat(Vec<Ast>),
+        Alternation(Vec<Ast>),
+        // capturing, with the name it was given if any
+        Group(Box<Ast>, Option<String>),
+        Repeat(Box<Ast>, Qualifier)
+    }
+
+    // what's known about a capturing group before anything is matched
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct GroupInfo {
+        pub name: Option<String>,
+        // inside a branch of an alternation, so a match may go another way round it
+        pub in_alternation: bool,
+        // inside a ?, * or +, so it may match more than once, keeping the last, or not at all
+        pub in_repetition: bool
+    }
+
+    fn sequence(ast: Ast) -> Vec<Ast> {
+        match ast {
+            Ast::Empty => vec![],
+            Ast::Concat(items) => items,
+            ast => vec![ast]
+        }
+    }
+
+    fn from_sequence(mut items: Vec<Ast>) -> Ast {
+        match items.len() {
+            0 => Ast::Empty,
+            1 => items.pop().unwrap(),
+            _ => Ast::Concat(items)
+        }
+    }
+
+    // text as it's written in NFC, with each char that NFD would split up also allowed in split form
+    #[cfg(feature = "unicode")]
+    fn either_form(text: &str) -> Vec<Ast> {
+        nfc(text).chars().map(|letter| match decomposition(letter) {
+            Some(decomposed) => {
+                let split = decomposed.chars().map(|a| Ast::Cost(CharCost::Singleton(a))).collect();
+                Ast::Alternation(vec![Ast::Cost(CharCost::Singleton(letter)), Ast::Concat(split)])
+            }
+            None => Ast::Cost(CharCost::Singleton(letter))
+        }).collect()
+    }
+
+    // foobar|foobaz|fooqux becomes foo(ba(r|z)|qux). Only neighbouring branches are merged, so the
+    // order branches are tried in never changes.
+    fn factor_prefixes(branches: Vec<Ast>) -> Vec<Ast> {
+        let mut runs: Vec<Vec<Vec<Ast>>> = vec![];
+        for branch in branches.into_iter().map(sequence) {
+            match runs.last_mut() {
+                Some(run) if !branch.is_empty() && run[0].first() == branch.first() => run.push(branch),
+                _ => runs.push(vec![branch])
+            }
+        }
+
+        let mut factored = vec![];
+        for mut run in runs {
+            if run.len() == 1 {
+                factored.push(from_sequence(run.pop().unwrap()));
+                continue;
+            }
+            let shortest = run.iter().map(|a| a.len()).min().unwrap();
+            let common = (0..shortest).take_while(|i| run.iter().all(|a| a[*i] == run[0][*i])).count();
+            let mut prefix: Vec<Ast> = run[0][..common].to_vec();
+            let rests = run.into_iter().map(|a| from_sequence(a[common..].to_vec())).collect();
+            match Ast::Alternation(rests).simplify() {
+                Ast::Empty => {}
+                rest @ Ast::Alternation(_) => prefix.push(Ast::Group(Box::new(rest), None)),
+                rest => prefix.extend(sequence(rest))
+            }
+            factored.push(from_sequence(prefix));
+        }
+        factored
+    }
+
+    impl Ast {
+        // rewrites into an equivalent tree that builds a smaller graph
+        pub fn simplify(self) -> Ast {
+            match self {
+                Ast::Empty => Ast::Empty,
+                Ast::Cost(CharCost::Class(class)) => match class.singleton() {
+                    Some(only) => Ast::Cost(CharCost::Singleton(only)),
+                    None => Ast::Cost(CharCost::Class(class))
+                },
+                Ast::Cost(cost) => Ast::Cost(cost),
+                Ast::Group(inner, name) => match inner.simplify() {
+                    atom @ (Ast::Empty | Ast::Cost(_) | Ast::Group(..)) => atom,
+                    inner => Ast::Group(Box::new(inner), name)
+                },
+                Ast::Concat(items) => {
+                    let mut flat = vec![];
+                    for item in items {
+                        match item.simplify() {
+                            Ast::Empty => {}
+                            Ast::Concat(inner) => flat.extend(inner),
+                            Ast::Group(inner, _) if matches!(*inner, Ast::Concat(_)) => {
+                                if let Ast::Concat(inner) = *inner {
+                                    flat.extend(inner);
+                                }
+                            }
+                            item => flat.push(item)
+                        }
+                    }
+                    match flat.len() {
+                        0 => Ast::Empty,
+                        1 => flat.pop().unwrap(),
+                        _ => Ast::Concat(flat)
+                    }
+                }
+                Ast::Alternation(branches) => {
+                    let mut flat = vec![];
+                    for branch in branches {
+                        let branch = match branch.simplify() {
+                            Ast::Group(inner, _) if matches!(*inner, Ast::Alternation(_)) => *inner,
+                            branch => branch
+                        };
+                        let inner = match branch {
+                            Ast::Alternation(inner) => inner,
+                            branch => vec![branch]
+                        };
+                        for branch in inner {
+                            if !flat.contains(&branch) {
+                                flat.push(branch);
+                            }
+                        }
+                    }
+                    let mut flat = factor_prefixes(flat);
+                    if flat.len() == 1 {
+                        return flat.pop().unwrap();
+                    }
+                    Ast::Alternation(flat)
+                }
+                // the counts that say no more than one of the other qualifiers do
+                Ast::Repeat(inner, qualifier) => match (qualifier, inner.simplify()) {
+                    (Qualifier::Counted(_, Some(0)), _) => Ast::Empty,
+                    (Qualifier::Counted(1, Some(1)), inner) => inner,
+                    (Qualifier::Counted(0, Some(1)), inner) => Ast::Repeat(Box::new(inner), Qualifier::ZeroOrOne),
+                    (Qualifier::Counted(0, None), inner) => Ast::Repeat(Box::new(inner), Qualifier::ZeroOrMore),
+                    (Qualifier::Counted(1, None), inner) => Ast::Repeat(Box::new(inner), Qualifier::OneOrMore),
+                    (qualifier, inner) => Ast::Repeat(Box::new(inner), qualifier)
+                }
+            }
+        }
+
+        // every capturing group in open paren order, which is how they're numbered from 1 on
+        pub fn groups(&self) -> Vec<GroupInfo> {
+            let mut groups = vec![];
+            self.collect_groups(false, false, &mut groups);
+            groups
+        }
+
+        fn collect_groups(&self, in_alternation: bool, in_repetition: bool, groups: &mut Vec<GroupInfo>) {
+            match self {
+                Ast::Empty | Ast::Cost(_) => {}
+                Ast::Concat(items) => {
+                    for item in items {
+                        item.collect_groups(in_alternation, in_repetition, groups);
+                    }
+                }
+                Ast::Alternation(branches) => {
+                    for branch in branches {
+                        branch.collect_groups(true, in_repetition, groups);
+                    }
+                }
+                Ast::Group(inner, name) => {
+                    groups.push(GroupInfo { name: name.clone(), in_alternation, in_repetition });
+                    inner.collect_groups(in_alternation, in_repetition, groups);
+                }
+                Ast::Repeat(inner, _) => inner.collect_groups(in_alternation, true, groups)
+            }
+        }
+
+        // how many groups take part in every match, if that's always the same. A group under a ?
+        // or * may be skipped, and one in a repeated alternation may be set on one pass and not
+        // another, so those only count when every branch sets all of its groups.
+        pub fn static_captures_len(&self) -> Option<usize> {
+            match self {
+                Ast::Empty | Ast::Cost(_) => Some(0),
+                Ast::Concat(items) => items.iter().map(Ast::static_captures_len).sum(),
+                Ast::Alternation(branches) => {
+                    let first = branches.first()?.static_captures_len()?;
+                    branches.iter().all(|a| a.static_captures_len() == Some(first)).then_some(first)
+                }
+                Ast::Group(inner, _) => Some(inner.static_captures_len()? + 1),
+                Ast::Repeat(inner, qualifier) if qualifier.min() > 0 => {
+                    let all = inner.groups().len();
+                    (inner.static_captures_len() == Some(all)).then_some(all)
+                }
+                Ast::Repeat(inner, _) => inner.groups().is_empty().then_some(0)
+            }
+        }
+
+        // the one string this matches, if that's all it ever matches
+        pub fn literal(&self) -> Option<String> {
+            match self {
+                Ast::Empty => Some(String::new()),
+                Ast::Cost(CharCost::Singleton(a)) => Some(a.to_string()),
+                Ast::Cost(CharCost::Literal(a)) => Some(a.clone()),
+                Ast::Concat(items) => items.iter().map(Ast::literal).collect(),
+                Ast::Group(inner, _) => inner.literal(),
+                _ => None
+            }
+        }
+
+        // text every match ends with, as much of it as can be told from the tree
+        pub fn required_suffix(&self) -> Option<String> {
+            let suffix = match self {
+                Ast::Concat(items) => {
+                    let mut suffix = String::new();
+                    for item in items.iter().rev() {
+                        if let Some(literal) = item.literal() {
+                            suffix.insert_str(0, &literal);
+                            continue;
+                        }
+                        suffix.insert_str(0, &item.required_suffix().unwrap_or_default());
+                        break;
+                    }
+                    suffix
+                }
+                Ast::Alternation(branches) => {
+                    let suffixes = branches.iter().map(Ast::required_suffix).collect::<Option<Vec<String>>>()?;
+                    let (first, rest) = suffixes.split_first()?;
+                    let common = rest.iter().fold(first.as_str(), |common, a| {
+                        let length: usize = common.chars().rev().zip(a.chars().rev()).take_while(|(x, y)| x == y).map(|(x, _)| x.len_utf8()).sum();
+                        &common[common.len() - length..]
+                    });
+                    common.to_string()
+                }
+                Ast::Group(inner, _) => inner.required_suffix()?,
+                Ast::Repeat(inner, qualifier) if qualifier.min() > 0 => inner.required_suffix()?,
+                ast => ast.literal()?
+            };
+            (!suffix.is_empty()).then_some(suffix)
+        }
+
+        // a literal every match has in it, with the part of the tree that ends with it, which is
+        // all of it when the literal is a suffix. Otherwise it's the longest run of plain text
+        // between the other items.
+        pub fn required_factor(&self) -> Option<(Ast, String)> {
+            if let Some(suffix) = self.required_suffix() {
+                return Some((self.clone(), suffix));
+            }
+            let Ast::Concat(items) = self else { return None };
+            let mut best: Option<(usize, String)> = None;
+            let mut run = String::new();
+            for (index, item) in items.iter().enumerate() {
+                let Some(literal) = item.literal() else {
+                    run.clear();
+                    continue;
+                };
+                run.push_str(&literal);
+                if best.as_ref().is_none_or(|(_, a)| run.len() > a.len()) {
+                    best = Some((index + 1, run.clone()));
+                }
+            }
+            let (end, literal) = best.filter(|(_, a)| !a.is_empty())?;
+            Some((from_sequence(items[..end].to_vec()), literal))
+        }
+
+        // the same tree with every cost swapped for whatever f makes of it
+        pub fn map_costs(self, f: &mut impl FnMut(CharCost) -> Ast) -> Ast {
+            match self {
+                Ast::Empty => Ast::Empty,
+                Ast::Cost(cost) => f(cost),
+                Ast::Concat(items) => Ast::Concat(items.into_iter().map(|a| a.map_costs(f)).collect()),
+                Ast::Alternation(branches) => Ast::Alternation(branches.into_iter().map(|a| a.map_costs(f)).collect()),
+                Ast::Group(inner, name) => Ast::Group(Box::new(inner.map_costs(f)), name),
+                Ast::Repeat(inner, qualifier) => Ast::Repeat(Box::new(inner.map_costs(f)), qualifier)
+            }
+        }
+
+        // the same tree with every char and class widened to all its cases
+        pub fn case_insensitive(self) -> Ast {
+            self.fold_case(CharCost::case_folded)
+        }
+
+        // the same, but only ASCII letters get their other case, which makes for less to build
+        // when the haystack is known to be ASCII anyway
+        pub fn ascii_case_insensitive(self) -> Ast {
+            self.fold_case(CharCost::ascii_case_folded)
+        }
+
+        fn fold_case(self, fold: fn(CharCost) -> CharCost) -> Ast {
+            self.map_costs(&mut |cost| match cost {
+                CharCost::Literal(literal) => Ast::Concat(literal.chars().map(|a| Ast::Cost(fold(CharCost::Singleton(a)))).collect()),
+                cost => Ast::Cost(fold(cost))
+            })
+        }
+
+        // the same tree matching text whether it's in NFC or NFD. Runs of plain chars are composed
+        // first, so a pattern can be written either way too. A class only picks up the split forms
+        // of its members when it isn't negated.
+        #[cfg(feature = "unicode")]
+        pub fn normalization_insensitive(self) -> Ast {
+            match self {
+                Ast::Cost(CharCost::Singleton(letter)) => from_sequence(either_form(&letter.to_string())),
+                Ast::Cost(CharCost::Literal(literal)) => from_sequence(either_form(&literal)),
+                Ast::Cost(CharCost::Class(class)) if !class.is_negated() => {
+                    let cost = CharCost::Class(class);
+                    let mut branches = vec![];
+                    for (letter, decomposed) in CANONICAL_DECOMPOSITION.iter() {
+                        if char::from_u32(*letter).is_some_and(|a| cost.matches(a)) {
+                            branches.push(Ast::Concat(decomposed.chars().map(|a| Ast::Cost(CharCost::Singleton(a))).collect()));
+                        }
+                    }
+                    if branches.is_empty() {
+                        return Ast::Cost(cost);
+                    }
+                    branches.insert(0, Ast::Cost(cost));
+                    Ast::Alternation(branches)
+                }
+                Ast::Concat(items) => {
+                    let mut normalized = vec![];
+                    let mut run = String::new();
+                    for item in items {
+                        match item {
+                            Ast::Cost(CharCost::Singleton(letter)) => run.push(letter),
+                            Ast::Cost(CharCost::Literal(literal)) => run.push_str(&literal),
+                            item => {
+                                normalized.extend(either_form(&std::mem::take(&mut run)));
+                                normalized.push(item.normalization_insensitive());
+                            }
+                        }
+                    }
+                    normalized.extend(either_form(&run));
+                    from_sequence(normalized)
+                }
+                Ast::Alternation(branches) => Ast::Alternation(branches.into_iter().map(Ast::normalization_insensitive).collect()),
+                Ast::Group(inner, name) => Ast::Group(Box::new(inner.normalization_insensitive()), name),
+                Ast::Repeat(inner, qualifier) => Ast::Repeat(Box::new(inner.normalization_insensitive()), qualifier),
+                ast => ast
+            }
+        }
+
+        // the same tree with ^ and $ matching at the ends of lines as well
+        pub fn multi_line(self, crlf: bool) -> Ast {
+            self.map_costs(&mut |cost| match cost {
+                CharCost::Look(look) => Ast::Cost(CharCost::Look(look.multi_line(crlf))),
+                cost => Ast::Cost(cost)
+            })
+        }
+
+        // the same tree with lines ending at terminator rather than \n, for . and the line anchors
+        // multi_line put in
+        pub fn line_terminator(self, terminator: u8) -> Ast {
+            self.map_costs(&mut |cost| match cost {
+                CharCost::Dot => {
+                    let mut class = CharClass::new();
+                    class.plus_literal(terminator as char);
+                    Ast::Cost(CharCost::Class(class.complement()))
+                }
+                CharCost::Look(Look::StartLine(_)) => Ast::Cost(CharCost::Look(Look::StartLine(terminator))),
+                CharCost::Look(Look::EndLine(_)) => Ast::Cost(CharCost::Look(Look::EndLine(terminator))),
+                cost => Ast::Cost(cost)
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use crate::parser::parser::{parse_ast, parser};
+
+        #[test]
+        fn test_simplify() {
+            let simplified = parse_ast("(a|a)(b(cd))((e))").unwrap().simplify();
+            let goal = parse_ast("abcde").unwrap();
+            assert_eq!(goal, simplified);
+
+            let simplified = parse_ast("(a|(b|a))|[cc]").unwrap().simplify();
+            let goal = parse_ast("a|b|c").unwrap();
+            assert_eq!(goal, simplified);
+
+            let simplified = parse_ast("[a-cb-ea]").unwrap().simplify();
+            let goal = parse_ast("[a-e]").unwrap().simplify();
+            assert_eq!(goal, simplified);
+        }
+
+        #[test]
+        fn test_factor_prefixes() {
+            let simplified = parse_ast("(foobar|foobaz|fooqux)").unwrap().simplify();
+            let goal = parse_ast("(foo(ba(r|z)|qux))").unwrap().simplify();
+            assert_eq!(goal, simplified);
+
+            // only neighbours are merged
+            let simplified = parse_ast("ab|c|ad").unwrap().simplify();
+            let goal = parse_ast("ab|c|ad").unwrap();
+            assert_eq!(goal, simplified);
+
+            let simplified = parse_ast("ab|a").unwrap().simplify();
+            let goal = parse_ast("a(b|)").unwrap().simplify();
+            assert_eq!(goal, simplified);
+        }
+
+        #[test]
+        fn test_static_captures_len() {
+            let cases = [("a(b)(c|d)", Some(2)), ("(a)|(b)", Some(1)), ("(a)|b", None), ("((a)b)+", Some(2)), ("((a)|(b))+", None), ("(a)?", None), ("a*", Some(0))];
+            for (pattern, expected) in cases {
+                assert_eq!(parse_ast(pattern).unwrap().static_captures_len(), expected, "{}", pattern);
+            }
+        }
+
+        #[test]
+        fn test_required_suffix() {
+            let cases = [
+                ("[0-9]+px", Some("px")), ("a(bc)", Some("abc")), ("x(ab)+", Some("ab")), ("(apx|bx)", Some("x")),
+                ("a(px|em)", None), ("ab*", None), ("a$", None), ("é|aé", Some("é"))
+            ];
+            for (pattern, expected) in cases {
+                assert_eq!(parse_ast(pattern).unwrap().required_suffix().as_deref(), expected, "{}", pattern);
+            }
+        }
+
+        #[test]
+        fn test_required_factor() {
+            let (before, literal) = parse_ast("[a-z]+@(ex)ample\\.[a-z]+").unwrap().required_factor().unwrap();
+            assert_eq!((before, literal.as_str()), (parse_ast("[a-z]+@(ex)ample\\.").unwrap(), "@example."));
+            assert_eq!(parse_ast("[0-9]+px").unwrap().required_factor().map(|a| a.1), Some("px".to_string()));
+            assert_eq!(parse_ast("a*|b").unwrap().required_factor(), None);
+        }
+
+        #[test]
+        fn test_simplify_shrinks_graph() {
+            assert_eq!(parser("(a|a|a)b").unwrap().arena.len(), 3);
+            let factored = parser("foobar|foobaz|fooqux").unwrap().arena.len();
+            let unfactorable = parser("foobar|goobaz|hooqux").unwrap().arena.len();
+            assert!(factored < unfactorable);
+        }
+    }
+}
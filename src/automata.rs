@@ -1,13 +1,799 @@
-mod automata {
-    use crate::graph::graph::{Graph, NodeIndex};
-    use crate::parser::parser::{CharClass, CharCost};
+pub mod automata {
+    use std::collections::{BTreeSet, HashSet};
+    use std::ops::Range;
+    use std::sync::OnceLock;
 
-    struct MatchData {
-        matched_string: String,
-        location: usize
+    use crate::engine::engine::Metrics;
+    use crate::graph::graph::{Graph, NodeIndex, Program};
+    use crate::haystack::haystack::Haystack;
+    use crate::parser::parser::{CharCost, Count};
+    use crate::util::util::{ScratchPool, SparseSet};
+
+    pub struct MatchData {
+        pub matched_string: String,
+        // a byte offset into the haystack, not a char index
+        pub location: usize
+    }
+
+    impl MatchData {
+        pub fn range(&self) -> Range<usize> {
+            self.location..self.location + self.matched_string.len()
+        }
+
+        // the same match counted in chars, for callers that index text by character
+        pub fn char_range(&self, haystack: &str) -> Range<usize> {
+            char_range(haystack, self.range())
+        }
+    }
+
+    // converts a byte range on char boundaries of haystack into char indices
+    pub fn char_range(haystack: &str, bytes: Range<usize>) -> Range<usize> {
+        let start = haystack[..bytes.start].chars().count();
+        start..start + haystack[bytes].chars().count()
+    }
+
+    // leftmost-longest, non-overlapping; location is a byte offset into code
+    pub fn run_automata(automata: Graph<CharCost>, code: String) -> Vec<MatchData> {
+        let automata = automata.freeze();
+        let closures = epsilon_closures(&automata);
+        find_all(&code, EmptyMatches::default(), |position| longest_match_at(&automata, &closures, code.as_str(), position))
+    }
+
+    // what happens to an empty match found right where the previous match ended, like the one
+    // a* finds after the aa in baab
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub enum EmptyMatches {
+        // baab gives "", "aa", "", ""
+        #[default]
+        ReportAll,
+        // baab gives "", "aa", "", as Perl, PCRE and Rust's regex do
+        SkipAdjacent
+    }
+
+    pub(crate) fn find_all(code: &str, empty: EmptyMatches, longest_match_at: impl Fn(usize) -> Option<usize>) -> Vec<MatchData> {
+        match_spans(code, empty, longest_match_at).map(|(start, end)| MatchData {
+            matched_string: code[start..end].to_string(),
+            location: start
+        }).collect()
+    }
+
+    // the search loop shared by every engine, which only has to say where the longest match
+    // starting at a given position ends. Yields (start, end) byte offsets, allocating nothing
+    // itself, whatever longest_match_at might. An empty match always moves the search on by a
+    // char, so the loop ends either way.
+    pub(crate) fn match_spans<'a, H: Haystack + ?Sized>(code: &'a H, empty: EmptyMatches, longest_match_at: impl Fn(usize) -> Option<usize> + 'a) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let mut position = 0;
+        let mut last_end = None;
+        std::iter::from_fn(move || {
+            while position <= code.len() {
+                let start = position;
+                let end = longest_match_at(start);
+                position = match end {
+                    Some(end) if end > start => end,
+                    _ => next_boundary(code, start)
+                };
+                match end {
+                    Some(end) if end == start && last_end == Some(start) && empty == EmptyMatches::SkipAdjacent => {}
+                    Some(end) => {
+                        last_end = Some(end);
+                        return Some((start, end));
+                    }
+                    None => {}
+                }
+            }
+            None
+        })
+    }
+
+    pub(crate) fn next_boundary<H: Haystack + ?Sized>(code: &H, position: usize) -> usize {
+        position + code.char_at(position).map_or(1, |(_, length)| length)
+    }
+
+    pub(crate) fn longest_match_at<T: Reads<I>, I: ?Sized>(automata: &Program<T>, closures: &Closures, code: &I, position: usize) -> Option<usize> {
+        longest_match_counted(automata, closures, code, position, &mut Metrics::default())
+    }
+
+    // the offsets come in increasing order, so the last one is where the longest match ends
+    pub(crate) fn longest_match_counted<T: Reads<I>, I: ?Sized>(automata: &Program<T>, closures: &Closures, code: &I, position: usize, metrics: &mut Metrics) -> Option<usize> {
+        let mut longest = None;
+        simulate(automata, closures, code, position, metrics, |offset, _| {
+            longest = Some(offset);
+            true
+        });
+        longest
+    }
+
+    // the first offset some match from position ends at, the simulation stopping right there
+    pub(crate) fn earliest_match_counted<T: Reads<I>, I: ?Sized>(automata: &Program<T>, closures: &Closures, code: &I, position: usize, metrics: &mut Metrics) -> Option<usize> {
+        let mut earliest = None;
+        simulate(automata, closures, code, position, metrics, |offset, _| {
+            earliest = Some(offset);
+            false
+        });
+        earliest
+    }
+
+    // the end of the longest match from position and the pattern it's for, the lowest numbered
+    // one when several end there
+    pub(crate) fn longest_pattern_match_at(automata: &Program<CharCost>, closures: &Closures, code: &str, position: usize) -> Option<(usize, usize)> {
+        let mut best: Option<(usize, usize)> = None;
+        simulate(automata, closures, code, position, &mut Metrics::default(), |offset, pattern| {
+            if best.is_none_or(|(end, best)| offset > end || pattern < best) {
+                best = Some((offset, pattern));
+            }
+            true
+        });
+        best
     }
 
-    fn run_automata(automata: Graph<CharCost>, code: String) -> Vec<MatchData> {
-        todo!()
-    } 
-}
\ No newline at end of file
+    // calls on_match with the offset and pattern every time a path from position reaches an
+    // accepting node, offsets in increasing order, until it says to stop. States are parked at the
+    // offset they next read from, which lets a literal edge be crossed in one go with a substring
+    // comparison. The sets and the list they're parked in come from the thread's ScratchPool and
+    // go back to it afterwards, so once a search has run, the next one over the same automata
+    // doesn't allocate.
+    // states are numbered by closures' Numbering, which is just the nodes unless there are counted
+    // repeats
+    fn simulate<T: Reads<I>, I: ?Sized>(automata: &Program<T>, closures: &Closures, code: &I, position: usize, metrics: &mut Metrics, mut on_match: impl FnMut(usize, usize) -> bool) {
+        let numbering = closures.numbering(automata);
+        let capacity = numbering.capacity();
+        let mut scratch = ScratchPool::take();
+        let mut pending = scratch.pending();
+        let mut start = scratch.set(capacity);
+        start.insert(numbering.id(automata.start(), 0));
+        pending.push((position, start));
+        let mut states = scratch.set(capacity);
+        'offsets: while let Some((offset, parked)) = pending.pop() {
+            states.clear();
+            for (node, counts) in parked.iter().map(|a| numbering.split(a)) {
+                for state in closures.nodes[node].iter() {
+                    metrics.epsilon_expansions += states.insert(numbering.id(*state, counts)) as usize;
+                }
+            }
+            scratch.give(parked);
+            // looks are epsilons that only exist at some offsets, and counts ones that only exist
+            // for some counts, so they're followed here rather than in the closures
+            let mut index = 0;
+            while index < states.len() {
+                let (node, counts) = numbering.split(states.as_slice()[index]);
+                for (next, cost) in automata.edges(node) {
+                    let Some(cost) = cost else { continue };
+                    let counts = match cost.count() {
+                        Some(count) => count.counts_after(counts),
+                        None => cost.holds(code, offset).filter(|a| *a).map(|_| counts)
+                    };
+                    if let Some(counts) = counts {
+                        for state in closures.nodes[*next].iter() {
+                            metrics.epsilon_expansions += states.insert(numbering.id(*state, counts)) as usize;
+                        }
+                    }
+                }
+                index += 1;
+            }
+            for (node, pattern) in automata.accept() {
+                if states.contains(numbering.id(*node, 0)) && !on_match(offset, *pattern) {
+                    break 'offsets;
+                }
+            }
+            for (node, counts) in states.iter().map(|a| numbering.split(a)) {
+                for (next, cost) in automata.edges(node) {
+                    if let Some(length) = cost.as_ref().and_then(|a| a.reads(code, offset)) {
+                        metrics.transitions += 1;
+                        parked_at(&mut pending, offset + length, &mut scratch, capacity).insert(numbering.id(*next, counts));
+                    }
+                }
+            }
+        }
+        scratch.give(states);
+        scratch.give_pending(pending);
+    }
+
+    // the set parked at offset, added if there isn't one. pending is kept sorted from the furthest
+    // offset down to the nearest, so the next one to read from comes off the end.
+    fn parked_at<'a>(pending: &'a mut Vec<(usize, SparseSet)>, offset: usize, scratch: &mut ScratchPool, capacity: usize) -> &'a mut SparseSet {
+        let index = pending.partition_point(|(a, _)| *a > offset);
+        if pending.get(index).is_none_or(|(a, _)| *a != offset) {
+            pending.insert(index, (offset, scratch.set(capacity)));
+        }
+        &mut pending[index].1
+    }
+
+    // the epsilon closures of every node, and the numbering of states, which is only worked out
+    // the first time a simulation needs it, as that's when which edges count is known
+    #[derive(Debug, Clone)]
+    pub(crate) struct Closures {
+        nodes: Vec<Vec<usize>>,
+        numbering: OnceLock<Numbering>
+    }
+
+    impl Closures {
+        // automata has to be the one the closures are for, and read whatever I is just as the
+        // simulator will
+        pub(crate) fn numbering<T: Reads<I>, I: ?Sized>(&self, automata: &Program<T>) -> &Numbering {
+            self.numbering.get_or_init(|| Numbering::new(automata.node_count(), automata.start(), |node| {
+                automata.edges(node).iter().map(|(next, cost)| (*next, cost.as_ref().and_then(|a| a.count()))).collect()
+            }))
+        }
+    }
+
+    // numbers the states of an automaton with counted repeats in it, a state being a node along
+    // with the counts of the repeats around it. Each node gets a block of ids, one for each way
+    // its counts can stand, so a \d{1,1000} has a thousand states for the one node it counts in
+    // rather than a thousand copies of \d. Without counted repeats a node's id is just the node.
+    #[derive(Debug, Clone, Default)]
+    pub(crate) struct Numbering {
+        starts: Vec<usize>,
+        counted: bool
+    }
+
+    impl Numbering {
+        // edges gives the nodes a node leads to and the count crossed getting to each one. Nodes
+        // nothing reaches from start get a block of one.
+        pub(crate) fn new(node_count: usize, start: usize, edges: impl Fn(usize) -> Vec<(usize, Option<Count>)>) -> Self {
+            let mut sizes = vec![None; node_count];
+            sizes[start] = Some(1);
+            let mut counted = false;
+            let mut stack = vec![start];
+            while let Some(node) = stack.pop() {
+                let size = sizes[node].unwrap_or(1);
+                for (next, count) in edges(node) {
+                    counted |= count.is_some();
+                    if sizes[next].is_none() {
+                        sizes[next] = Some(count.map_or(size, |a| a.size_after(size)));
+                        stack.push(next);
+                    }
+                }
+            }
+            let mut starts = vec![0];
+            for size in sizes {
+                starts.push(starts[starts.len() - 1] + size.unwrap_or(1));
+            }
+            Numbering { starts, counted }
+        }
+
+        pub(crate) fn capacity(&self) -> usize {
+            self.starts.last().copied().unwrap_or(0)
+        }
+
+        pub(crate) fn id(&self, node: usize, counts: usize) -> usize {
+            self.starts[node] + counts
+        }
+
+        // the node and counts an id is for
+        pub(crate) fn split(&self, id: usize) -> (usize, usize) {
+            if !self.counted {
+                return (id, 0);
+            }
+            let node = self.starts.partition_point(|a| *a <= id) - 1;
+            (node, id - self.starts[node])
+        }
+    }
+
+    // the epsilon closure of every node, in order, worked out once up front so the simulator never
+    // has to chase None edges while reading input
+    pub(crate) fn epsilon_closures<T>(automata: &Program<T>) -> Closures {
+        let nodes = (0..automata.node_count()).map(|node| {
+            let mut closure = BTreeSet::from([node]);
+            let mut stack = vec![node];
+            while let Some(node) = stack.pop() {
+                for (next, _) in automata.edges(node).iter().filter(|(_, cost)| cost.is_none()) {
+                    if closure.insert(*next) {
+                        stack.push(*next);
+                    }
+                }
+            }
+            closure.into_iter().collect()
+        }).collect();
+        Closures { nodes, numbering: OnceLock::new() }
+    }
+
+    // whether some text the automaton matches has literal in it anywhere but at its very end, as
+    // x.*ypx does with px. The automaton is walked alongside a KMP matcher for literal, which only
+    // has to know which of literal's chars an edge can read, and whether it can read anything else.
+    pub(crate) fn has_inner_occurrence(automata: &Program<CharCost>, literal: &str) -> bool {
+        let literal: Vec<char> = literal.chars().collect();
+        // the longest proper prefix of literal[..=index] that's also a suffix of it, which is how much
+        // is still matched when the char after that doesn't fit
+        let mut fallback = vec![0; literal.len()];
+        for index in 1..literal.len() {
+            let mut matched = fallback[index - 1];
+            while matched > 0 && literal[index] != literal[matched] {
+                matched = fallback[matched - 1];
+            }
+            fallback[index] = matched + (literal[index] == literal[matched]) as usize;
+        }
+        // None for a char literal hasn't got, which never continues it
+        let advance = |mut matched: usize, letter: Option<char>| {
+            let Some(letter) = letter else { return 0 };
+            if matched == literal.len() {
+                matched = fallback[matched - 1];
+            }
+            while matched > 0 && literal[matched] != letter {
+                matched = fallback[matched - 1];
+            }
+            matched + (literal[matched] == letter) as usize
+        };
+        // a state is a node, how much of literal the text so far ends with, and 0 before literal
+        // has been seen, 1 with it just seen and 2 once anything has come after it
+        let mut seen = HashSet::new();
+        let mut stack = vec![(automata.start(), 0, 0)];
+        while let Some((node, matched, found)) = stack.pop() {
+            if !seen.insert((node, matched, found)) {
+                continue;
+            }
+            if found == 2 && automata.accept().iter().any(|(a, _)| *a == node) {
+                return true;
+            }
+            for (next, cost) in automata.edges(node) {
+                let texts: Vec<Vec<Option<char>>> = match cost {
+                    None | Some(CharCost::Look(_) | CharCost::Count(_)) => vec![vec![]],
+                    Some(CharCost::Literal(a)) => vec![a.chars().map(Some).collect()],
+                    Some(cost) => {
+                        let mut letters: Vec<char> = literal.iter().copied().filter(|a| cost.matches(*a)).collect();
+                        letters.sort_unstable();
+                        letters.dedup();
+                        let size: usize = cost.intervals().iter().map(|(start, end)| (end - start) as usize).sum();
+                        let other = (size > letters.len()).then_some(None);
+                        letters.into_iter().map(Some).chain(other).map(|a| vec![a]).collect()
+                    }
+                };
+                for text in texts {
+                    let (mut matched, mut found) = (matched, found);
+                    for letter in text {
+                        matched = advance(matched, letter);
+                        found = match found {
+                            0 => (matched == literal.len()) as u8,
+                            _ => 2
+                        };
+                    }
+                    stack.push((*next, matched, found));
+                }
+            }
+        }
+        false
+    }
+
+    fn epsilon_closure(automata: &Graph<CharCost>, mut states: BTreeSet<NodeIndex>) -> BTreeSet<NodeIndex> {
+        let mut stack: Vec<NodeIndex> = states.iter().copied().collect();
+        while let Some(node_index) = stack.pop() {
+            if let Some(node) = automata.node(node_index) {
+                for (next, _) in node.edges.iter().filter(|(_, cost)| cost.is_none()) {
+                    if states.insert(*next) {
+                        stack.push(*next);
+                    }
+                }
+            }
+        }
+        states
+    }
+
+    fn step(automata: &Graph<CharCost>, states: &BTreeSet<NodeIndex>, letter: char) -> BTreeSet<NodeIndex> {
+        let mut next_states = BTreeSet::new();
+        for node_index in states {
+            if let Some(node) = automata.node(*node_index) {
+                for (next, cost) in node.edges.iter() {
+                    if cost.as_ref().is_some_and(|a| a.matches(letter)) {
+                        next_states.insert(*next);
+                    }
+                }
+            }
+        }
+        epsilon_closure(automata, next_states)
+    }
+
+    // one representative char for every stretch of codepoints the edges can't tell apart
+    fn alphabet(automata: &Graph<CharCost>) -> Vec<char> {
+        let mut cuts = BTreeSet::from([0, char::MAX as u32 + 1]);
+        for (_, _, cost) in automata.edges() {
+            for (start, end) in cost.iter().flat_map(|a| a.intervals()) {
+                cuts.insert(start);
+                cuts.insert(end);
+            }
+        }
+        let cuts: Vec<u32> = cuts.into_iter().collect();
+        cuts.windows(2).filter_map(|a| (a[0]..a[1]).find_map(char::from_u32)).collect()
+    }
+
+    fn fusable(cost: &Option<CharCost>) -> Option<String> {
+        match cost {
+            Some(CharCost::Singleton(a)) => Some(a.to_string()),
+            Some(CharCost::Literal(a)) => Some(a.clone()),
+            _ => None
+        }
+    }
+
+    // what an edge needs to say for a Program of them to run over a slice of symbols other than
+    // chars, like tokens or events, with any Graph built up from costs that know their symbols
+    pub trait Matches<S> {
+        fn matches(&self, symbol: &S) -> bool;
+
+        // the count an edge keeps instead of reading anything, for a counted repeat
+        fn count(&self) -> Option<Count> {
+            None
+        }
+    }
+
+    // a char edge reads one char of a slice of them like any other symbol
+    impl Matches<char> for CharCost {
+        fn matches(&self, symbol: &char) -> bool {
+            CharCost::matches(self, *symbol)
+        }
+
+        fn count(&self) -> Option<Count> {
+            match self {
+                CharCost::Count(count) => Some(*count),
+                _ => None
+            }
+        }
+    }
+
+    // how an edge gets crossed on whatever the simulator is reading, so text and symbols share it
+    pub(crate) trait Reads<I: ?Sized> {
+        // how far from offset the edge takes a path, or None if it can't be crossed there
+        fn reads(&self, input: &I, offset: usize) -> Option<usize>;
+
+        // for an edge that reads nothing but is only there at some offsets, like a look, whether it
+        // is at offset
+        fn holds(&self, _input: &I, _offset: usize) -> Option<bool> {
+            None
+        }
+
+        // the count kept by an edge for a counted repeat, which the simulator does itself
+        fn count(&self) -> Option<Count>;
+    }
+
+    // offsets into a slice count symbols, one read at a time
+    impl<S, T: Matches<S>> Reads<[S]> for T {
+        fn reads(&self, symbols: &[S], offset: usize) -> Option<usize> {
+            symbols.get(offset).filter(|a| self.matches(a)).map(|_| 1)
+        }
+
+        fn count(&self) -> Option<Count> {
+            Matches::count(self)
+        }
+    }
+
+    // offsets into text count bytes, and a Literal edge is read in one go
+    impl<H: Haystack + ?Sized> Reads<H> for CharCost {
+        fn reads(&self, code: &H, offset: usize) -> Option<usize> {
+            self.consumes(code, offset)
+        }
+
+        fn holds(&self, code: &H, offset: usize) -> Option<bool> {
+            match self {
+                CharCost::Look(look) => Some(look.holds(code, offset)),
+                _ => None
+            }
+        }
+
+        fn count(&self) -> Option<Count> {
+            Matches::count(self)
+        }
+    }
+
+    impl Graph<CharCost> {
+        // runs the optimisation passes over a freshly built graph
+        pub fn compile(self) -> Self {
+            self.fuse_literals().compact()
+        }
+
+        // collapses chains of single chars into one Literal edge, so "hello world" becomes one edge
+        // rather than eleven. The nodes in the middle of a chain are removed, leaving slots for compact.
+        fn fuse_literals(mut self) -> Self {
+            let mut incoming = vec![0; self.arena.len()];
+            for (_, next, _) in self.edges() {
+                incoming[next.index()] += 1;
+            }
+
+            for node_index in self.indices().collect::<Vec<_>>() {
+                let edge_count = self.node(node_index).map_or(0, |a| a.edges.len());
+                for edge_index in 0..edge_count {
+                    loop {
+                        let (middle, cost) = &self.node(node_index).unwrap().edges[edge_index];
+                        let middle = *middle;
+                        let Some(mut literal) = fusable(cost) else { break };
+                        let passable = middle != node_index && middle != self.start && incoming[middle.index()] == 1 && !self.accept.contains(&middle);
+                        let Some(next) = self.node(middle).filter(|_| passable) else { break };
+                        let [(after, next_cost)] = next.edges.as_slice() else { break };
+                        let Some(rest) = fusable(next_cost).filter(|_| *after != middle) else { break };
+                        literal.push_str(&rest);
+                        let after = *after;
+                        self.node_mut(node_index).unwrap().edges[edge_index] = (after, Some(CharCost::Literal(literal)));
+                        self.remove_node(middle);
+                    }
+                }
+            }
+            self
+        }
+
+        // the same automaton with every Literal edge spelled back out one char at a time
+        fn unfused(&self) -> Self {
+            let mut graph = self.clone();
+            for node_index in graph.indices().collect::<Vec<_>>() {
+                let Some(node) = graph.node_mut(node_index) else { continue };
+                let edges = std::mem::take(&mut node.edges);
+                let mut unfused_edges = vec![];
+                for (next, cost) in edges {
+                    let Some(CharCost::Literal(literal)) = cost else {
+                        unfused_edges.push((next, cost));
+                        continue;
+                    };
+                    let letters: Vec<char> = literal.chars().collect();
+                    let mut target = next;
+                    for letter in letters[1..].iter().rev() {
+                        let middle = graph.new_node();
+                        graph.node_mut(middle).unwrap().edges.push((target, Some(CharCost::Singleton(*letter))));
+                        target = middle;
+                    }
+                    unfused_edges.push((target, Some(CharCost::Singleton(letters[0]))));
+                }
+                graph.node_mut(node_index).unwrap().edges = unfused_edges;
+            }
+            graph
+        }
+
+        // true when the whole of text is accepted, not just some substring of it
+        pub fn accepts(&self, text: &str) -> bool {
+            let program = self.clone().freeze();
+            longest_match_at(&program, &epsilon_closures(&program), text, 0) == Some(text.len())
+        }
+
+        // every pattern accepting the whole of text, in increasing order
+        pub fn matching_patterns(&self, text: &str) -> Vec<usize> {
+            let mut patterns = BTreeSet::new();
+            let program = self.clone().freeze();
+            simulate(&program, &epsilon_closures(&program), text, 0, &mut Metrics::default(), |offset, pattern| {
+                if offset == text.len() {
+                    patterns.insert(pattern);
+                }
+                true
+            });
+            patterns.into_iter().collect()
+        }
+
+        // a dead pattern: no string at all reaches an accepting node
+        pub fn matches_nothing(&self) -> bool {
+            let mut seen = HashSet::new();
+            let mut stack = vec![self.start];
+            while let Some(node_index) = stack.pop() {
+                if !seen.insert(node_index) {
+                    continue;
+                }
+                if self.accept.contains(&node_index) {
+                    return false;
+                }
+                if let Some(node) = self.node(node_index) {
+                    for (next, cost) in node.edges.iter() {
+                        // an empty class is an edge nothing can cross, though a look or a count reads nothing at all
+                        if cost.as_ref().is_none_or(|a| a.reads_nothing() || !a.intervals().is_empty()) {
+                            stack.push(*next);
+                        }
+                    }
+                }
+            }
+            true
+        }
+
+        // whether any edge is a look, which only the simulator and the pikevm know what to do with
+        pub fn has_looks(&self) -> bool {
+            self.edges().any(|(_, _, cost)| cost.is_some_and(CharCost::is_look))
+        }
+
+        pub fn has_counters(&self) -> bool {
+            self.edges().any(|(_, _, cost)| cost.is_some_and(CharCost::is_count))
+        }
+
+        // a vacuous pattern: every string, the empty one included, is accepted. Looks count as
+        // edges that can't be crossed, so this can say no when the answer is yes, but never the
+        // other way around.
+        pub fn matches_everything(&self) -> bool {
+            let automata = self.unfused();
+            let alphabet = alphabet(&automata);
+            let start = epsilon_closure(&automata, BTreeSet::from([automata.start]));
+            let mut seen = HashSet::from([start.clone()]);
+            let mut queue = vec![start];
+            while let Some(states) = queue.pop() {
+                if !states.iter().any(|a| automata.accept.contains(a)) {
+                    return false;
+                }
+                for letter in alphabet.iter() {
+                    let next_states = step(&automata, &states, *letter);
+                    if seen.insert(next_states.clone()) {
+                        queue.push(next_states);
+                    }
+                }
+            }
+            true
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{char_range, epsilon_closures, has_inner_occurrence, longest_match_at, run_automata, Matches, ScratchPool};
+        use crate::engine::engine::Nfa;
+        use crate::graph::graph::Graph;
+        use crate::parser::parser::{graph_from_patterns, parser, CharCost};
+
+        #[test]
+        fn test_run_automata() {
+            let found: Vec<(String, usize)> = run_automata(parser("ab*|c").unwrap(), "xabbbcaé".to_string())
+                .into_iter().map(|a| (a.matched_string, a.location)).collect();
+            assert_eq!(found, vec![("abbb".to_string(), 1), ("c".to_string(), 5), ("a".to_string(), 6)]);
+        }
+
+        #[test]
+        fn test_accepts() {
+            let graph = parser("(a|b(c|d))*e?").unwrap();
+            for text in ["", "a", "bce", "abdbca", "e"] {
+                assert!(graph.accepts(text), "{}", text);
+            }
+            for text in ["b", "ad", "ee", "ea"] {
+                assert!(!graph.accepts(text), "{}", text);
+            }
+            assert!(!parser("(b*|c)d").unwrap().accepts("bcd"));
+            assert!(parser("(|a)b").unwrap().accepts("b"));
+        }
+
+        #[test]
+        fn test_matching_patterns() {
+            let graph = graph_from_patterns(&["[a-z]+", "if|else", "[0-9]+", "i."]).unwrap().compile();
+            assert_eq!(graph.matching_patterns("if"), vec![0, 1, 3]);
+            assert_eq!(graph.matching_patterns("else"), vec![0, 1]);
+            assert_eq!(graph.matching_patterns("42"), vec![2]);
+            assert_eq!(graph.matching_patterns("4a"), Vec::<usize>::new());
+            assert!(graph_from_patterns(&[]).unwrap().matches_nothing());
+            assert!(graph_from_patterns(&["a", "("]).is_err());
+        }
+
+        #[test]
+        fn test_symbols() {
+            #[derive(Debug, PartialEq)]
+            enum Token {
+                Number(i64),
+                Comma,
+                End
+            }
+            // what an edge can ask of a token
+            enum Kind {
+                AnyNumber,
+                Exactly(Token)
+            }
+            impl Matches<Token> for Kind {
+                fn matches(&self, symbol: &Token) -> bool {
+                    match self {
+                        Kind::AnyNumber => matches!(symbol, Token::Number(_)),
+                        Kind::Exactly(token) => token == symbol
+                    }
+                }
+            }
+            // a number, then a comma and a number as many times as you like
+            let mut graph = Graph::new();
+            graph.add_cost(Kind::AnyNumber);
+            let start = graph.fresh_start();
+            graph.add_cost(Kind::Exactly(Token::Comma));
+            graph.add_cost(Kind::AnyNumber);
+            graph.zero_or_more(start);
+            graph.mark_accepting();
+            let nfa = Nfa::new(graph);
+            let tokens = [Token::Comma, Token::Number(1), Token::Comma, Token::Number(2), Token::Comma, Token::End];
+            assert_eq!(nfa.find_in(&tokens), Some(1..4));
+            assert!(nfa.accepts_all(&tokens[1..4]) && !nfa.accepts_all(&tokens[1..5]));
+            assert_eq!(nfa.longest_match_in(&tokens, 5), None);
+            // the sets come from the same pool the text searches use
+            let pooled = ScratchPool::take().len();
+            assert_eq!(nfa.find_in(&tokens), Some(1..4));
+            assert_eq!(ScratchPool::take().len(), pooled);
+            // and a char graph runs over a slice of chars as well as over text
+            let nfa = Nfa::new(parser("a(b|c)*d").unwrap().compile());
+            let letters: Vec<char> = "xabcbdé".chars().collect();
+            assert_eq!(nfa.find_in(&letters), Some(1..6));
+        }
+
+        #[test]
+        fn test_simulate_reuses_sets() {
+            let program = parser("a(b|c)*d").unwrap().freeze();
+            let closures = epsilon_closures(&program);
+            assert_eq!(longest_match_at(&program, &closures, "abcbd", 0), Some(5));
+            let pooled = ScratchPool::take().len();
+            assert!(pooled >= 2);
+            assert_eq!(longest_match_at(&program, &closures, "abcbd", 0), Some(5));
+            assert_eq!(ScratchPool::take().len(), pooled);
+        }
+
+        #[test]
+        fn test_epsilon_closures() {
+            let program = parser("a?b*").unwrap().compile().freeze();
+            let closures = epsilon_closures(&program);
+            assert_eq!(closures.nodes.len(), program.node_count());
+            for (node, closure) in closures.nodes.iter().enumerate() {
+                assert!(closure.contains(&node));
+            }
+            // nothing has to be read to get from the start to the end
+            let start = &closures.nodes[program.start()];
+            assert!(program.accept().iter().any(|(a, _)| start.contains(a)));
+        }
+
+        #[test]
+        fn test_has_inner_occurrence() {
+            let inner = |pattern: &str, literal: &str| has_inner_occurrence(&parser(pattern).unwrap().compile().freeze(), literal);
+            assert!(inner("x.*ypx|px", "px") && !inner("[0-9]+px", "px"));
+            assert!(!inner("[a-z]+@example\\.com", "@example.com") && inner("[a-z@.]+@example\\.com", "@example.com"));
+            // [ab]*aab matches aabaab, with aab in it twice, where a*aab only ever has the one on the end
+            assert!(inner("[ab]*aab", "aab") && !inner("a*aab", "aab"));
+        }
+
+        #[test]
+        fn test_compile_fuses_literals() {
+            let graph = parser("hello world").unwrap().compile();
+            let mut goal = Graph::new();
+            goal.add_cost(CharCost::Literal("hello world".to_string()));
+            goal.mark_accepting();
+            assert!(graph.is_isomorphic_to(&goal));
+            assert!(graph.accepts("hello world"));
+            assert!(!graph.accepts("hello"));
+
+            let graph = parser("(hello|help)+ me").unwrap().compile();
+            assert!(graph.arena.len() < parser("(hello|help)+ me").unwrap().arena.len());
+            let found: Vec<usize> = run_automata(graph.clone(), "a helphello me".to_string()).into_iter().map(|a| a.location).collect();
+            assert_eq!(found, vec![2]);
+            assert!(!graph.matches_nothing());
+            assert!(parser("(ab|\n|.)*").unwrap().compile().matches_everything());
+        }
+
+        #[test]
+        fn test_graph_combinators() {
+            let concat = parser("ab*").unwrap().concat(parser("c|d").unwrap());
+            for text in ["ac", "abbc", "ad"] {
+                assert!(concat.accepts(text), "{}", text);
+            }
+            for text in ["a", "c", "abcd"] {
+                assert!(!concat.accepts(text), "{}", text);
+            }
+
+            let union = parser("a*").unwrap().union(parser("bc").unwrap().compile());
+            for text in ["", "aaa", "bc"] {
+                assert!(union.accepts(text), "{}", text);
+            }
+            for text in ["abc", "b", "bca"] {
+                assert!(!union.accepts(text), "{}", text);
+            }
+        }
+
+        #[test]
+        fn test_reverse() {
+            for graph in [parser("ab*(cd|e)").unwrap(), parser("ab*(cd|e)").unwrap().compile()] {
+                let reversed = graph.clone().reverse().map(CharCost::reversed);
+                for text in ["ae", "abbcd", "acd"] {
+                    assert!(graph.accepts(text), "{}", text);
+                    let backwards: String = text.chars().rev().collect();
+                    assert!(reversed.accepts(&backwards), "{}", backwards);
+                    assert!(!reversed.accepts(text), "{}", text);
+                }
+                assert!(graph.reverse().reverse().accepts("abbcd"));
+            }
+        }
+
+        #[test]
+        fn test_matches_nothing() {
+            assert!(parser("a[]b").unwrap().matches_nothing());
+            assert!(!parser("abc").unwrap().matches_nothing());
+            assert!(!parser("([]|a)").unwrap().matches_nothing());
+        }
+
+        #[test]
+        fn test_matches_everything() {
+            assert!(parser("(.|\n)*").unwrap().matches_everything());
+            assert!(!parser("(.|\n)+").unwrap().matches_everything());
+            assert!(!parser("(.)*").unwrap().matches_everything());
+            assert!(!parser("a[]b").unwrap().matches_everything());
+        }
+
+        #[test]
+        fn test_char_range() {
+            let matches = run_automata(parser("b+").unwrap(), "ébbü bb".to_string());
+            assert_eq!(matches.iter().map(|a| a.range()).collect::<Vec<_>>(), vec![2..4, 7..9]);
+            assert_eq!(matches.iter().map(|a| a.char_range("ébbü bb")).collect::<Vec<_>>(), vec![1..3, 5..7]);
+            assert_eq!(char_range("añb", 3..4), 2..3);
+        }
+    }
+}
@@ -0,0 +1,414 @@
+pub mod conformance {
+    use std::collections::BTreeMap;
+    use std::iter::Peekable;
+    use std::ops::Range;
+    use std::str::Chars;
+
+    use crate::parser::parser::Dialect;
+    use crate::regex::regex::{DfaKind, RegexBuilder};
+
+    // the bit of TOML a corpus needs: [[test]] tables of strings, integers, booleans and arrays of
+    // those, with one key = value on each line
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Value {
+        String(String),
+        Integer(i64),
+        Boolean(bool),
+        Array(Vec<Value>)
+    }
+
+    pub fn parse_toml(text: &str) -> Result<Vec<BTreeMap<String, Value>>, String> {
+        let mut tables: Vec<BTreeMap<String, Value>> = vec![];
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line == "[[test]]" {
+                tables.push(BTreeMap::new());
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {}", number + 1, message);
+            let (key, value) = line.split_once('=').ok_or_else(|| error("expected key = value"))?;
+            let mut chars = value.trim().chars().peekable();
+            let value = parse_value(&mut chars).map_err(|a| error(&a))?;
+            skip_space(&mut chars);
+            if chars.next().is_some_and(|a| a != '#') {
+                return Err(error("more after the value"));
+            }
+            let table = tables.last_mut().ok_or_else(|| error("key outside a [[test]]"))?;
+            table.insert(key.trim().to_string(), value);
+        }
+        Ok(tables)
+    }
+
+    fn skip_space(chars: &mut Peekable<Chars>) {
+        while chars.next_if(|a| a.is_whitespace()).is_some() {}
+    }
+
+    fn parse_value(chars: &mut Peekable<Chars>) -> Result<Value, String> {
+        skip_space(chars);
+        match chars.next() {
+            // literal strings take everything as it is, which suits patterns full of backslashes
+            Some('\'') => {
+                let text: String = chars.by_ref().take_while(|a| *a != '\'').collect();
+                Ok(Value::String(text))
+            }
+            Some('"') => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => return Ok(Value::String(text)),
+                        Some('\\') => text.push(match chars.next() {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some('r') => '\r',
+                            Some('u') => {
+                                let hex: String = chars.by_ref().take(4).collect();
+                                u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32).ok_or(format!("bad escape \\u{}", hex))?
+                            }
+                            Some(a @ ('"' | '\\')) => a,
+                            a => return Err(format!("bad escape {:?}", a))
+                        }),
+                        Some(a) => text.push(a),
+                        None => return Err("unclosed string".to_string())
+                    }
+                }
+            }
+            Some('[') => {
+                let mut items = vec![];
+                loop {
+                    skip_space(chars);
+                    if chars.next_if_eq(&']').is_some() {
+                        return Ok(Value::Array(items));
+                    }
+                    items.push(parse_value(chars)?);
+                    skip_space(chars);
+                    match chars.next() {
+                        Some(',') => {}
+                        Some(']') => return Ok(Value::Array(items)),
+                        _ => return Err("unclosed array".to_string())
+                    }
+                }
+            }
+            Some(a) if a.is_ascii_alphanumeric() || a == '-' => {
+                let mut word = a.to_string();
+                while let Some(a) = chars.next_if(|a| a.is_ascii_alphanumeric() || *a == '_') {
+                    word.push(a);
+                }
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    word => word.replace('_', "").parse().map(Value::Integer).map_err(|_| format!("unknown value {}", word))
+                }
+            }
+            a => Err(format!("unexpected {:?}", a))
+        }
+    }
+
+    // one pattern run over one haystack. Whatever is left out isn't checked.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Case {
+        pub name: String,
+        pub pattern: String,
+        pub haystack: String,
+        pub dialect: Dialect,
+        pub case_insensitive: bool,
+        pub multi_line: bool,
+        // the pattern is meant to be rejected
+        pub error: bool,
+        // every match find_all should find, as [start, end] byte offsets
+        pub matches: Option<Vec<Range<usize>>>,
+        // the groups of the first match, with [] for a group that took no part. Groups past the
+        // end of the list aren't checked, and an empty list means there's no match.
+        pub captures: Option<Vec<Option<Range<usize>>>>,
+        // other groups that are as good as captures, like the ones an AT&T categorization allows
+        pub alternatives: Vec<Vec<Option<Range<usize>>>>
+    }
+
+    fn span(value: &Value) -> Result<Option<Range<usize>>, String> {
+        match value {
+            Value::Array(items) if items.is_empty() => Ok(None),
+            Value::Array(items) => match items.as_slice() {
+                [Value::Integer(start), Value::Integer(end)] => Ok(Some(*start as usize..*end as usize)),
+                _ => Err(format!("expected [start, end], found {:?}", items))
+            },
+            a => Err(format!("expected [start, end], found {:?}", a))
+        }
+    }
+
+    fn spans(value: Option<&Value>) -> Result<Option<Vec<Option<Range<usize>>>>, String> {
+        match value {
+            Some(Value::Array(items)) => items.iter().map(span).collect::<Result<_, _>>().map(Some),
+            Some(a) => Err(format!("expected a list of spans, found {:?}", a)),
+            None => Ok(None)
+        }
+    }
+
+    // the cases in a corpus, in the order they're written
+    pub fn load(text: &str) -> Result<Vec<Case>, String> {
+        parse_toml(text)?.into_iter().enumerate().map(|(index, table)| {
+            let string = |key: &str| match table.get(key) {
+                Some(Value::String(a)) => Ok(a.clone()),
+                _ => Err(format!("test {} needs a string {}", index + 1, key))
+            };
+            let flag = |key: &str| matches!(table.get(key), Some(Value::Boolean(true)));
+            let matches = spans(table.get("matches"))?;
+            if matches.as_ref().is_some_and(|a| a.iter().any(|x| x.is_none())) {
+                return Err(format!("test {} has an empty span in matches", index + 1));
+            }
+            Ok(Case {
+                name: string("name").unwrap_or_else(|_| format!("test {}", index + 1)),
+                pattern: string("pattern")?,
+                haystack: string("haystack")?,
+                dialect: Dialect::Default,
+                case_insensitive: flag("case_insensitive"),
+                multi_line: flag("multi_line"),
+                error: flag("error"),
+                matches: matches.map(|a| a.into_iter().flatten().collect()),
+                captures: spans(table.get("captures"))?,
+                alternatives: vec![]
+            })
+        }).collect()
+    }
+
+    // the tab separated format of the AT&T testregex suite: flags, pattern, haystack and result,
+    // where the result is (start,end) for each group, (?,?) for one that took no part, NOMATCH,
+    // or an error name like EPAREN or BADBR for a pattern that shouldn't parse. SAME repeats the
+    // last pattern and NULL is the empty string. Of the flags, B and E pick the POSIX dialect, i
+    // ignores case and $ turns on C escapes in the pattern and haystack; a line with any other
+    // flag is for something this engine doesn't do, and is left out like the comments are. So is
+    // a block from a line flagged { to a }, which goes with its first line's feature. A line
+    // flagged ? starts a categorization, whose alternatives follow flagged |, and the ; lines
+    // naming what each one says about an engine are left out too.
+    pub fn load_att(text: &str) -> Result<Vec<Case>, String> {
+        let mut cases: Vec<Case> = vec![];
+        let mut last_pattern = String::new();
+        let mut in_block = false;
+        // where the cases of the categorization going on start
+        let mut categorization = 0;
+        for (number, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split('\t').filter(|a| !a.is_empty()).collect();
+            match fields.first() {
+                Some(flags) if flags.starts_with('{') => in_block = true,
+                Some(&"}") => in_block = false,
+                _ => {}
+            }
+            let [flags, pattern, haystack, result, ..] = fields[..] else { continue };
+            let (alternative, flags) = match flags.strip_prefix('|') {
+                Some(flags) => (true, flags),
+                None => (false, flags.strip_prefix('?').unwrap_or(flags))
+            };
+            let field = |a: &str| match (a, flags.contains('$')) {
+                ("NULL", _) => String::new(),
+                (a, true) => unescape(a),
+                (a, false) => a.to_string()
+            };
+            let pattern = match pattern {
+                "SAME" => last_pattern.clone(),
+                a => field(a)
+            };
+            last_pattern = pattern.clone();
+            if in_block || !flags.chars().all(|a| "BEi$".contains(a)) || !flags.contains(['B', 'E']) {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {}", number + 1, message);
+            let is_error = result.chars().all(|a| a.is_ascii_uppercase()) && result != "NOMATCH";
+            let captures = match result {
+                "NOMATCH" => vec![],
+                _ if is_error => vec![],
+                a => a.strip_prefix('(').and_then(|x| x.strip_suffix(')')).ok_or_else(|| error("bad result"))?.split(")(").map(|x| match x {
+                    "?,?" => Ok(None),
+                    x => {
+                        let (start, end) = x.split_once(',').ok_or_else(|| error("bad span"))?;
+                        Ok(Some(start.parse().map_err(|_| error("bad span"))?..end.parse().map_err(|_| error("bad span"))?))
+                    }
+                }).collect::<Result<_, String>>()?
+            };
+            let haystack = field(haystack);
+            // one line often runs the same pattern in both dialects
+            let dialects = [('B', Dialect::PosixBasic), ('E', Dialect::PosixExtended)].into_iter().filter(|a| flags.contains(a.0));
+            if alternative {
+                for case in cases[categorization..].iter_mut() {
+                    if case.pattern == pattern && case.haystack == haystack && dialects.clone().any(|a| a.1 == case.dialect) {
+                        case.alternatives.push(captures.clone());
+                    }
+                }
+                continue;
+            }
+            categorization = cases.len();
+            for (letter, dialect) in dialects {
+                cases.push(Case {
+                    name: format!("line {} {}", number + 1, letter),
+                    pattern: pattern.clone(),
+                    haystack: haystack.clone(),
+                    dialect,
+                    case_insensitive: flags.contains('i'),
+                    multi_line: false,
+                    error: is_error,
+                    matches: None,
+                    captures: Some(captures.clone()),
+                    alternatives: vec![]
+                });
+            }
+        }
+        Ok(cases)
+    }
+
+    fn unescape(text: &str) -> String {
+        let mut unescaped = String::new();
+        let mut chars = text.chars();
+        while let Some(letter) = chars.next() {
+            if letter != '\\' {
+                unescaped.push(letter);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => unescaped.push('\n'),
+                Some('t') => unescaped.push('\t'),
+                Some('r') => unescaped.push('\r'),
+                Some('f') => unescaped.push('\u{c}'),
+                Some('x') => {
+                    let hex: String = chars.by_ref().take(2).collect();
+                    unescaped.extend(u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32));
+                }
+                // anything else is the pattern's own escape, so it stays
+                Some(a) => unescaped.extend(['\\', a]),
+                None => unescaped.push('\\')
+            }
+        }
+        unescaped
+    }
+
+    // runs case on every engine and says what each got wrong, so nothing at all means it passed
+    pub fn run(case: &Case) -> Vec<String> {
+        let mut failures = vec![];
+        for kind in [DfaKind::Off, DfaKind::Dense, DfaKind::Sparse, DfaKind::Lazy] {
+            let built = RegexBuilder::new(&case.pattern).dialect(case.dialect).dfa(kind).case_insensitive(case.case_insensitive).multi_line(case.multi_line).build();
+            let regex = match (built, case.error) {
+                (Ok(a), false) => a,
+                (Err(_), true) => return failures,
+                (Ok(_), true) => {
+                    failures.push(format!("{}: {} parses but shouldn't", case.name, case.pattern));
+                    return failures;
+                }
+                (Err(a), false) => {
+                    failures.push(format!("{}: {} doesn't parse: {}", case.name, case.pattern, a));
+                    return failures;
+                }
+            };
+            if let Some(expected) = &case.matches {
+                let found: Vec<Range<usize>> = regex.find_all(&case.haystack).iter().map(|a| a.range()).collect();
+                if &found != expected {
+                    failures.push(format!("{} with {:?}: matches {:?}, expected {:?}", case.name, kind, found, expected));
+                }
+            }
+            if let Some(expected) = &case.captures {
+                let found: Vec<Option<Range<usize>>> = regex.captures(&case.haystack).map_or(vec![], |a| a.iter().take(expected.len().max(1)).map(|x| x.map(|y| y.range())).collect());
+                if &found != expected && !case.alternatives.contains(&found) {
+                    failures.push(format!("{} with {:?}: captures {:?}, expected {:?}", case.name, kind, found, expected));
+                }
+            }
+        }
+        failures
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_load() {
+            let corpus = "# a comment\n[[test]]\nname = \"one\"\npattern = '\\d+'\nhaystack = \"a\\u00e912 3\"\nmatches = [[3, 5], [6, 7]]\ncaptures = [[3, 5], []] # trailing\n";
+            let cases = load(corpus).unwrap();
+            assert_eq!(cases[0].pattern, "\\d+");
+            assert_eq!(cases[0].haystack, "aé12 3");
+            assert_eq!(cases[0].matches, Some(vec![3..5, 6..7]));
+            assert_eq!(cases[0].captures, Some(vec![Some(3..5), None]));
+            assert!(load("pattern = 'a'").unwrap_err().starts_with("line 1"));
+            assert!(load("[[test]]\npattern = [1, 2").is_err());
+        }
+
+        // the AT&T cases asking for what this engine doesn't do, which are expected to fail
+        const ATT_UNSUPPORTED: &[(&str, &[&str])] = &[
+            // backreferences
+            ("nullsubexpr.dat", &["line 57 B", "line 58 B", "line 59 B", "line 60 B", "line 61 B"]),
+            // a repeated group that only ever matched empty is left unset, where POSIX has it match
+            ("basic.dat", &["line 127 E", "line 129 E", "line 134 E", "line 139 E"]),
+            ("nullsubexpr.dat", &["line 4 E", "line 21 E", "line 29 E", "line 41 E", "line 43 E", "line 54 B", "line 63 E"])
+        ];
+
+        #[test]
+        fn test_corpus() {
+            let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata");
+            let mut failures = vec![];
+            let mut count = 0;
+            for entry in std::fs::read_dir(directory).unwrap() {
+                let path = entry.unwrap().path();
+                let loaded = match path.extension().and_then(|a| a.to_str()) {
+                    Some("toml") => load(&std::fs::read_to_string(&path).unwrap()),
+                    Some("dat") => load_att(&std::fs::read_to_string(&path).unwrap()),
+                    _ => continue
+                };
+                let cases = loaded.unwrap_or_else(|a| panic!("{}: {}", path.display(), a));
+                count += cases.len();
+                let name = path.file_name().unwrap().to_string_lossy().to_string();
+                for case in cases.iter() {
+                    let known = ATT_UNSUPPORTED.iter().any(|(file, names)| *file == name && names.contains(&case.name.as_str()));
+                    match (run(case), known) {
+                        (found, false) => failures.extend(found.into_iter().map(|a| format!("{}: {}", name, a))),
+                        (found, true) if found.is_empty() => failures.push(format!("{}: {} passes now", name, case.name)),
+                        _ => {}
+                    }
+                }
+            }
+            assert!(count > 0);
+            assert!(failures.is_empty(), "{}", failures.join("\n"));
+        }
+
+        #[test]
+        fn test_att() {
+            let suite = [
+                "# comments and lines for other engines are skipped",
+                "E\ta|b|c|d|e\t\tabcde\t\t(0,1)",
+                "E\t(a|b|c|d|e)f\t\tabcdef\t\t(4,6)(4,5)",
+                "BE\tabc\t\txabcy\t\t(1,4)",
+                "E\tSAME\t\tabd\t\tNOMATCH",
+                "E\tab+bc\t\tabbbbc\t\t(0,6)",
+                "E\ta[^bc]d\t\taed\t\t(0,3)",
+                "B\t\\(a\\)b\t\tab\t\t(0,2)(0,1)",
+                "E\t(a+|b)*\t\tab\t\t(0,2)(1,2)",
+                "E\t(a)|(b)\t\tb\t\t(0,1)(?,?)(0,1)",
+                "E\ta(\t\t-\t\tEPAREN",
+                "E$\ta\\nb\t\ta\\nb\t\t(0,3)",
+                "Ei\tabc\t\tABC\t\t(0,3)",
+                "EL\ta.c\t\ta.c\t\t(0,3)",
+                "E\tNULL\t\tNULL\t\t(0,0)"
+            ].join("\n");
+            let cases = load_att(&suite).unwrap();
+            assert_eq!(cases.len(), 14);
+            assert_eq!(cases[4].pattern, "abc");
+            assert_eq!(cases[11].pattern, "a\nb");
+            let failures: Vec<String> = cases.iter().flat_map(run).collect();
+            assert!(failures.is_empty(), "{}", failures.join("\n"));
+        }
+
+        #[test]
+        fn test_att_categorize_and_blocks() {
+            let suite = [
+                "?E\t(a|ab)(c|bcd)\tabcd\t(0,4)(0,2)(2,4)",
+                ";\t\t\t\tALTERNATION=longest",
+                "|E\t(a|ab)(c|bcd)\tabcd\t(0,4)(0,1)(1,4)",
+                "{E\ta{2}\taa\t(0,2)\tno counted repetition",
+                "E\ta{3}\taaa\t(0,3)",
+                "}",
+                "E\ta{1,}\tNULL\tBADBR"
+            ].join("\n");
+            let cases = load_att(&suite).unwrap();
+            assert_eq!(cases.len(), 2);
+            assert_eq!(cases[0].alternatives, vec![vec![Some(0..4), Some(0..1), Some(1..4)]]);
+            assert!(cases[1].error && cases[1].pattern == "a{1,}");
+            assert!(run(&cases[0]).is_empty());
+        }
+    }
+}
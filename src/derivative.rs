@@ -0,0 +1,207 @@
+pub mod derivative {
+    use crate::ast::ast::{Ast, Qualifier};
+    use crate::parser::parser::CharCost;
+
+    // matches by repeatedly taking the Brzozowski derivative of the expression itself, no graph
+    // involved. Slower than the automata, but simple enough to act as an oracle for them.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Term {
+        Nothing,
+        Epsilon,
+        Cost(CharCost),
+        Concat(Box<Term>, Box<Term>),
+        Alternation(Vec<Term>),
+        Intersection(Vec<Term>),
+        Complement(Box<Term>),
+        Star(Box<Term>)
+    }
+
+    fn dedup(terms: Vec<Term>) -> Vec<Term> {
+        let mut unique = vec![];
+        for term in terms {
+            if !unique.contains(&term) {
+                unique.push(term);
+            }
+        }
+        unique
+    }
+
+    impl Term {
+        pub fn from_ast(ast: &Ast) -> Self {
+            match ast {
+                Ast::Empty => Term::Epsilon,
+                Ast::Cost(cost) => Term::Cost(cost.clone()),
+                Ast::Concat(items) => items.iter().rev().fold(Term::Epsilon, |a, x| Term::from_ast(x).concat(a)),
+                Ast::Alternation(branches) => branches.iter().fold(Term::Nothing, |a, x| a.or(Term::from_ast(x))),
+                Ast::Group(inner, _) => Term::from_ast(inner),
+                Ast::Repeat(inner, qualifier) => {
+                    let inner = Term::from_ast(inner);
+                    match qualifier {
+                        Qualifier::ZeroOrOne => Term::Epsilon.or(inner),
+                        Qualifier::ZeroOrMore => inner.star(),
+                        Qualifier::OneOrMore => inner.clone().concat(inner.star()),
+                        // written out in full, which is fine for an oracle
+                        Qualifier::Counted(min, max) => {
+                            let required = (0..*min).fold(Term::Epsilon, |a, _| a.concat(inner.clone()));
+                            let optional = match max {
+                                Some(max) => (*min..*max).fold(Term::Epsilon, |a, _| Term::Epsilon.or(inner.clone().concat(a))),
+                                None => inner.star()
+                            };
+                            required.concat(optional)
+                        }
+                    }
+                }
+            }
+        }
+
+        // the constructors below simplify as they go, which keeps derivatives from growing without bound
+        pub fn concat(self, other: Term) -> Term {
+            match (self, other) {
+                (Term::Nothing, _) | (_, Term::Nothing) => Term::Nothing,
+                (Term::Epsilon, a) | (a, Term::Epsilon) => a,
+                (a, b) => Term::Concat(Box::new(a), Box::new(b))
+            }
+        }
+
+        pub fn or(self, other: Term) -> Term {
+            let mut terms = vec![];
+            for term in [self, other] {
+                match term {
+                    Term::Nothing => {}
+                    Term::Alternation(inner) => terms.extend(inner),
+                    a => terms.push(a)
+                }
+            }
+            let mut terms = dedup(terms);
+            match terms.len() {
+                0 => Term::Nothing,
+                1 => terms.pop().unwrap(),
+                _ => Term::Alternation(terms)
+            }
+        }
+
+        pub fn and(self, other: Term) -> Term {
+            let mut terms = vec![];
+            for term in [self, other] {
+                match term {
+                    Term::Nothing => return Term::Nothing,
+                    Term::Intersection(inner) => terms.extend(inner),
+                    a => terms.push(a)
+                }
+            }
+            let mut terms = dedup(terms);
+            if terms.len() == 1 {
+                return terms.pop().unwrap();
+            }
+            Term::Intersection(terms)
+        }
+
+        pub fn complement(self) -> Term {
+            match self {
+                Term::Complement(inner) => *inner,
+                a => Term::Complement(Box::new(a))
+            }
+        }
+
+        pub fn star(self) -> Term {
+            match self {
+                Term::Nothing | Term::Epsilon => Term::Epsilon,
+                Term::Star(inner) => Term::Star(inner),
+                a => Term::Star(Box::new(a))
+            }
+        }
+
+        pub fn nullable(&self) -> bool {
+            match self {
+                Term::Nothing | Term::Cost(_) => false,
+                Term::Epsilon | Term::Star(_) => true,
+                Term::Concat(a, b) => a.nullable() && b.nullable(),
+                Term::Alternation(terms) => terms.iter().any(|a| a.nullable()),
+                Term::Intersection(terms) => terms.iter().all(|a| a.nullable()),
+                Term::Complement(inner) => !inner.nullable()
+            }
+        }
+
+        pub fn derive(&self, letter: char) -> Term {
+            match self {
+                Term::Nothing | Term::Epsilon => Term::Nothing,
+                Term::Cost(CharCost::Literal(literal)) => match literal.strip_prefix(letter) {
+                    Some("") => Term::Epsilon,
+                    Some(rest) => Term::Cost(CharCost::Literal(rest.to_string())),
+                    None => Term::Nothing
+                },
+                Term::Cost(cost) => if cost.matches(letter) { Term::Epsilon } else { Term::Nothing },
+                Term::Concat(a, b) => {
+                    let left = a.derive(letter).concat((**b).clone());
+                    if a.nullable() {
+                        left.or(b.derive(letter))
+                    } else {
+                        left
+                    }
+                }
+                Term::Alternation(terms) => terms.iter().fold(Term::Nothing, |a, x| a.or(x.derive(letter))),
+                Term::Intersection(terms) => terms.iter().map(|a| a.derive(letter)).reduce(Term::and).unwrap_or(Term::Nothing),
+                Term::Complement(inner) => inner.derive(letter).complement(),
+                Term::Star(inner) => inner.derive(letter).concat(self.clone())
+            }
+        }
+
+        // full match: the whole of text has to be consumed
+        pub fn is_match(&self, text: &str) -> bool {
+            let mut term = self.clone();
+            for letter in text.chars() {
+                term = term.derive(letter);
+                if term == Term::Nothing {
+                    return false;
+                }
+            }
+            term.nullable()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::parser::parser::{parse_ast, parser};
+
+        fn strings(alphabet: &[char], max_len: usize) -> Vec<String> {
+            let mut all = vec![String::new()];
+            let mut last = vec![String::new()];
+            for _ in 0..max_len {
+                last = last.iter().flat_map(|a| alphabet.iter().map(move |x| format!("{}{}", a, x))).collect();
+                all.extend(last.iter().cloned());
+            }
+            all
+        }
+
+        #[test]
+        fn test_agrees_with_automata() {
+            let patterns = [
+                "a*b", "(a|b)*abb", "(ab|a)(bc|c)?", "a?a?aa", "([ab]c|.)+", "(|a)(b*|c)",
+                "((a|b)*c)*", "(a*b*)*c", "(a|b*)*", "((ab)*|b)+a", "(a+|b?)+c?",
+                "a{2}b", "(a|b){1,3}c", "(a*){2}b", "(ab|a){2,}", "a{0}b|c", "(a{1,2}b){2}", "((a|b){2})*c", "(a?){3,4}"
+            ];
+            for pattern in patterns {
+                let term = Term::from_ast(&parse_ast(pattern).unwrap());
+                let graph = parser(pattern).unwrap();
+                let compiled = graph.clone().compile();
+                for text in strings(&['a', 'b', 'c'], 5) {
+                    assert_eq!(term.is_match(&text), graph.accepts(&text), "{} on {:?}", pattern, text);
+                    assert_eq!(term.is_match(&text), compiled.accepts(&text), "{} compiled on {:?}", pattern, text);
+                }
+            }
+        }
+
+        #[test]
+        fn test_intersection_and_complement() {
+            let any_ab = Term::from_ast(&parse_ast("(a|b)*").unwrap());
+            let only_a = Term::from_ast(&parse_ast("a*").unwrap());
+            let needs_b = any_ab.and(only_a.complement());
+            assert!(needs_b.is_match("aab"));
+            assert!(needs_b.is_match("b"));
+            assert!(!needs_b.is_match("aaa"));
+            assert!(!needs_b.is_match(""));
+            assert!(!needs_b.is_match("abc"));
+        }
+    }
+}
@@ -0,0 +1,673 @@
+pub mod dfa {
+    use std::cell::RefCell;
+    use std::cmp::Ordering;
+    use std::collections::{BTreeSet, HashMap, HashSet};
+    use std::sync::{Mutex, MutexGuard};
+
+    use crate::automata::automata::{find_all, EmptyMatches, MatchData};
+    use crate::engine::engine::{Engine, Metrics, Nfa};
+    use crate::graph::graph::Graph;
+    use crate::haystack::haystack::Haystack;
+    use crate::parser::parser::CharCost;
+
+    // state 0 is always the dead state: once in it nothing can match any more
+    const DEAD: u32 = 0;
+
+    // every inclusive codepoint range split into runs whose UTF-8 encodings differ only in the
+    // byte ranges, each given as the inclusive byte range at every position
+    fn utf8_sequences(start: u32, end: u32) -> Vec<Vec<(u8, u8)>> {
+        let mut sequences = vec![];
+        let mut stack = vec![(start, end)];
+        'outer: while let Some((start, end)) = stack.pop() {
+            // surrogates have no encoding at all
+            if start <= 0xDFFF && end >= 0xD800 {
+                if start < 0xD800 {
+                    stack.push((start, 0xD7FF));
+                }
+                if end > 0xDFFF {
+                    stack.push((0xE000, end));
+                }
+                continue;
+            }
+            // one encoded length at a time
+            for max in [0x7F, 0x7FF, 0xFFFF] {
+                if start <= max && max < end {
+                    stack.push((start, max));
+                    stack.push((max + 1, end));
+                    continue 'outer;
+                }
+            }
+            // and only ranges where every trailing byte spans the whole of its own range
+            for bits in [6, 12, 18] {
+                let mask = (1 << bits) - 1;
+                if start & !mask != end & !mask {
+                    if start & mask != 0 {
+                        stack.push((start, start | mask));
+                        stack.push(((start | mask) + 1, end));
+                        continue 'outer;
+                    }
+                    if end & mask != mask {
+                        stack.push((start, (end & !mask) - 1));
+                        stack.push((end & !mask, end));
+                        continue 'outer;
+                    }
+                }
+            }
+            let (mut low, mut high) = ([0; 4], [0; 4]);
+            let low = char::from_u32(start).unwrap().encode_utf8(&mut low).as_bytes();
+            let high = char::from_u32(end).unwrap().encode_utf8(&mut high).as_bytes();
+            sequences.push(low.iter().zip(high).map(|(a, b)| (*a, *b)).collect());
+        }
+        sequences
+    }
+
+    // every byte a match can start with, or None when a match can be empty. Looks are taken to
+    // hold, so there may be more than a match really starts with but never fewer.
+    pub(crate) fn start_bytes(graph: &Graph<CharCost>) -> Option<Vec<u8>> {
+        let mut bytes = [false; 256];
+        let mut seen = HashSet::new();
+        let mut stack = vec![graph.start];
+        while let Some(node_index) = stack.pop() {
+            if !seen.insert(node_index) {
+                continue;
+            }
+            if graph.accept.contains(&node_index) {
+                return None;
+            }
+            for (next, cost) in graph.node(node_index).iter().flat_map(|a| a.edges.iter()) {
+                match cost {
+                    None | Some(CharCost::Look(_) | CharCost::Count(_)) => stack.push(*next),
+                    Some(cost) => {
+                        let sequences = cost.intervals().into_iter().flat_map(|(start, end)| utf8_sequences(start, end - 1));
+                        for (low, high) in sequences.map(|a| a[0]) {
+                            bytes[low as usize..=high as usize].fill(true);
+                        }
+                    }
+                }
+            }
+        }
+        Some((0..=255).filter(|a| bytes[*a as usize]).collect())
+    }
+
+    // a target and the inclusive byte range leading to it, or None for an epsilon
+    type ByteEdge = (usize, Option<(u8, u8)>);
+
+    // the char graph spelled out one byte at a time, which is what the tables run on
+    pub(crate) struct ByteNfa {
+        pub(crate) edges: Vec<Vec<ByteEdge>>,
+        pub(crate) start: usize,
+        pub(crate) accept: Vec<bool>
+    }
+
+    impl ByteNfa {
+        pub(crate) fn new(graph: &Graph<CharCost>) -> Self {
+            assert!(!graph.has_looks() && !graph.has_counters(), "a DFA can't check looks or keep counts, leave these to the simulator");
+            let mut edges: Vec<Vec<ByteEdge>> = vec![vec![]; graph.arena.len()];
+            for (from, to, cost) in graph.edges() {
+                let sequences = match cost {
+                    None => {
+                        edges[from.index()].push((to.index(), None));
+                        continue;
+                    }
+                    Some(CharCost::Literal(literal)) => vec![literal.bytes().map(|a| (a, a)).collect()],
+                    Some(cost) => cost.intervals().into_iter().flat_map(|(start, end)| utf8_sequences(start, end - 1)).collect()
+                };
+                for sequence in sequences {
+                    let mut at = from.index();
+                    for (position, range) in sequence.iter().enumerate() {
+                        let next = if position + 1 == sequence.len() {
+                            to.index()
+                        } else {
+                            edges.push(vec![]);
+                            edges.len() - 1
+                        };
+                        edges[at].push((next, Some(*range)));
+                        at = next;
+                    }
+                }
+            }
+            let mut accept = vec![false; edges.len()];
+            for node_index in graph.accept.iter() {
+                accept[node_index.index()] = true;
+            }
+            ByteNfa {
+                edges,
+                start: graph.start.index(),
+                accept
+            }
+        }
+
+        pub(crate) fn closure(&self, mut states: BTreeSet<usize>) -> BTreeSet<usize> {
+            let mut stack: Vec<usize> = states.iter().copied().collect();
+            while let Some(state) = stack.pop() {
+                for (next, _) in self.edges[state].iter().filter(|(_, range)| range.is_none()) {
+                    if states.insert(*next) {
+                        stack.push(*next);
+                    }
+                }
+            }
+            states
+        }
+
+        pub(crate) fn step(&self, states: &BTreeSet<usize>, byte: u8) -> BTreeSet<usize> {
+            let mut next_states = BTreeSet::new();
+            for state in states {
+                for (next, range) in self.edges[*state].iter() {
+                    if range.is_some_and(|(low, high)| low <= byte && byte <= high) {
+                        next_states.insert(*next);
+                    }
+                }
+            }
+            self.closure(next_states)
+        }
+
+        // bytes no edge can tell apart share a class, so tables need one column per class, not 256
+        pub(crate) fn byte_classes(&self) -> ([u8; 256], usize) {
+            let mut boundaries = [false; 257];
+            for (_, range) in self.edges.iter().flatten() {
+                if let Some((low, high)) = range {
+                    boundaries[*low as usize] = true;
+                    boundaries[*high as usize + 1] = true;
+                }
+            }
+            let mut classes = [0; 256];
+            let mut class = 0;
+            for byte in 1..256 {
+                if boundaries[byte] {
+                    class += 1;
+                }
+                classes[byte] = class;
+            }
+            (classes, class as usize + 1)
+        }
+    }
+
+    // subset construction, handing each new set of NFA states to on_state as it's numbered, for as
+    // long as on_state says to go on. Returns the number of states, the dead one included, or None
+    // if it was stopped.
+    pub(crate) fn determinize(nfa: &ByteNfa, representatives: &[u8], mut on_state: impl FnMut(u32, &[u32], bool) -> bool) -> Option<usize> {
+        let dead = BTreeSet::new();
+        let start = nfa.closure(BTreeSet::from([nfa.start]));
+        let mut ids = HashMap::from([(dead.clone(), DEAD), (start.clone(), 1)]);
+        let mut queue = vec![dead, start];
+        let mut next_id = 0;
+        while next_id < queue.len() {
+            let states = queue[next_id].clone();
+            let mut row = vec![];
+            for byte in representatives {
+                let next_states = nfa.step(&states, *byte);
+                let id = match ids.get(&next_states) {
+                    Some(id) => *id,
+                    None => {
+                        let id = queue.len() as u32;
+                        ids.insert(next_states.clone(), id);
+                        queue.push(next_states);
+                        id
+                    }
+                };
+                row.push(id);
+            }
+            if !on_state(next_id as u32, &row, states.iter().any(|a| nfa.accept[*a])) {
+                return None;
+            }
+            next_id += 1;
+        }
+        Some(queue.len())
+    }
+
+    fn representatives(classes: &[u8; 256], class_count: usize) -> Vec<u8> {
+        (0..class_count).map(|class| (0..=255u8).find(|a| classes[*a as usize] as usize == class).unwrap()).collect()
+    }
+
+    // a fully determinized automaton as one flat states × byte classes table, for the tightest
+    // possible match loop
+    pub struct DenseDfa {
+        table: Vec<u32>,
+        classes: [u8; 256],
+        class_count: usize,
+        accept: Vec<bool>,
+        start: u32
+    }
+
+    impl DenseDfa {
+        pub fn new(graph: &Graph<CharCost>) -> Self {
+            DenseDfa::with_limit(graph, usize::MAX).unwrap()
+        }
+
+        // None if the table would take more than limit bytes, which for some patterns it can
+        // do by a lot: (a|b)*a(a|b)(a|b)... doubles with every (a|b) on the end
+        pub fn with_limit(graph: &Graph<CharCost>, limit: usize) -> Option<Self> {
+            let nfa = ByteNfa::new(graph);
+            let (classes, class_count) = nfa.byte_classes();
+            let mut table = vec![];
+            let mut accept = vec![];
+            determinize(&nfa, &representatives(&classes, class_count), |_, row, accepting| {
+                table.extend_from_slice(row);
+                accept.push(accepting);
+                table.len() * size_of::<u32>() + classes.len() + accept.len() <= limit
+            })?;
+            table.shrink_to_fit();
+            Some(DenseDfa {
+                table,
+                classes,
+                class_count,
+                accept,
+                start: 1
+            })
+        }
+
+        pub fn state_count(&self) -> usize {
+            self.accept.len()
+        }
+
+        pub fn class_count(&self) -> usize {
+            self.class_count
+        }
+
+        fn next(&self, state: u32, byte: u8) -> u32 {
+            self.table[state as usize * self.class_count + self.classes[byte as usize] as usize]
+        }
+
+        pub fn memory_usage(&self) -> usize {
+            self.table.len() * size_of::<u32>() + self.classes.len() + self.accept.len()
+        }
+
+        pub fn longest_match_at<H: Haystack + ?Sized>(&self, haystack: &H, position: usize) -> Option<usize> {
+            longest_match_at(|a, x| self.next(a, x), self.start, |a| self.accept[a as usize], haystack, position, false, &mut Metrics::default())
+        }
+
+        pub fn accepts(&self, text: &str) -> bool {
+            self.accept[text.bytes().fold(self.start, |a, x| self.next(a, x)) as usize]
+        }
+
+        // leftmost-longest and non-overlapping, just like run_automata
+        pub fn find_all(&self, code: &str) -> Vec<MatchData> {
+            find_all(code, EmptyMatches::default(), |position| self.longest_match_at(code, position))
+        }
+    }
+
+    // the same automaton kept as a sorted list of byte ranges per state, leaving out every range that
+    // leads to the dead state. A step is a binary search rather than one lookup, but when states
+    // only have a few ways out it takes a fraction of the room DenseDfa does.
+    pub struct SparseDfa {
+        transitions: Vec<(u8, u8, u32)>,
+        // state i's ranges are transitions[offsets[i]..offsets[i + 1]]
+        offsets: Vec<usize>,
+        accept: Vec<bool>,
+        start: u32
+    }
+
+    impl SparseDfa {
+        pub fn new(graph: &Graph<CharCost>) -> Self {
+            SparseDfa::with_limit(graph, usize::MAX).unwrap()
+        }
+
+        // None if it would take more than limit bytes
+        pub fn with_limit(graph: &Graph<CharCost>, limit: usize) -> Option<Self> {
+            let nfa = ByteNfa::new(graph);
+            let (classes, class_count) = nfa.byte_classes();
+            let mut transitions = vec![];
+            let mut offsets = vec![0];
+            let mut accept = vec![];
+            determinize(&nfa, &representatives(&classes, class_count), |_, row, accepting| {
+                let mut low = 0;
+                while low < 256 {
+                    let target = row[classes[low] as usize];
+                    let mut high = low;
+                    while high < 255 && row[classes[high + 1] as usize] == target {
+                        high += 1;
+                    }
+                    if target != DEAD {
+                        transitions.push((low as u8, high as u8, target));
+                    }
+                    low = high + 1;
+                }
+                offsets.push(transitions.len());
+                accept.push(accepting);
+                transitions.len() * size_of::<(u8, u8, u32)>() + offsets.len() * size_of::<usize>() + accept.len() <= limit
+            })?;
+            transitions.shrink_to_fit();
+            Some(SparseDfa {
+                transitions,
+                offsets,
+                accept,
+                start: 1
+            })
+        }
+
+        pub fn state_count(&self) -> usize {
+            self.accept.len()
+        }
+
+        fn next(&self, state: u32, byte: u8) -> u32 {
+            let ranges = &self.transitions[self.offsets[state as usize]..self.offsets[state as usize + 1]];
+            let found = ranges.binary_search_by(|(low, high, _)| {
+                if *high < byte {
+                    Ordering::Less
+                } else if *low > byte {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            });
+            found.map_or(DEAD, |a| ranges[a].2)
+        }
+
+        pub fn memory_usage(&self) -> usize {
+            self.transitions.len() * size_of::<(u8, u8, u32)>() + self.offsets.len() * size_of::<usize>() + self.accept.len()
+        }
+
+        pub fn longest_match_at<H: Haystack + ?Sized>(&self, haystack: &H, position: usize) -> Option<usize> {
+            longest_match_at(|a, x| self.next(a, x), self.start, |a| self.accept[a as usize], haystack, position, false, &mut Metrics::default())
+        }
+
+        pub fn accepts(&self, text: &str) -> bool {
+            self.accept[text.bytes().fold(self.start, |a, x| self.next(a, x)) as usize]
+        }
+
+        pub fn find_all(&self, code: &str) -> Vec<MatchData> {
+            find_all(code, EmptyMatches::default(), |position| self.longest_match_at(code, position))
+        }
+    }
+
+    // the DFA built as searches need it rather than up front, one transition at a time. What's
+    // been built stays with it, so later searches only pay for states no earlier one reached.
+    // Once the states fill up the room it's allowed, a search that needs another one simulates
+    // the char graph instead.
+    pub struct LazyDfa {
+        nfa: ByteNfa,
+        classes: [u8; 256],
+        representatives: Vec<u8>,
+        // a search that finds another one using these builds its own from scratch rather than wait
+        states: Mutex<LazyStates>,
+        limit: usize,
+        fallback: Nfa
+    }
+
+    // the states of a LazyDfa so far, numbered like determinize's. Row i of table is state i's
+    // transitions, UNKNOWN for a class it hasn't followed yet.
+    struct LazyStates {
+        table: Vec<u32>,
+        accept: Vec<bool>,
+        sets: Vec<BTreeSet<usize>>,
+        ids: HashMap<BTreeSet<usize>, u32>,
+        memory: usize,
+        // the search going on needed a state there wasn't room for
+        full: bool
+    }
+
+    const UNKNOWN: u32 = u32::MAX;
+
+    impl LazyDfa {
+        pub fn new(graph: &Graph<CharCost>) -> Self {
+            LazyDfa::with_limit(graph, usize::MAX)
+        }
+
+        // the dead and start states are always there, whatever limit is
+        pub fn with_limit(graph: &Graph<CharCost>, limit: usize) -> Self {
+            let nfa = ByteNfa::new(graph);
+            let (classes, class_count) = nfa.byte_classes();
+            let representatives = representatives(&classes, class_count);
+            let states = Mutex::new(LazyStates::new(&nfa, class_count));
+            LazyDfa { nfa, classes, representatives, states, limit, fallback: Nfa::new(graph.clone()) }
+        }
+
+        // how many states have been built so far, the dead one included
+        pub fn state_count(&self) -> usize {
+            self.lock().accept.len()
+        }
+
+        pub fn memory_usage(&self) -> usize {
+            self.lock().memory + self.classes.len()
+        }
+
+        fn lock(&self) -> MutexGuard<'_, LazyStates> {
+            self.states.lock().unwrap_or_else(|a| a.into_inner())
+        }
+
+        fn search<H: Haystack + ?Sized>(&self, states: &mut LazyStates, haystack: &H, position: usize, earliest: bool, metrics: &mut Metrics) -> Option<usize> {
+            states.full = false;
+            let built = states.accept.len();
+            let states = RefCell::new(states);
+            let next = |state, byte| states.borrow_mut().next(self, state, byte);
+            let end = longest_match_at(next, 1, |a| states.borrow().accept[a as usize], haystack, position, earliest, metrics);
+            metrics.dfa_states_built += states.borrow().accept.len() - built;
+            if !states.borrow().full {
+                return end;
+            }
+            self.run_fallback(haystack, position, earliest, metrics)
+        }
+
+        fn run_fallback<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, earliest: bool, metrics: &mut Metrics) -> Option<usize> {
+            metrics.dfa_fallbacks += 1;
+            match earliest {
+                true => self.fallback.earliest_match_counted(haystack, position, metrics),
+                false => self.fallback.longest_match_counted(haystack, position, metrics)
+            }
+        }
+
+        // a search that finds the states in use by another thread runs the nfa instead of waiting
+        fn run<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, earliest: bool, metrics: &mut Metrics) -> Option<usize> {
+            match self.states.try_lock() {
+                Ok(mut states) => self.search(&mut states, haystack, position, earliest, metrics),
+                Err(_) => self.run_fallback(haystack, position, earliest, metrics)
+            }
+        }
+    }
+
+    impl LazyStates {
+        // just the dead state and the start
+        fn new(nfa: &ByteNfa, class_count: usize) -> Self {
+            let mut states = LazyStates { table: vec![], accept: vec![], sets: vec![], ids: HashMap::new(), memory: 0, full: false };
+            states.add(nfa, BTreeSet::new(), class_count);
+            states.add(nfa, nfa.closure(BTreeSet::from([nfa.start])), class_count);
+            states
+        }
+
+        fn add(&mut self, nfa: &ByteNfa, set: BTreeSet<usize>, class_count: usize) -> u32 {
+            let id = self.accept.len() as u32;
+            self.accept.push(set.iter().any(|a| nfa.accept[*a]));
+            // the dead state goes nowhere, so there's nothing to find out about it
+            self.table.extend(std::iter::repeat_n(if id == DEAD { DEAD } else { UNKNOWN }, class_count));
+            self.memory += class_count * size_of::<u32>() + 1 + set.len() * size_of::<usize>() * 2;
+            self.ids.insert(set.clone(), id);
+            self.sets.push(set);
+            id
+        }
+
+        // DEAD, with full set, when the state it leads to is new and there's no room left for it
+        fn next(&mut self, dfa: &LazyDfa, state: u32, byte: u8) -> u32 {
+            let class = dfa.classes[byte as usize] as usize;
+            let index = state as usize * dfa.representatives.len() + class;
+            if self.table[index] == UNKNOWN {
+                let set = dfa.nfa.step(&self.sets[state as usize], dfa.representatives[class]);
+                self.table[index] = match self.ids.get(&set) {
+                    Some(id) => *id,
+                    None if self.memory + dfa.representatives.len() * size_of::<u32>() + 1 + set.len() * size_of::<usize>() * 2 > dfa.limit => {
+                        self.full = true;
+                        return DEAD;
+                    }
+                    None => self.add(&dfa.nfa, set, dfa.representatives.len())
+                };
+            }
+            self.table[index]
+        }
+    }
+
+    impl Engine for LazyDfa {
+        fn longest_match_counted<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, metrics: &mut Metrics) -> Option<usize> {
+            self.run(haystack, position, false, metrics)
+        }
+
+        fn earliest_match_counted<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, metrics: &mut Metrics) -> Option<usize> {
+            self.run(haystack, position, true, metrics)
+        }
+    }
+
+    impl Engine for DenseDfa {
+        fn longest_match_counted<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, metrics: &mut Metrics) -> Option<usize> {
+            longest_match_at(|a, x| self.next(a, x), self.start, |a| self.accept[a as usize], haystack, position, false, metrics)
+        }
+
+        fn earliest_match_counted<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, metrics: &mut Metrics) -> Option<usize> {
+            longest_match_at(|a, x| self.next(a, x), self.start, |a| self.accept[a as usize], haystack, position, true, metrics)
+        }
+    }
+
+    impl Engine for SparseDfa {
+        fn longest_match_counted<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, metrics: &mut Metrics) -> Option<usize> {
+            longest_match_at(|a, x| self.next(a, x), self.start, |a| self.accept[a as usize], haystack, position, false, metrics)
+        }
+
+        fn earliest_match_counted<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, metrics: &mut Metrics) -> Option<usize> {
+            longest_match_at(|a, x| self.next(a, x), self.start, |a| self.accept[a as usize], haystack, position, true, metrics)
+        }
+    }
+
+    // runs through the haystack a chunk at a time, so it never has to be contiguous. With earliest
+    // it gives up on the longest match and stops at the first accepting state.
+    fn longest_match_at<H: Haystack + ?Sized>(mut next: impl FnMut(u32, u8) -> u32, start: u32, accept: impl Fn(u32) -> bool, haystack: &H, position: usize, earliest: bool, metrics: &mut Metrics) -> Option<usize> {
+        let mut state = start;
+        let mut last = accept(state).then_some(position);
+        if earliest && last.is_some() {
+            return last;
+        }
+        let mut chunk_start = position;
+        loop {
+            let chunk = haystack.chunk_at(chunk_start);
+            if chunk.is_empty() {
+                return last;
+            }
+            for (offset, byte) in chunk.iter().enumerate() {
+                metrics.transitions += 1;
+                state = next(state, *byte);
+                if state == DEAD {
+                    return last;
+                }
+                if accept(state) {
+                    last = Some(chunk_start + offset + 1);
+                    if earliest {
+                        return last;
+                    }
+                }
+            }
+            chunk_start += chunk.len();
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::automata::automata::run_automata;
+        use crate::parser::parser::parser;
+
+        #[test]
+        fn test_utf8_sequences() {
+            assert_eq!(utf8_sequences(0x61, 0x7A), vec![vec![(0x61, 0x7A)]]);
+            let mut all: Vec<Vec<(u8, u8)>> = utf8_sequences(0, 0x10FFFF);
+            all.sort();
+            assert_eq!(all, vec![
+                vec![(0x00, 0x7F)],
+                vec![(0xC2, 0xDF), (0x80, 0xBF)],
+                vec![(0xE0, 0xE0), (0xA0, 0xBF), (0x80, 0xBF)],
+                vec![(0xE1, 0xEC), (0x80, 0xBF), (0x80, 0xBF)],
+                vec![(0xED, 0xED), (0x80, 0x9F), (0x80, 0xBF)],
+                vec![(0xEE, 0xEF), (0x80, 0xBF), (0x80, 0xBF)],
+                vec![(0xF0, 0xF0), (0x90, 0xBF), (0x80, 0xBF), (0x80, 0xBF)],
+                vec![(0xF1, 0xF3), (0x80, 0xBF), (0x80, 0xBF), (0x80, 0xBF)],
+                vec![(0xF4, 0xF4), (0x80, 0x8F), (0x80, 0xBF), (0x80, 0xBF)]
+            ]);
+        }
+
+        #[test]
+        fn test_dfas_agree_with_automata() {
+            let patterns = ["a*b", "(a|b)*abb", "[a-zé]+", ".(é|日本)?", "héllo|hé", "[^]|x"];
+            let haystack = "xxabbab héllo 日本語 aé\nbb";
+            for pattern in patterns {
+                let graph = parser(pattern).unwrap().compile();
+                let dfa = DenseDfa::new(&graph);
+                let sparse = SparseDfa::new(&graph);
+                let expected: Vec<(String, usize)> = run_automata(graph.clone(), haystack.to_string()).into_iter().map(|a| (a.matched_string, a.location)).collect();
+                let found: Vec<(String, usize)> = dfa.find_all(haystack).into_iter().map(|a| (a.matched_string, a.location)).collect();
+                assert_eq!(expected, found, "{}", pattern);
+                let found: Vec<(String, usize)> = sparse.find_all(haystack).into_iter().map(|a| (a.matched_string, a.location)).collect();
+                assert_eq!(expected, found, "{} sparse", pattern);
+                for text in ["", "ab", "é", "日本", "abb", "\n"] {
+                    assert_eq!(graph.accepts(text), dfa.accepts(text), "{} on {:?}", pattern, text);
+                    assert_eq!(graph.accepts(text), sparse.accepts(text), "{} sparse on {:?}", pattern, text);
+                }
+            }
+        }
+
+        #[test]
+        fn test_lazy_dfa() {
+            let graph = parser("(a|b)*abb|[a-zé]+x").unwrap().compile();
+            let lazy = LazyDfa::new(&graph);
+            assert_eq!(lazy.state_count(), 2);
+            let haystack = "abababb héllox ba";
+            let expected: Vec<Option<usize>> = (0..haystack.len()).map(|a| DenseDfa::new(&graph).longest_match_at(haystack, a)).collect();
+            let mut metrics = Metrics::default();
+            let found: Vec<Option<usize>> = (0..haystack.len()).map(|a| lazy.longest_match_counted(haystack, a, &mut metrics)).collect();
+            assert_eq!(found, expected);
+            let built = lazy.state_count();
+            assert!(built > 2 && built <= DenseDfa::new(&graph).state_count());
+            assert_eq!(metrics.dfa_states_built, built - 2);
+            // the states built for the first search are still there for the second
+            let mut metrics = Metrics::default();
+            lazy.longest_match_counted(haystack, 0, &mut metrics);
+            assert_eq!((lazy.state_count(), metrics.dfa_states_built), (built, 0));
+        }
+
+        #[test]
+        fn test_lazy_dfa_contended() {
+            let graph = parser("(a|b)*abb").unwrap().compile();
+            let lazy = LazyDfa::new(&graph);
+            let mut metrics = Metrics::default();
+            // with the states taken, as another thread searching would have them
+            let states = lazy.lock();
+            assert_eq!(lazy.longest_match_counted("babba", 0, &mut metrics), Some(4));
+            assert_eq!((metrics.dfa_fallbacks, metrics.dfa_states_built), (1, 0));
+            drop(states);
+            assert_eq!(lazy.state_count(), 2);
+        }
+
+        #[test]
+        fn test_size_limit() {
+            let graph = parser("(a|b)*a(a|b)(a|b)(a|b)(a|b)(a|b)").unwrap().compile();
+            let dense = DenseDfa::new(&graph);
+            assert!(DenseDfa::with_limit(&graph, dense.memory_usage()).is_some());
+            assert!(DenseDfa::with_limit(&graph, dense.memory_usage() / 2).is_none());
+            assert!(SparseDfa::with_limit(&graph, 1000).is_none());
+            let lazy = LazyDfa::with_limit(&graph, 1000);
+            let haystack = "abbabababbbaababbbaa";
+            let mut metrics = Metrics::default();
+            let found: Vec<Option<usize>> = (0..haystack.len()).map(|a| lazy.longest_match_counted(haystack, a, &mut metrics)).collect();
+            assert_eq!(found, (0..haystack.len()).map(|a| dense.longest_match_at(haystack, a)).collect::<Vec<_>>());
+            assert!(metrics.dfa_fallbacks > 0 && lazy.memory_usage() <= 1000 + 256);
+        }
+
+        #[test]
+        fn test_start_bytes() {
+            assert_eq!(start_bytes(&parser("(ab|é)c|[xy]+").unwrap()), Some(vec![b'a', b'x', b'y', 0xC3]));
+            assert_eq!(start_bytes(&parser("\\bfoo").unwrap()), Some(vec![b'f']));
+            assert_eq!(start_bytes(&parser("a|b*").unwrap()), None);
+        }
+
+        #[test]
+        fn test_byte_classes() {
+            let dfa = DenseDfa::new(&parser("[a-z]+").unwrap());
+            assert_eq!(dfa.class_count(), 3);
+            assert_eq!(dfa.state_count(), 3);
+            assert_eq!(dfa.table.len(), 9);
+        }
+
+        #[test]
+        fn test_sparse_is_smaller() {
+            let graph = parser("abcdefghijklmnop").unwrap();
+            let dense = DenseDfa::new(&graph);
+            let sparse = SparseDfa::new(&graph);
+            assert_eq!(dense.state_count(), sparse.state_count());
+            assert!(sparse.memory_usage() * 2 < dense.memory_usage());
+        }
+    }
+}
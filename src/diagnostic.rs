@@ -0,0 +1,142 @@
+pub mod diagnostic {
+    use std::fmt;
+    use std::ops::Range;
+
+    use crate::parser::parser::{lexer, parse_ast_spanned, Dialect, Lexeme, ParseError, SyntaxConfig};
+
+    const RED: &str = "\x1b[1;31m";
+    const BOLD: &str = "\x1b[1m";
+    const RESET: &str = "\x1b[0m";
+
+    // a pattern that doesn't parse and where in it the trouble is, for a tool to show whoever
+    // wrote the pattern
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Diagnostic {
+        pub error: ParseError,
+        // bytes of the pattern, empty at its end when something was left open
+        pub span: Range<usize>,
+        // a likely fix, for the mistakes people tend to make
+        pub suggestion: Option<Suggestion>,
+        pattern: String
+    }
+
+    // a quick fix a UI can offer: replacing span of the pattern with replacement
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Suggestion {
+        pub message: String,
+        pub span: Range<usize>,
+        pub replacement: String
+    }
+
+    impl Suggestion {
+        fn escape(span: Range<usize>, letter: char) -> Self {
+            Suggestion { message: format!("escape it as `\\{}`", letter), span, replacement: format!("\\{}", letter) }
+        }
+    }
+
+    impl Diagnostic {
+        // None when pattern parses
+        pub fn check(pattern: &str, config: &SyntaxConfig) -> Option<Diagnostic> {
+            let (error, span) = parse_ast_spanned(pattern, config).err()?;
+            let suggestion = match &error {
+                // a repeat with nothing before it, which was likely meant literally
+                ParseError::Unexpected(a @ ('*' | '+' | '?')) => Some(Suggestion::escape(span.clone(), *a)),
+                ParseError::UnmatchedParen => Some(Suggestion::escape(span.clone(), ')')),
+                ParseError::TrailingBackslash => Some(Suggestion::escape(span.clone(), '\\')),
+                ParseError::UnclosedClass if config.dialect == Dialect::Default => unclosed_bracket(pattern).map(|a| Suggestion::escape(a, '[')),
+                _ => None
+            };
+            Some(Diagnostic { error, span, suggestion, pattern: pattern.to_string() })
+        }
+
+        // the pattern with the suggestion taken
+        pub fn fixed(&self) -> Option<String> {
+            let suggestion = self.suggestion.as_ref()?;
+            let mut fixed = self.pattern.clone();
+            fixed.replace_range(suggestion.span.clone(), &suggestion.replacement);
+            Some(fixed)
+        }
+
+        // the error, then the pattern with carets under the span, like
+        //
+        //   error: unsupported escape \q
+        //     a\qb
+        //      ^^
+        //
+        // with the error and carets in ANSI colors if color is set
+        pub fn render(&self, color: bool) -> String {
+            let (red, bold, reset) = if color { (RED, BOLD, RESET) } else { ("", "", "") };
+            // one column a char, with tabs and line breaks flattened so the carets line up
+            let pattern: String = self.pattern.chars().map(|a| if a.is_control() { ' ' } else { a }).collect();
+            let indent = self.pattern[..self.span.start].chars().count();
+            let width = self.pattern[self.span.clone()].chars().count().max(1);
+            let mut rendered = format!(
+                "{red}error{reset}{bold}: {}{reset}\n  {}\n  {}{red}{}{reset}\n",
+                self.error, pattern, " ".repeat(indent), "^".repeat(width)
+            );
+            if let Some(suggestion) = &self.suggestion {
+                rendered += &format!("{bold}help{reset}: {}\n", suggestion.message);
+            }
+            rendered
+        }
+    }
+
+    // the outermost [ that's never closed, since that's the one the class runs on from
+    fn unclosed_bracket(pattern: &str) -> Option<Range<usize>> {
+        let mut open = vec![];
+        for (lexeme, span) in lexer(pattern).ok()? {
+            match lexeme {
+                Lexeme::OpenBracket => open.push(span),
+                Lexeme::CloseBracket => drop(open.pop()),
+                _ => {}
+            }
+        }
+        open.into_iter().next()
+    }
+
+    impl fmt::Display for Diagnostic {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", self.render(false))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn rendered(pattern: &str) -> String {
+            Diagnostic::check(pattern, &SyntaxConfig::default()).unwrap().to_string()
+        }
+
+        #[test]
+        fn test_render() {
+            assert_eq!(rendered("a\\qb"), "error: unsupported escape \\q\n  a\\qb\n   ^^\n");
+            assert_eq!(rendered("(ab"), "error: unclosed group\n  (ab\n     ^\n");
+            assert_eq!(rendered("é)x"), "error: unmatched ')'\n  é)x\n   ^\nhelp: escape it as `\\)`\n");
+            assert_eq!(rendered("a\\"), "error: trailing backslash with nothing to escape\n  a\\\n   ^\nhelp: escape it as `\\\\`\n");
+            assert_eq!(rendered("[a-\\d]"), "error: '-' in a class needs a char on both sides\n  [a-\\d]\n    ^^^\n");
+            assert!(Diagnostic::check("a|b", &SyntaxConfig::default()).is_none());
+            let colored = Diagnostic::check("*", &SyntaxConfig::default()).unwrap().render(true);
+            assert_eq!(colored, "\x1b[1;31merror\x1b[0m\x1b[1m: unexpected '*'\x1b[0m\n  *\n  \x1b[1;31m^\x1b[0m\n\x1b[1mhelp\x1b[0m: escape it as `\\*`\n");
+        }
+
+        #[test]
+        fn test_suggestions() {
+            let fixed = |pattern: &str| Diagnostic::check(pattern, &SyntaxConfig::default()).unwrap().fixed();
+            assert_eq!(fixed("*.txt").as_deref(), Some("\\*.txt"));
+            assert_eq!(fixed("f(x))").as_deref(), Some("f(x)\\)"));
+            assert_eq!(fixed("a[b[c]d").as_deref(), Some("a\\[b[c]d"));
+            assert_eq!(fixed("a\\q"), None);
+            assert_eq!(rendered("+1"), "error: unexpected '+'\n  +1\n  ^\nhelp: escape it as `\\+`\n");
+        }
+
+        #[test]
+        fn test_spans() {
+            let span = |pattern: &str, config: &SyntaxConfig| Diagnostic::check(pattern, config).unwrap().span;
+            let config = SyntaxConfig { looks: false, ..SyntaxConfig::default() };
+            assert_eq!(span("ab$", &config), 2..3);
+            assert_eq!(span("(?<n>a)(?<n>b)", &SyntaxConfig::default()), 9..12);
+            assert_eq!(span("x\\p{Klingon}", &SyntaxConfig::default()), 1..12);
+        }
+    }
+}
@@ -0,0 +1,218 @@
+pub mod engine {
+    use std::ops::Range;
+
+    use crate::automata::automata::{earliest_match_counted, epsilon_closures, Closures, longest_match_at, longest_match_counted, next_boundary, Matches};
+    use crate::graph::graph::{Graph, Program};
+    use crate::haystack::haystack::Haystack;
+    use crate::parser::parser::CharCost;
+
+    // where a search begins and whether it may look further on than that
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct SearchConfig {
+        // a byte offset on a char boundary
+        pub start: usize,
+        // only a match starting exactly at start counts
+        pub anchored: bool,
+        // stop at the first place a match is seen to end rather than carry on for the longest,
+        // for when only whether there's a match matters
+        pub earliest: bool
+    }
+
+    // what a search got up to, for working out why a pattern is slow. Each engine counts what it
+    // has: the NFA simulation counts everything, the DFAs have no epsilons, and so on.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct Metrics {
+        // offsets a match was tried from
+        pub positions: usize,
+        // offsets a Regex's prefilters ruled out without trying a match from them
+        pub skipped_positions: usize,
+        // edges crossed, or bytes stepped through for a DFA
+        pub transitions: usize,
+        // states reached without reading anything, through epsilons and looks that held
+        pub epsilon_expansions: usize,
+        // positions a Regex had to simulate because its pattern needs looks the DFA asked for
+        // can't check, because the DFA would have outgrown its size limit, or because another
+        // thread had a lazy DFA's states
+        pub dfa_fallbacks: usize,
+        // states a lazy DFA had to build rather than find already there, which once it has
+        // warmed up should stay at 0
+        pub dfa_states_built: usize
+    }
+
+    // anything that can say where the longest match from a given offset ends. That's all a new
+    // engine has to provide; the search itself, leftmost-longest like everything else here, comes
+    // for free, so engines can be swapped and timed against each other on equal terms.
+    pub trait Engine {
+        // adds whatever it did to metrics
+        fn longest_match_counted<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, metrics: &mut Metrics) -> Option<usize>;
+
+        fn longest_match_at<H: Haystack + ?Sized>(&self, haystack: &H, position: usize) -> Option<usize> {
+            self.longest_match_counted(haystack, position, &mut Metrics::default())
+        }
+
+        // where some match from position ends, as soon as one is found, which needn't be the
+        // longest. An engine that can stop early should; the rest just find the longest.
+        fn earliest_match_counted<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, metrics: &mut Metrics) -> Option<usize> {
+            self.longest_match_counted(haystack, position, metrics)
+        }
+
+        fn search<H: Haystack + ?Sized>(&self, haystack: &H, config: &SearchConfig) -> Option<Range<usize>> {
+            self.search_counted(haystack, config, &mut Metrics::default())
+        }
+
+        // search, with metrics left holding what it took
+        fn search_counted<H: Haystack + ?Sized>(&self, haystack: &H, config: &SearchConfig, metrics: &mut Metrics) -> Option<Range<usize>> {
+            let mut position = config.start;
+            while position <= haystack.len() {
+                metrics.positions += 1;
+                let end = match config.earliest {
+                    true => self.earliest_match_counted(haystack, position, metrics),
+                    false => self.longest_match_counted(haystack, position, metrics)
+                };
+                if let Some(end) = end {
+                    return Some(position..end);
+                }
+                if config.anchored {
+                    break;
+                }
+                position = next_boundary(haystack, position);
+            }
+            None
+        }
+    }
+
+    // the graph simulated directly, states parked per offset, with nothing built up front but the
+    // frozen Program and its epsilon closures. Over chars it's an Engine; over any other symbols a
+    // Graph's costs have Matches for, it runs on slices of them, offsets counting symbols.
+    #[derive(Debug, Clone)]
+    pub struct Nfa<T = CharCost> {
+        program: Program<T>,
+        closures: Closures
+    }
+
+    impl<T> Nfa<T> {
+        pub fn new(graph: Graph<T>) -> Self {
+            let program = graph.freeze();
+            let closures = epsilon_closures(&program);
+            Nfa { program, closures }
+        }
+
+        pub fn program(&self) -> &Program<T> {
+            &self.program
+        }
+
+        // the same leftmost-longest matching as over text, a symbol at a time
+        pub fn longest_match_in<S>(&self, symbols: &[S], position: usize) -> Option<usize> where T: Matches<S> {
+            longest_match_at(&self.program, &self.closures, symbols, position)
+        }
+
+        pub fn find_in<S>(&self, symbols: &[S]) -> Option<Range<usize>> where T: Matches<S> {
+            (0..=symbols.len()).find_map(|start| Some(start..self.longest_match_in(symbols, start)?))
+        }
+
+        pub fn accepts_all<S>(&self, symbols: &[S]) -> bool where T: Matches<S> {
+            self.longest_match_in(symbols, 0) == Some(symbols.len())
+        }
+    }
+
+    impl Nfa {
+        // how many states a simulation numbers, which is more than there are nodes once there are
+        // counted repeats
+        pub(crate) fn state_count(&self) -> usize {
+            self.closures.numbering::<_, str>(&self.program).capacity()
+        }
+    }
+
+    impl Engine for Nfa {
+        fn longest_match_counted<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, metrics: &mut Metrics) -> Option<usize> {
+            longest_match_counted(&self.program, &self.closures, haystack, position, metrics)
+        }
+
+        fn earliest_match_counted<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, metrics: &mut Metrics) -> Option<usize> {
+            earliest_match_counted(&self.program, &self.closures, haystack, position, metrics)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::dfa::dfa::{DenseDfa, LazyDfa, SparseDfa};
+        use crate::parser::parser::{parse_ast, parser};
+        use crate::pikevm::pikevm::PikeVm;
+
+        fn search_all<E: Engine>(engine: &E, haystack: &str) -> Vec<Option<Range<usize>>> {
+            (0..=haystack.len()).flat_map(|start| {
+                [false, true].map(|anchored| engine.search(haystack, &SearchConfig { start, anchored, ..SearchConfig::default() }))
+            }).collect()
+        }
+
+        #[test]
+        fn test_engines_agree() {
+            let patterns = ["a+b", "(ab|a)(bc|c)?", "x*", "[^a]b|c", "(a|b)*abb"];
+            let haystack = "cabcxabbabb";
+            for pattern in patterns {
+                let graph = parser(pattern).unwrap().compile();
+                let expected = search_all(&Nfa::new(graph.clone()), haystack);
+                assert_eq!(search_all(&DenseDfa::new(&graph), haystack), expected, "{}", pattern);
+                assert_eq!(search_all(&SparseDfa::new(&graph), haystack), expected, "{}", pattern);
+                assert_eq!(search_all(&LazyDfa::new(&graph), haystack), expected, "{}", pattern);
+                assert_eq!(search_all(&PikeVm::new(&parse_ast(pattern).unwrap()), haystack), expected, "{}", pattern);
+            }
+        }
+
+        #[test]
+        fn test_anchored() {
+            let nfa = Nfa::new(parser("b+").unwrap());
+            assert_eq!(nfa.search("abba", &SearchConfig::default()), Some(1..3));
+            assert_eq!(nfa.search("abba", &SearchConfig { start: 0, anchored: true, ..SearchConfig::default() }), None);
+            assert_eq!(nfa.search("abba", &SearchConfig { start: 2, anchored: true, ..SearchConfig::default() }), Some(2..3));
+            assert_eq!(nfa.search("abba", &SearchConfig { start: 3, anchored: false, ..SearchConfig::default() }), None);
+        }
+
+        #[test]
+        fn test_metrics() {
+            let graph = parser("ab|[ab]*c").unwrap().compile();
+            let mut nfa = Metrics::default();
+            assert_eq!(Nfa::new(graph.clone()).search_counted("xxabab", &SearchConfig::default(), &mut nfa), Some(2..4));
+            assert_eq!(nfa.positions, 3);
+            assert!(nfa.transitions >= 4 && nfa.epsilon_expansions > 0);
+            let mut dense = Metrics::default();
+            DenseDfa::new(&graph).search_counted("xxabab", &SearchConfig::default(), &mut dense);
+            // one byte each to rule out 0 and 1, then the rest from 2 looking for a c
+            assert_eq!((dense.positions, dense.transitions, dense.epsilon_expansions), (3, 6, 0));
+        }
+
+        #[test]
+        fn test_predicate() {
+            use crate::ast::ast::{Ast, Qualifier};
+            use crate::parser::parser::{graph_from_ast, Predicate};
+            let cjk = Ast::Cost(CharCost::Predicate(Predicate::new(|a| ('\u{4e00}'..='\u{9fff}').contains(&a))));
+            let ast = Ast::Concat(vec![Ast::Repeat(Box::new(cjk), Qualifier::OneOrMore), Ast::Cost(CharCost::Singleton('!'))]);
+            let graph = graph_from_ast(ast.clone()).compile();
+            let haystack = "ok 你好! 好";
+            let starts = |engine: &dyn Fn(&SearchConfig) -> Option<Range<usize>>| -> Vec<Option<Range<usize>>> {
+                haystack.char_indices().map(|(start, _)| engine(&SearchConfig { start, ..SearchConfig::default() })).collect()
+            };
+            let nfa = Nfa::new(graph.clone());
+            let expected = starts(&|a| nfa.search(haystack, a));
+            assert_eq!(expected[..5], [Some(3..10), Some(3..10), Some(3..10), Some(3..10), Some(6..10)]);
+            let dense = DenseDfa::new(&graph);
+            assert_eq!(starts(&|a| dense.search(haystack, a)), expected);
+            let pikevm = PikeVm::new(&ast);
+            assert_eq!(starts(&|a| pikevm.search(haystack, a)), expected);
+        }
+
+        #[test]
+        fn test_earliest() {
+            let graph = parser("a+|abc").unwrap().compile();
+            let config = SearchConfig { earliest: true, ..SearchConfig::default() };
+            assert_eq!(Nfa::new(graph.clone()).search("xaaabc", &SearchConfig::default()), Some(1..4));
+            assert_eq!(Nfa::new(graph.clone()).search("xaaabc", &config), Some(1..2));
+            let mut metrics = Metrics::default();
+            assert_eq!(DenseDfa::new(&graph).search_counted("xaaabc", &config, &mut metrics), Some(1..2));
+            // one byte to rule out 0, one to find the a
+            assert_eq!(metrics.transitions, 2);
+            assert_eq!(SparseDfa::new(&graph).search("xaaabc", &config), Some(1..2));
+        }
+    }
+}
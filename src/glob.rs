@@ -0,0 +1,133 @@
+pub mod glob {
+    use std::iter::Peekable;
+    use std::str::Chars;
+
+    use crate::ast::ast::{Ast, Qualifier};
+    use crate::automata::automata::{epsilon_closures, Closures, longest_match_at};
+    use crate::graph::graph::Program;
+    use crate::parser::parser::{graph_from_ast, CharClass, CharCost, ParseError};
+
+    // a shell-style pattern over paths: * and ? stay inside one path component, ** crosses any
+    // number of them, and [...] is a class, negated with a leading ! or ^. A backslash makes the
+    // next char literal.
+    #[derive(Debug, Clone)]
+    pub struct Glob {
+        program: Program<CharCost>,
+        closures: Closures
+    }
+
+    impl Glob {
+        pub fn new(pattern: &str) -> Result<Self, ParseError> {
+            let program = graph_from_ast(translate(pattern)?).compile().freeze();
+            let closures = epsilon_closures(&program);
+            Ok(Glob { program, closures })
+        }
+
+        // globs only ever match the whole path
+        pub fn is_match(&self, path: &str) -> bool {
+            longest_match_at(&self.program, &self.closures, path, 0) == Some(path.len())
+        }
+    }
+
+    fn any() -> CharClass {
+        CharClass::new().complement()
+    }
+
+    fn not_slash() -> CharClass {
+        let mut class = CharClass::new();
+        class.plus_literal('/');
+        class.complement()
+    }
+
+    fn class(class: CharClass) -> Ast {
+        Ast::Cost(CharCost::Class(class))
+    }
+
+    fn translate(pattern: &str) -> Result<Ast, ParseError> {
+        let mut items = vec![];
+        let mut chars = pattern.chars().peekable();
+        while let Some(letter) = chars.next() {
+            let item = match letter {
+                '*' if chars.next_if_eq(&'*').is_some() => {
+                    let anything = Ast::Repeat(Box::new(class(any())), Qualifier::ZeroOrMore);
+                    // a/**/b has to match a/b too, so the slash after ** goes with it
+                    match chars.next_if_eq(&'/') {
+                        Some(_) => Ast::Repeat(Box::new(Ast::Group(Box::new(Ast::Concat(vec![anything, Ast::Cost(CharCost::Singleton('/'))])), None)), Qualifier::ZeroOrOne),
+                        None => anything
+                    }
+                }
+                '*' => Ast::Repeat(Box::new(class(not_slash())), Qualifier::ZeroOrMore),
+                '?' => class(not_slash()),
+                '[' => class(translate_class(&mut chars)?),
+                '\\' => Ast::Cost(CharCost::Singleton(chars.next().unwrap_or('\\'))),
+                a => Ast::Cost(CharCost::Singleton(a))
+            };
+            items.push(item);
+        }
+        Ok(Ast::Concat(items))
+    }
+
+    // everything after the '[', up to and including the ']'. A ']' straight after the opening
+    // (or its negation) is a member rather than the end.
+    fn translate_class(chars: &mut Peekable<Chars>) -> Result<CharClass, ParseError> {
+        let negated = chars.next_if(|a| *a == '!' || *a == '^').is_some();
+        let mut class = CharClass::new();
+        let mut first = true;
+        loop {
+            let start = match chars.next() {
+                None => return Err(ParseError::UnclosedClass),
+                Some(']') if !first => break,
+                Some('\\') => chars.next().ok_or(ParseError::UnclosedClass)?,
+                Some(a) => a
+            };
+            first = false;
+            let mut ahead = chars.clone();
+            match (ahead.next(), ahead.next()) {
+                (Some('-'), Some(end)) if end != ']' => {
+                    chars.nth(1);
+                    if start > end {
+                        return Err(ParseError::ReversedRange(start, end));
+                    }
+                    class.plus_range(start, end);
+                }
+                _ => class.plus_literal(start)
+            }
+        }
+        // like * and ?, a negated class never matches the slash between components
+        Ok(match negated {
+            true => {
+                class.plus_literal('/');
+                class.complement()
+            }
+            false => class.intersect(&not_slash())
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_wildcards() {
+            let glob = Glob::new("*.rs").unwrap();
+            assert!(glob.is_match("main.rs") && glob.is_match(".rs"));
+            assert!(!glob.is_match("src/main.rs") && !glob.is_match("main.rsx"));
+            let glob = Glob::new("src/**/*.r?").unwrap();
+            for path in ["src/lib.rs", "src/a/b/c.rb"] {
+                assert!(glob.is_match(path), "{}", path);
+            }
+            assert!(!glob.is_match("lib.rs") && !glob.is_match("src/a/.r/"));
+            assert!(Glob::new("**").unwrap().is_match("any/thing/at/all"));
+        }
+
+        #[test]
+        fn test_classes() {
+            let glob = Glob::new("[a-c]x[!0-9]").unwrap();
+            assert!(glob.is_match("bxz") && !glob.is_match("dxz") && !glob.is_match("ax5") && !glob.is_match("ax/"));
+            let glob = Glob::new("[]!]\\*").unwrap();
+            assert!(glob.is_match("]*") && glob.is_match("!*") && !glob.is_match("]x"));
+            assert_eq!(Glob::new("[ab").unwrap_err(), ParseError::UnclosedClass);
+            assert_eq!(Glob::new("[z-a]").unwrap_err(), ParseError::ReversedRange('z', 'a'));
+        }
+    }
+}
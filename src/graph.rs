@@ -1,16 +1,37 @@
 pub mod graph {
-    #[derive(Debug, PartialEq)]
+    use std::collections::VecDeque;
+
+    #[derive(Debug, PartialEq, Clone)]
     pub struct Graph<T> {
         pub arena: Vec<Option<Node<T>>>,
         pub start: NodeIndex,
-        pub active: NodeIndex
+        pub active: NodeIndex,
+        // filled in by mark_accepting once building is done, and kept up to date by every pass after
+        pub accept: Vec<NodeIndex>,
+        // the pattern each node in accept belongs to, kept in step with it. Anything built from a
+        // single pattern has nothing but 0 here, see with_pattern for the rest.
+        pub patterns: Vec<usize>,
+        // slots emptied by remove_node, waiting to be handed out again
+        free: Vec<NodeIndex>
+    }
+
+    // only ever handed out by a Graph, so it always points at a slot that existed in that graph
+    #[repr(transparent)]
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+    pub struct NodeIndex(usize);
+
+    impl NodeIndex {
+        pub fn index(self) -> usize {
+            self.0
+        }
     }
 
-    pub type NodeIndex = usize;
+    type Edges<T> = [(NodeIndex, Option<T>)];
 
-    #[derive(Debug, PartialEq)]
+    #[derive(Debug, PartialEq, Clone)]
     pub struct Node<T> {
         pub edges: Vec<(NodeIndex, Option<T>)>,
+        // dangling: still waiting, while building, for whatever comes next to be linked on
         endlinked: bool
     }
 
@@ -23,37 +44,193 @@ pub mod graph {
         }
     }
 
+    // a Graph once building is done, frozen for matching: nodes numbered from 0 with no empty slots
+    // or building bookkeeping, and every edge in one allocation, each node's side by side
+    #[derive(Debug, PartialEq, Clone)]
+    pub struct Program<T> {
+        // node i's edges are edges[bounds[i]..bounds[i + 1]]
+        bounds: Box<[usize]>,
+        edges: Box<[(usize, Option<T>)]>,
+        start: usize,
+        // the accepting nodes with the pattern each is for, in the order the graph had them
+        accept: Box<[(usize, usize)]>
+    }
+
+    impl<T> Program<T> {
+        pub fn node_count(&self) -> usize {
+            self.bounds.len() - 1
+        }
+
+        pub fn start(&self) -> usize {
+            self.start
+        }
+
+        pub fn edges(&self, node: usize) -> &[(usize, Option<T>)] {
+            &self.edges[self.bounds[node]..self.bounds[node + 1]]
+        }
+
+        pub fn accept(&self) -> &[(usize, usize)] {
+            &self.accept
+        }
+    }
+
+    pub struct Dfs<'a, T> {
+        graph: &'a Graph<T>,
+        stack: Vec<NodeIndex>,
+        seen: Vec<bool>
+    }
+
+    impl<T> Iterator for Dfs<'_, T> {
+        type Item = NodeIndex;
+
+        fn next(&mut self) -> Option<NodeIndex> {
+            while let Some(node_index) = self.stack.pop() {
+                if std::mem::replace(&mut self.seen[node_index.0], true) {
+                    continue;
+                }
+                if let Some(node) = self.graph.node(node_index) {
+                    // pushed backwards so edges get explored in the order they were added
+                    self.stack.extend(node.edges.iter().rev().map(|(next, _)| *next));
+                    return Some(node_index);
+                }
+            }
+            None
+        }
+    }
+
+    pub struct Bfs<'a, T> {
+        graph: &'a Graph<T>,
+        queue: VecDeque<NodeIndex>,
+        seen: Vec<bool>
+    }
+
+    impl<T> Iterator for Bfs<'_, T> {
+        type Item = NodeIndex;
+
+        fn next(&mut self) -> Option<NodeIndex> {
+            let node_index = self.queue.pop_front()?;
+            if let Some(node) = self.graph.node(node_index) {
+                for (next, _) in node.edges.iter() {
+                    if !std::mem::replace(&mut self.seen[next.0], true) {
+                        self.queue.push_back(*next);
+                    }
+                }
+            }
+            Some(node_index)
+        }
+    }
+
+    impl<T> Default for Graph<T> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
     impl<T> Graph<T> {
-        // ensures "monotonicity" of node numbers
+        // ensures "monotonicity" of node numbers, so long as nothing has been removed. Building relies
+        // on that; only the passes run afterwards remove nodes.
         fn add_node(&mut self, node: Node<T>) -> NodeIndex {
+            if let Some(index) = self.free.pop() {
+                self.arena[index.0] = Some(node);
+                return index;
+            }
             self.arena.push(Some(node));
-            let index = self.arena.len()-1;
-            index
+            NodeIndex(self.arena.len()-1)
+        }
+
+        // the checked way in from a plain number: None unless it names a live node
+        pub fn node_index(&self, index: usize) -> Option<NodeIndex> {
+            self.arena.get(index)?.as_ref().map(|_| NodeIndex(index))
+        }
+
+        pub fn node(&self, index: NodeIndex) -> Option<&Node<T>> {
+            self.arena.get(index.0)?.as_ref()
+        }
+
+        pub fn node_mut(&mut self, index: NodeIndex) -> Option<&mut Node<T>> {
+            self.arena.get_mut(index.0)?.as_mut()
+        }
+
+        // every live node, in arena order
+        pub fn indices(&self) -> impl Iterator<Item = NodeIndex> + '_ {
+            (0..self.arena.len()).filter_map(|a| self.node_index(a))
+        }
+
+        pub fn nodes(&self) -> impl Iterator<Item = (NodeIndex, &Node<T>)> {
+            self.arena.iter().enumerate().filter_map(|(a, x)| Some((NodeIndex(a), x.as_ref()?)))
+        }
+
+        // every (from, to, cost) triple, grouped by from in arena order
+        pub fn edges(&self) -> impl Iterator<Item = (NodeIndex, NodeIndex, Option<&T>)> {
+            self.nodes().flat_map(|(from, node)| node.edges.iter().map(move |(to, cost)| (from, *to, cost.as_ref())))
+        }
+
+        // the nodes reachable from start, each once, depth first
+        pub fn dfs(&self, start: NodeIndex) -> Dfs<'_, T> {
+            Dfs {
+                graph: self,
+                stack: vec![start],
+                seen: vec![false; self.arena.len()]
+            }
+        }
+
+        // the nodes reachable from start, each once, breadth first
+        pub fn bfs(&self, start: NodeIndex) -> Bfs<'_, T> {
+            let mut seen = vec![false; self.arena.len()];
+            seen[start.0] = true;
+            Bfs {
+                graph: self,
+                queue: VecDeque::from([start]),
+                seen
+            }
+        }
+
+        // a node outside the build order: not linked to anything and not dangling
+        pub fn new_node(&mut self) -> NodeIndex {
+            self.add_node(Node::new(vec![]))
+        }
+
+        // tombstones the slot, dropping every edge into it, and frees it up for the next add_node
+        pub fn remove_node(&mut self, index: NodeIndex) -> Option<Node<T>> {
+            assert_ne!(index, self.start);
+            let removed = self.arena[index.0].take()?;
+            for node in self.arena.iter_mut().flatten() {
+                node.edges.retain(|(next, _)| *next != index);
+            }
+            if let Some(position) = self.accept.iter().position(|a| *a == index) {
+                self.accept.remove(position);
+                self.patterns.remove(position);
+            }
+            self.free.push(index);
+            Some(removed)
         }
 
         pub fn new() -> Self {
             let mut graph = Graph {
                 arena: Vec::new(),
-                start: 0,
-                active: 0
+                start: NodeIndex(0),
+                active: NodeIndex(0),
+                accept: Vec::new(),
+                patterns: Vec::new(),
+                free: Vec::new()
             };
             let start = Node::new(vec!());
-            assert_eq!(graph.add_node(start), 0);
-            graph.set_active(0);
+            assert_eq!(graph.add_node(start), NodeIndex(0));
+            graph.set_active(NodeIndex(0));
             graph
         }
 
         fn set_active(&mut self, new_active: NodeIndex) {
-            self.arena[new_active].as_mut().unwrap().endlinked = true;
+            self.arena[new_active.0].as_mut().unwrap().endlinked = true;
             self.active = new_active;
         }
 
         fn bump_endlinked(&mut self, endlinked: NodeIndex, new: NodeIndex, cost: Option<T>) {
-            assert!(self.arena.len() >= endlinked && self.arena.len() >= new);
-            assert!(self.arena[endlinked].as_ref().is_some());
-            assert!(self.arena[endlinked].as_ref().unwrap().endlinked);
+            assert!(self.arena.len() >= endlinked.0 && self.arena.len() >= new.0);
+            assert!(self.arena[endlinked.0].as_ref().is_some());
+            assert!(self.arena[endlinked.0].as_ref().unwrap().endlinked);
 
-            let bumped_node = self.arena[endlinked].as_mut().unwrap();
+            let bumped_node = self.arena[endlinked.0].as_mut().unwrap();
             bumped_node.endlinked = false;
             bumped_node.edges.push((new, cost));
         }
@@ -66,10 +243,28 @@ pub mod graph {
             self.set_active(new_active_node_index);
         }
 
+        pub fn add_epsilon(&mut self) {
+            let new_active_node = Node::new(vec![]);
+            let new_active_node_index = self.add_node(new_active_node);
+
+            self.bump_endlinked(self.active, new_active_node_index, None);
+            self.set_active(new_active_node_index);
+        }
+
+        // hands back a start node nothing else hangs off yet, so a loop or junction begun there stays self-contained
+        pub fn fresh_start(&mut self) -> NodeIndex {
+            let has_edges = !self.arena[self.active.0].as_ref().unwrap().edges.is_empty();
+            let dangling_after = self.arena[self.active.0+1..].iter().any(|a| a.as_ref().is_some_and(|x| x.endlinked));
+            if has_edges || dangling_after {
+                self.add_epsilon();
+            }
+            self.active
+        }
+
         pub fn add_junction(&mut self, start: NodeIndex) {
             // check that it's a valid starting node
-            assert!(start < self.arena.len());
-            assert!(self.arena[start].is_some());
+            assert!(start.0 < self.arena.len());
+            assert!(self.arena[start.0].is_some());
 
             self.set_active(start);
         }
@@ -78,15 +273,15 @@ pub mod graph {
             // we will use a very strong property of the way we've made this structure. 
             // if start is actually a junction, then we know that everything it points to is _after_ it, and before "now"
             let mut dangling_nodes: Vec<NodeIndex> = Vec::new();
-            for node_index in start..self.arena.len() {
+            for node_index in start.0..self.arena.len() {
                 if self.arena[node_index].is_none() {
                     continue;
                 }
                 if self.arena[node_index].as_ref().unwrap().endlinked {
-                    dangling_nodes.push(node_index);
+                    dangling_nodes.push(NodeIndex(node_index));
                 }
             }
-            if dangling_nodes.len() == 0 {
+            if dangling_nodes.is_empty() {
                 return
             }
             let new_active_node = Node::new(vec![]);
@@ -99,28 +294,218 @@ pub mod graph {
             self.set_active(new_active_node_index);
         }
 
+        // copies other's nodes in after ours, shifting every index it uses, and hands back where its
+        // start and active nodes ended up
+        fn splice(&mut self, other: Graph<T>) -> (NodeIndex, NodeIndex) {
+            let offset = self.arena.len();
+            let shift = |a: NodeIndex| NodeIndex(a.0 + offset);
+            self.arena.extend(other.arena.into_iter().map(|node| node.map(|mut node| {
+                for (next, _) in node.edges.iter_mut() {
+                    *next = shift(*next);
+                }
+                node
+            })));
+            self.free.extend(other.free.into_iter().map(shift));
+            self.accept.extend(other.accept.into_iter().map(shift));
+            self.patterns.extend(other.patterns);
+            (shift(other.start), shift(other.active))
+        }
+
+        // accepts a string of ours followed by a string of other's
+        pub fn concat(mut self, other: Graph<T>) -> Self {
+            let accepting = std::mem::take(&mut self.accept);
+            self.patterns.clear();
+            let (start, active) = self.splice(other);
+            for node_index in accepting {
+                let node = self.arena[node_index.0].as_mut().unwrap();
+                node.endlinked = false;
+                node.edges.push((start, None));
+            }
+            self.active = active;
+            self
+        }
+
+        // accepts a string of either. Like an alternation that hasn't been closed yet, both sides'
+        // accepting nodes are left dangling and building carries on from other's active node.
+        pub fn union(mut self, other: Graph<T>) -> Self {
+            let (start, active) = self.splice(other);
+            // a new start, since either old one may be the target of a loop
+            self.start = self.add_node(Node::new(vec![(self.start, None), (start, None)]));
+            self.active = active;
+            self
+        }
+
+        // the automaton for the reversed language: every edge flipped, the old accepting nodes fed by a
+        // new start, and the old start the one accepting node. Costs are moved over as they are, so
+        // multi-char ones need a map to flip them too.
+        pub fn reverse(self) -> Self {
+            let mut arena: Vec<Option<Node<T>>> = self.arena.iter().map(|a| a.as_ref().map(|_| Node::new(vec![]))).collect();
+            for (from, node) in self.arena.into_iter().enumerate() {
+                for (to, cost) in node.into_iter().flat_map(|a| a.edges) {
+                    arena[to.0].as_mut().unwrap().edges.push((NodeIndex(from), cost));
+                }
+            }
+
+            let mut graph = Graph {
+                arena,
+                start: self.start,
+                active: self.start,
+                accept: vec![self.start],
+                patterns: vec![0],
+                free: self.free
+            };
+            graph.start = graph.add_node(Node::new(self.accept.into_iter().map(|a| (a, None)).collect()));
+            graph.set_active(self.start);
+            graph
+        }
+
+        // same topology, every cost run through f
+        pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> Graph<U> {
+            let arena = self.arena.into_iter().map(|node| node.map(|node| Node {
+                edges: node.edges.into_iter().map(|(next, cost)| (next, cost.map(&mut f))).collect(),
+                endlinked: node.endlinked
+            })).collect();
+            Graph {
+                arena,
+                start: self.start,
+                active: self.active,
+                accept: self.accept,
+                patterns: self.patterns,
+                free: self.free
+            }
+        }
+
+        // drops empty slots and every node start can't reach, renumbering what's left in order
+        pub fn compact(mut self) -> Self {
+            let mut reachable = vec![false; self.arena.len()];
+            for node_index in self.dfs(self.start) {
+                reachable[node_index.0] = true;
+            }
+
+            let mut renumbered = vec![None; self.arena.len()];
+            let mut arena = Vec::new();
+            for (node_index, node) in std::mem::take(&mut self.arena).into_iter().enumerate() {
+                if let Some(node) = node.filter(|_| reachable[node_index]) {
+                    renumbered[node_index] = Some(NodeIndex(arena.len()));
+                    arena.push(Some(node));
+                }
+            }
+            for node in arena.iter_mut().flatten() {
+                for (next, _) in node.edges.iter_mut() {
+                    *next = renumbered[next.0].unwrap();
+                }
+            }
+            arena.shrink_to_fit();
+
+            self.arena = arena;
+            self.free.clear();
+            self.start = renumbered[self.start.0].unwrap();
+            self.active = renumbered[self.active.0].unwrap_or(self.start);
+            let kept = self.accept.iter().zip(self.patterns.iter()).filter_map(|(a, x)| Some((renumbered[a.0]?, *x)));
+            (self.accept, self.patterns) = kept.unzip();
+            self
+        }
+
+        // the Program matching runs over, made from what start can reach
+        pub fn freeze(self) -> Program<T> {
+            let graph = self.compact();
+            let mut bounds = vec![0];
+            let mut edges = vec![];
+            for node in graph.arena.into_iter().flatten() {
+                edges.extend(node.edges.into_iter().map(|(next, cost)| (next.0, cost)));
+                bounds.push(edges.len());
+            }
+            let accept = graph.accept.iter().zip(graph.patterns.iter()).map(|(a, x)| (a.0, *x)).collect();
+            Program { bounds: bounds.into(), edges: edges.into(), start: graph.start.0, accept }
+        }
+
         pub fn zero_or_one(&mut self, start: NodeIndex) {
             self.add_junction(start);
             self.close_junction(start);
         }
 
         pub fn one_or_more(&mut self, start: NodeIndex) {
-            self.arena[self.active].as_mut().unwrap().edges.push((start, None));
+            self.arena[self.active.0].as_mut().unwrap().edges.push((start, None));
         }
 
         pub fn zero_or_more(&mut self, start: NodeIndex) {
-            self.arena[self.active].as_mut().unwrap().edges.push((start, None));
+            // the loop end carries on through start, so it mustn't be left dangling itself
+            self.bump_endlinked(self.active, start, None);
             self.add_junction(start);
         }
 
-        pub fn compile(self) -> Self {
-            todo!()
+        // equal up to renumbering: what each start can reach has the same shape, the same costs and
+        // the same accepting nodes, whatever indices it all sits at and whatever order edges were added in
+        pub fn is_isomorphic_to(&self, other: &Graph<T>) -> bool where T: PartialEq {
+            self.dfs(self.start).count() == other.dfs(other.start).count()
+                && self.match_nodes(other, vec![(self.start, other.start)], vec![None; self.arena.len()], vec![None; other.arena.len()])
+        }
+
+        fn match_nodes(&self, other: &Graph<T>, mut pending: Vec<(NodeIndex, NodeIndex)>, mut forward: Vec<Option<NodeIndex>>, mut backward: Vec<Option<NodeIndex>>) -> bool where T: PartialEq {
+            while let Some((ours, theirs)) = pending.pop() {
+                match (forward[ours.0], backward[theirs.0]) {
+                    (Some(a), Some(b)) if a == theirs && b == ours => continue,
+                    (None, None) => {}
+                    _ => return false
+                }
+                let (Some(our_node), Some(their_node)) = (self.node(ours), other.node(theirs)) else { return false };
+                if our_node.edges.len() != their_node.edges.len() || self.pattern_of(ours) != other.pattern_of(theirs) {
+                    return false;
+                }
+                forward[ours.0] = Some(theirs);
+                backward[theirs.0] = Some(ours);
+                let used = vec![false; their_node.edges.len()];
+                return self.match_edges(other, (&our_node.edges, &their_node.edges), used, pending, forward, backward);
+            }
+            true
+        }
+
+        // tries every way of pairing up the edges left in ours with equally costed ones in theirs
+        fn match_edges(&self, other: &Graph<T>, (ours, theirs): (&Edges<T>, &Edges<T>), used: Vec<bool>, pending: Vec<(NodeIndex, NodeIndex)>, forward: Vec<Option<NodeIndex>>, backward: Vec<Option<NodeIndex>>) -> bool where T: PartialEq {
+            let Some(((our_next, our_cost), rest)) = ours.split_first() else {
+                return self.match_nodes(other, pending, forward, backward);
+            };
+            for (index, (their_next, their_cost)) in theirs.iter().enumerate() {
+                if used[index] || our_cost != their_cost {
+                    continue;
+                }
+                let mut used = used.clone();
+                used[index] = true;
+                let mut pending = pending.clone();
+                pending.push((*our_next, *their_next));
+                if self.match_edges(other, (rest, theirs), used, pending, forward.clone(), backward.clone()) {
+                    return true;
+                }
+            }
+            false
+        }
+
+        // nodes still left dangling once building is done are the accepting ones
+        pub fn mark_accepting(&mut self) {
+            let dangling: Vec<NodeIndex> = self.nodes().filter(|(_, a)| a.endlinked).map(|(a, _)| a).collect();
+            for node_index in dangling {
+                if !self.accept.contains(&node_index) {
+                    self.accept.push(node_index);
+                    self.patterns.push(0);
+                }
+            }
+        }
+
+        // which pattern node accepts for, None if it isn't accepting
+        pub fn pattern_of(&self, node: NodeIndex) -> Option<usize> {
+            self.accept.iter().position(|a| *a == node).map(|a| self.patterns[a])
+        }
+
+        // hands every accepting node to pattern, so unions of several graphs can tell which one matched
+        pub fn with_pattern(mut self, pattern: usize) -> Self {
+            self.patterns = vec![pattern; self.accept.len()];
+            self
         }
     }
 
     #[cfg(test)]
 mod tests {
-    use super::{Graph, Node};
+    use super::{Graph, Node, NodeIndex, Program};
 
     #[test]
     fn basic_addition() {
@@ -130,71 +515,290 @@ mod tests {
         let goal = Graph {
             arena: vec![
                 Some(Node {
-                    edges: vec![(1, Some('a'))],
+                    edges: vec![(NodeIndex(1), Some('a'))],
                     endlinked: false
                 }), Some(Node {
-                    edges: vec![(2, Some('b'))],
+                    edges: vec![(NodeIndex(2), Some('b'))],
                     endlinked: false
                 }), Some(Node {
                     edges: vec![],
                     endlinked: true
                 }) ],
-            start: 0,
-            active: 2
+            start: NodeIndex(0),
+            active: NodeIndex(2),
+            accept: vec![],
+            patterns: vec![],
+            free: vec![]
         };
 
         assert_eq!(graph, goal)
     }
 
+    #[test]
+    fn compaction() {
+        let mut graph = Graph::new();
+        graph.add_cost('a');
+        let orphan = graph.new_node();
+        graph.node_mut(orphan).unwrap().edges.push((NodeIndex(1), Some('x')));
+        graph.arena.push(None);
+        graph.add_cost('b');
+        let graph = graph.compact();
+
+        let mut goal = Graph::new();
+        goal.add_cost('a');
+        goal.add_cost('b');
+        assert_eq!(goal, graph);
+        // the nodes left are renumbered from 0 with no gaps, and the edges follow them
+        assert!(graph.arena.capacity() >= graph.arena.len());
+        assert_eq!(graph.arena.len(), 3);
+        assert_eq!(graph.node(NodeIndex(0)).unwrap().edges, vec![(NodeIndex(1), Some('a'))]);
+        assert_eq!(graph.node(NodeIndex(1)).unwrap().edges, vec![(NodeIndex(2), Some('b'))]);
+    }
+
+    #[test]
+    fn freezing() {
+        let mut graph = Graph::new();
+        graph.add_cost('a');
+        let start = graph.fresh_start();
+        graph.add_cost('b');
+        graph.zero_or_more(start);
+        graph.arena.push(None);
+        graph.mark_accepting();
+        let program = graph.freeze();
+        let goal = Program {
+            bounds: vec![0, 1, 2, 3].into(),
+            edges: vec![(1, Some('a')), (2, Some('b')), (1, None)].into(),
+            start: 0,
+            accept: vec![(1, 0)].into()
+        };
+        assert_eq!(program, goal);
+        assert_eq!(program.edges(1), &[(2, Some('b'))]);
+    }
+
+    #[test]
+    fn removal_reuses_slots() {
+        let mut graph = Graph::new();
+        graph.add_cost('a');
+        graph.add_cost('b');
+        graph.add_junction(NodeIndex(0));
+        graph.add_cost('c');
+        let removed = graph.remove_node(NodeIndex(1)).unwrap();
+        assert_eq!(removed.edges, vec![(NodeIndex(2), Some('b'))]);
+        assert_eq!(graph.arena[0].as_ref().unwrap().edges, vec![(NodeIndex(3), Some('c'))]);
+        assert!(graph.arena[1].is_none());
+
+        assert_eq!(graph.new_node(), NodeIndex(1));
+        assert_eq!(graph.new_node(), NodeIndex(4));
+        assert!(graph.remove_node(NodeIndex(1)).is_some());
+        assert!(graph.remove_node(NodeIndex(1)).is_none());
+    }
+
+    #[test]
+    fn traversals() {
+        let mut graph = Graph::new();
+        graph.add_cost('a');
+        graph.add_cost('b');
+        graph.add_junction(NodeIndex(0));
+        graph.add_cost('c');
+        graph.close_junction(NodeIndex(0));
+        graph.one_or_more(NodeIndex(0));
+        graph.new_node();
+
+        let order = |a: &mut dyn Iterator<Item = NodeIndex>| a.map(|x| x.index()).collect::<Vec<_>>();
+        assert_eq!(order(&mut graph.dfs(NodeIndex(0))), vec![0, 1, 2, 4, 3]);
+        assert_eq!(order(&mut graph.bfs(NodeIndex(0))), vec![0, 1, 3, 2, 4]);
+        assert_eq!(order(&mut graph.bfs(NodeIndex(3))), vec![3, 4, 0, 1, 2]);
+        assert_eq!(graph.nodes().count(), 6);
+        let edges: Vec<(usize, usize, Option<char>)> = graph.edges().map(|(a, b, c)| (a.index(), b.index(), c.copied())).collect();
+        assert_eq!(edges, vec![(0, 1, Some('a')), (0, 3, Some('c')), (1, 2, Some('b')), (2, 4, None), (3, 4, None), (4, 0, None)]);
+    }
+
+    #[test]
+    fn mapping() {
+        let mut graph = Graph::new();
+        graph.add_cost('a');
+        graph.add_junction(NodeIndex(0));
+        graph.add_cost('b');
+        graph.close_junction(NodeIndex(0));
+        graph.zero_or_more(NodeIndex(0));
+
+        let mut goal = Graph::new();
+        goal.add_cost(97);
+        goal.add_junction(NodeIndex(0));
+        goal.add_cost(98);
+        goal.close_junction(NodeIndex(0));
+        goal.zero_or_more(NodeIndex(0));
+        assert_eq!(goal, graph.map(|a| a as u32));
+    }
+
+    #[test]
+    fn combinators() {
+        let mut left = Graph::new();
+        left.add_cost('a');
+        left.mark_accepting();
+        let mut right = Graph::new();
+        right.add_cost('b');
+        right.add_cost('c');
+        right.mark_accepting();
+
+        let mut goal = Graph::new();
+        goal.add_cost('a');
+        goal.add_epsilon();
+        goal.add_cost('b');
+        goal.add_cost('c');
+        goal.mark_accepting();
+        assert_eq!(goal, left.clone().concat(right.clone()));
+
+        let union = left.union(right);
+        assert_eq!(union.start, NodeIndex(5));
+        assert_eq!(union.arena[5].as_ref().unwrap().edges, vec![(NodeIndex(0), None), (NodeIndex(2), None)]);
+        assert_eq!(union.accept, vec![NodeIndex(1), NodeIndex(4)]);
+        assert_eq!(union.patterns, vec![0, 0]);
+        assert_eq!(union.active, NodeIndex(4));
+    }
+
+    #[test]
+    fn reversal() {
+        let mut graph = Graph::new();
+        graph.add_cost('a');
+        graph.add_junction(NodeIndex(0));
+        graph.add_cost('b');
+        graph.mark_accepting();
+        assert_eq!(graph.accept, vec![NodeIndex(1), NodeIndex(2)]);
+        let reversed = graph.reverse();
+
+        assert_eq!(reversed.start, NodeIndex(3));
+        assert_eq!(reversed.accept, vec![NodeIndex(0)]);
+        assert_eq!(reversed.arena[3].as_ref().unwrap().edges, vec![(NodeIndex(1), None), (NodeIndex(2), None)]);
+        assert_eq!(reversed.arena[1].as_ref().unwrap().edges, vec![(NodeIndex(0), Some('a'))]);
+        assert_eq!(reversed.arena[2].as_ref().unwrap().edges, vec![(NodeIndex(0), Some('b'))]);
+        assert!(reversed.arena[0].as_ref().unwrap().edges.is_empty());
+    }
+
+    #[test]
+    fn isomorphism() {
+        let build = |first: char, second: char| {
+            let mut graph = Graph::new();
+            graph.add_cost(first);
+            graph.add_cost('x');
+            graph.add_junction(NodeIndex(0));
+            graph.add_cost(second);
+            graph.close_junction(NodeIndex(0));
+            graph.zero_or_more(NodeIndex(0));
+            graph.mark_accepting();
+            graph
+        };
+        let mut shuffled = build('a', 'b');
+        shuffled.arena[0].as_mut().unwrap().edges.reverse();
+        // the same graph with every node moved to a new slot
+        let order = [3, 0, 4, 1, 2];
+        let mut renumbered = build('a', 'b');
+        let arena = std::mem::take(&mut renumbered.arena);
+        renumbered.arena = (0..arena.len()).map(|_| None).collect();
+        for (index, node) in arena.into_iter().enumerate() {
+            let mut node = node.unwrap();
+            for (next, _) in node.edges.iter_mut() {
+                *next = NodeIndex(order[next.0]);
+            }
+            renumbered.arena[order[index]] = Some(node);
+        }
+        renumbered.start = NodeIndex(order[0]);
+        renumbered.accept = renumbered.accept.iter().map(|a| NodeIndex(order[a.0])).collect();
+
+        assert!(build('a', 'b').is_isomorphic_to(&build('a', 'b')));
+        assert!(build('a', 'b').is_isomorphic_to(&shuffled));
+        assert!(build('a', 'b').is_isomorphic_to(&renumbered));
+        assert_ne!(build('a', 'b'), renumbered);
+        assert!(!build('a', 'b').is_isomorphic_to(&build('a', 'c')));
+        assert!(!build('a', 'b').is_isomorphic_to(&build('b', 'a')));
+        let mut unaccepting = build('a', 'b');
+        unaccepting.accept.clear();
+        unaccepting.patterns.clear();
+        assert!(!build('a', 'b').is_isomorphic_to(&unaccepting));
+        assert!(!build('a', 'b').is_isomorphic_to(&build('a', 'b').with_pattern(1)));
+    }
+
+    #[test]
+    fn pattern_ids() {
+        let build = |letter: char, pattern: usize| {
+            let mut graph = Graph::new();
+            graph.add_cost(letter);
+            graph.mark_accepting();
+            graph.with_pattern(pattern)
+        };
+        let mut union = build('a', 0).union(build('b', 1)).union(build('c', 2));
+        assert_eq!(union.pattern_of(NodeIndex(3)), Some(1));
+        assert_eq!(union.pattern_of(NodeIndex(0)), None);
+        union.remove_node(NodeIndex(3));
+        assert_eq!(union.patterns, vec![0, 2]);
+        let compacted = union.compact();
+        assert_eq!(compacted.accept.iter().map(|a| compacted.pattern_of(*a)).collect::<Vec<_>>(), vec![Some(0), Some(2)]);
+    }
+
+    #[test]
+    fn checked_indices() {
+        let mut graph: Graph<char> = Graph::new();
+        graph.add_cost('a');
+        assert_eq!(graph.node_index(1), Some(NodeIndex(1)));
+        assert_eq!(graph.node_index(2), None);
+        graph.add_cost('b');
+        graph.remove_node(NodeIndex(1));
+        assert_eq!(graph.node_index(1), None);
+        assert_eq!(graph.indices().map(|a| a.index()).collect::<Vec<_>>(), vec![0, 2]);
+    }
+
     #[test]
     fn ripped_graph() {
         let mut graph = Graph::new();
         graph.add_cost('N');
         graph.add_cost('3');
-        graph.add_junction(0);
+        graph.add_junction(NodeIndex(0));
         graph.add_cost('T');
         graph.add_cost('R');
         graph.add_cost('A');
-        graph.add_junction(0);
+        graph.add_junction(NodeIndex(0));
         graph.add_cost('N');
         graph.add_cost('7');
-        graph.close_junction(0);
-        graph.one_or_more(0);
+        graph.close_junction(NodeIndex(0));
+        graph.one_or_more(NodeIndex(0));
 
         let goal = Graph {
             arena: vec![
                 Some(Node {
-                    edges: vec![(1, Some('N')),(3, Some('T')),(6, Some('N'))],
+                    edges: vec![(NodeIndex(1), Some('N')),(NodeIndex(3), Some('T')),(NodeIndex(6), Some('N'))],
                     endlinked: false
                 }), Some(Node {
-                    edges: vec![(2, Some('3'))],
+                    edges: vec![(NodeIndex(2), Some('3'))],
                     endlinked: false
                 }), Some(Node {
-                    edges: vec![(8, None)],
+                    edges: vec![(NodeIndex(8), None)],
                     endlinked: false
                 }), Some(Node {
-                    edges: vec![(4, Some('R'))],
+                    edges: vec![(NodeIndex(4), Some('R'))],
                     endlinked: false
                 }), Some(Node {
-                    edges: vec![(5, Some('A'))],
+                    edges: vec![(NodeIndex(5), Some('A'))],
                     endlinked: false
                 }), Some(Node {
-                    edges: vec![(8, None)],
+                    edges: vec![(NodeIndex(8), None)],
                     endlinked: false
                 }), Some(Node {
-                    edges: vec![(7, Some('7'))],
+                    edges: vec![(NodeIndex(7), Some('7'))],
                     endlinked: false
                 }), Some(Node {
-                    edges: vec![(8, None)],
+                    edges: vec![(NodeIndex(8), None)],
                     endlinked: false
                 }), Some(Node {
-                    edges: vec![(0, None)],
+                    edges: vec![(NodeIndex(0), None)],
                     endlinked: true
                 })
                 
             ],
-            start: 0,
-            active: 8
+            start: NodeIndex(0),
+            active: NodeIndex(8),
+            accept: vec![],
+            patterns: vec![],
+            free: vec![]
         };
 
         assert_eq!(goal, graph);
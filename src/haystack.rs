@@ -0,0 +1,272 @@
+pub mod haystack {
+    use std::ffi::OsStr;
+    use std::path::Path;
+
+    // text the matchers can read without it having to be one contiguous str, like the pieces of an
+    // editor's piece table. Offsets are always bytes. A byte that isn't part of valid UTF-8 reads as
+    // no char at all, so nothing matches across it.
+    pub trait Haystack {
+        fn len(&self) -> usize;
+
+        // the bytes from offset up to the end of whichever piece holds offset, empty at the end
+        fn chunk_at(&self, offset: usize) -> &[u8];
+
+        // the bytes from the start of whichever piece holds offset - 1 up to offset, empty at 0
+        fn chunk_before(&self, offset: usize) -> &[u8];
+
+        fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+
+        // the char starting at offset and how many bytes it takes
+        fn char_at(&self, offset: usize) -> Option<(char, usize)> {
+            decode(self.chunk_at(offset))
+        }
+
+        fn char_before(&self, offset: usize) -> Option<char> {
+            let chunk = self.chunk_before(offset);
+            let start = (chunk.len().saturating_sub(4)..chunk.len()).rev().find(|a| chunk[*a] & 0xC0 != 0x80)?;
+            decode(&chunk[start..]).filter(|(_, length)| start + length == chunk.len()).map(|(a, _)| a)
+        }
+
+        fn starts_with_at(&self, mut offset: usize, text: &str) -> bool {
+            let mut text = text.as_bytes();
+            while !text.is_empty() {
+                let chunk = self.chunk_at(offset);
+                let length = chunk.len().min(text.len());
+                if length == 0 || chunk[..length] != text[..length] {
+                    return false;
+                }
+                text = &text[length..];
+                offset += length;
+            }
+            true
+        }
+
+        // the whole haystack as one str, when it's held as one, so a search can look ahead
+        // through it with str's own substring search
+        fn as_str(&self) -> Option<&str> {
+            None
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Option<(char, usize)> {
+        (1..=bytes.len().min(4)).find_map(|a| std::str::from_utf8(&bytes[..a]).ok().map(|x| (x.chars().next().unwrap(), a)))
+    }
+
+    impl Haystack for str {
+        fn len(&self) -> usize {
+            self.len()
+        }
+
+        fn chunk_at(&self, offset: usize) -> &[u8] {
+            &self.as_bytes()[offset..]
+        }
+
+        fn chunk_before(&self, offset: usize) -> &[u8] {
+            &self.as_bytes()[..offset]
+        }
+
+        fn char_at(&self, offset: usize) -> Option<(char, usize)> {
+            self[offset..].chars().next().map(|a| (a, a.len_utf8()))
+        }
+
+        fn char_before(&self, offset: usize) -> Option<char> {
+            self[..offset].chars().next_back()
+        }
+
+        fn starts_with_at(&self, offset: usize, text: &str) -> bool {
+            self[offset..].starts_with(text)
+        }
+
+        fn as_str(&self) -> Option<&str> {
+            Some(self)
+        }
+    }
+
+    impl Haystack for [u8] {
+        fn len(&self) -> usize {
+            self.len()
+        }
+
+        fn chunk_at(&self, offset: usize) -> &[u8] {
+            &self[offset..]
+        }
+
+        fn chunk_before(&self, offset: usize) -> &[u8] {
+            &self[..offset]
+        }
+    }
+
+    // file names as the OS gives them, with no lossy conversion: raw bytes on Unix and WTF-8 on
+    // Windows, where an unpaired surrogate, like an invalid byte, is no char at all. Offsets are
+    // into those bytes.
+    impl Haystack for OsStr {
+        fn len(&self) -> usize {
+            self.as_encoded_bytes().len()
+        }
+
+        fn chunk_at(&self, offset: usize) -> &[u8] {
+            &self.as_encoded_bytes()[offset..]
+        }
+
+        fn chunk_before(&self, offset: usize) -> &[u8] {
+            &self.as_encoded_bytes()[..offset]
+        }
+    }
+
+    impl Haystack for Path {
+        fn len(&self) -> usize {
+            self.as_os_str().len()
+        }
+
+        fn chunk_at(&self, offset: usize) -> &[u8] {
+            self.as_os_str().chunk_at(offset)
+        }
+
+        fn chunk_before(&self, offset: usize) -> &[u8] {
+            self.as_os_str().chunk_before(offset)
+        }
+    }
+
+    // a haystack made of several strs read one after another without copying them together
+    #[derive(Debug, Clone, Default)]
+    pub struct Chunked<'a> {
+        chunks: Vec<&'a str>,
+        // where each chunk ends in the whole
+        ends: Vec<usize>
+    }
+
+    impl<'a> Chunked<'a> {
+        pub fn new(chunks: impl IntoIterator<Item = &'a str>) -> Self {
+            let mut chunked = Chunked::default();
+            for chunk in chunks.into_iter().filter(|a| !a.is_empty()) {
+                chunked.ends.push(chunked.len() + chunk.len());
+                chunked.chunks.push(chunk);
+            }
+            chunked
+        }
+
+        // which chunk holds offset, and where it starts
+        fn locate(&self, offset: usize) -> Option<(&'a str, usize)> {
+            let index = self.ends.partition_point(|a| *a <= offset);
+            let chunk = self.chunks.get(index)?;
+            Some((chunk, self.ends[index] - chunk.len()))
+        }
+    }
+
+    impl Haystack for Chunked<'_> {
+        fn len(&self) -> usize {
+            self.ends.last().copied().unwrap_or(0)
+        }
+
+        fn chunk_at(&self, offset: usize) -> &[u8] {
+            match self.locate(offset) {
+                Some((chunk, start)) => &chunk.as_bytes()[offset - start..],
+                None => &[]
+            }
+        }
+
+        fn chunk_before(&self, offset: usize) -> &[u8] {
+            match offset.checked_sub(1).and_then(|a| self.locate(a)) {
+                Some((chunk, start)) => &chunk.as_bytes()[..offset - start],
+                None => &[]
+            }
+        }
+    }
+
+    // a str read from its end back to its start, so an automaton built for the reversed pattern
+    // can run over it. Offset n here is offset len - n in the str, and each char is its own piece,
+    // its bytes still in their usual order.
+    #[derive(Debug, Clone, Copy)]
+    pub struct Reversed<'a> {
+        text: &'a str
+    }
+
+    impl<'a> Reversed<'a> {
+        pub fn new(text: &'a str) -> Self {
+            Reversed { text }
+        }
+
+        // the bytes in text of the char holding offset
+        fn piece(&self, offset: usize) -> Option<(usize, usize)> {
+            let end = self.text.len().checked_sub(offset).filter(|a| *a > 0)?;
+            let start = (0..end).rev().find(|a| self.text.is_char_boundary(*a))?;
+            let end = (end..=self.text.len()).find(|a| self.text.is_char_boundary(*a))?;
+            Some((start, end))
+        }
+    }
+
+    impl Haystack for Reversed<'_> {
+        fn len(&self) -> usize {
+            self.text.len()
+        }
+
+        fn chunk_at(&self, offset: usize) -> &[u8] {
+            match self.piece(offset) {
+                Some((start, end)) => &self.text.as_bytes()[start + offset - (self.len() - end)..end],
+                None => &[]
+            }
+        }
+
+        fn chunk_before(&self, offset: usize) -> &[u8] {
+            match offset.checked_sub(1).and_then(|a| self.piece(a)) {
+                Some((start, end)) => &self.text.as_bytes()[start..start + offset - (self.len() - end)],
+                None => &[]
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_chunked() {
+            let chunked = Chunked::new(["ab", "", "cé", "d"]);
+            assert_eq!(chunked.len(), 6);
+            assert_eq!(chunked.chunk_at(1), b"b");
+            assert_eq!(chunked.chunk_at(2), "cé".as_bytes());
+            assert_eq!(chunked.chunk_before(2), b"ab");
+            assert_eq!(chunked.char_at(3), Some(('é', 2)));
+            assert_eq!(chunked.char_before(5), Some('é'));
+            assert_eq!(chunked.char_at(6), None);
+            assert!(chunked.starts_with_at(1, "bcé") && !chunked.starts_with_at(1, "bcéde"));
+        }
+
+        #[test]
+        fn test_os_str() {
+            let regex = crate::regex::regex::Regex::new("[a-z]+\\.rs$").unwrap();
+            assert_eq!(regex.find_in(Path::new("src/lib.rs")), Some(4..10));
+            #[cfg(unix)]
+            {
+                use std::os::unix::ffi::OsStrExt;
+                // the invalid byte splits the name rather than making the whole of it unreadable
+                let name = OsStr::from_bytes(b"caf\xe9/main.rs");
+                assert_eq!(regex.find_in(name), Some(5..12));
+                assert_eq!(name.char_at(3), None);
+            }
+        }
+
+        #[test]
+        fn test_reversed() {
+            let reversed = Reversed::new("aéb");
+            assert_eq!(reversed.char_at(0), Some(('b', 1)));
+            assert_eq!(reversed.char_at(1), Some(('é', 2)));
+            assert_eq!(reversed.chunk_at(2), &"é".as_bytes()[1..]);
+            assert_eq!(reversed.char_before(3), Some('é'));
+            assert_eq!(reversed.char_at(4), None);
+            assert!(reversed.starts_with_at(1, "éa") && !reversed.starts_with_at(0, "ba"));
+        }
+
+        #[test]
+        fn test_invalid_bytes() {
+            let bytes: &[u8] = b"a\xffb\xc3\xa9";
+            assert_eq!(bytes.char_at(1), None);
+            assert_eq!(bytes.char_at(3), Some(('é', 2)));
+            assert_eq!(bytes.char_before(2), None);
+            assert_eq!(bytes.char_before(5), Some('é'));
+            assert_eq!(bytes.char_before(4), None);
+        }
+    }
+}
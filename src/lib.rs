@@ -0,0 +1,31 @@
+// every module wraps its contents in a same-named inner module
+#![allow(clippy::module_inception)]
+
+pub mod ast;
+pub mod graph;
+pub mod parser;
+pub mod automata;
+pub mod derivative;
+pub mod dfa;
+pub mod regex;
+pub mod util;
+pub mod pikevm;
+pub mod onepass;
+pub mod glob;
+pub mod like;
+pub mod haystack;
+pub mod engine;
+pub mod printer;
+pub mod diagnostic;
+pub mod scanner;
+// runs test corpora, in TOML or the AT&T format, against every engine
+#[cfg(test)]
+mod conformance;
+// the Unicode tables, and everything needing them, can be left out by turning off the default
+// unicode feature, at which point \d, \w, \s and case folding only know about ASCII
+#[cfg(feature = "unicode")]
+pub mod unicode;
+#[cfg(feature = "unicode")]
+pub mod normalize;
+#[cfg(feature = "unicode")]
+pub mod segment;
@@ -0,0 +1,74 @@
+pub mod like {
+    use crate::ast::ast::{Ast, Qualifier};
+    use crate::automata::automata::{epsilon_closures, Closures, longest_match_at};
+    use crate::graph::graph::Program;
+    use crate::parser::parser::{graph_from_ast, CharClass, CharCost, ParseError};
+
+    // an SQL LIKE pattern: % stands for any run of chars, _ for exactly one. With an escape char,
+    // that char followed by %, _ or itself stands for the second of the two literally.
+    #[derive(Debug, Clone)]
+    pub struct Like {
+        program: Program<CharCost>,
+        closures: Closures
+    }
+
+    impl Like {
+        pub fn new(pattern: &str, escape: Option<char>) -> Result<Self, ParseError> {
+            let program = graph_from_ast(translate(pattern, escape)?).compile().freeze();
+            let closures = epsilon_closures(&program);
+            Ok(Like { program, closures })
+        }
+
+        // LIKE is always about the whole value
+        pub fn is_match(&self, value: &str) -> bool {
+            longest_match_at(&self.program, &self.closures, value, 0) == Some(value.len())
+        }
+    }
+
+    fn translate(pattern: &str, escape: Option<char>) -> Result<Ast, ParseError> {
+        let any = Ast::Cost(CharCost::Class(CharClass::new().complement()));
+        let mut items = vec![];
+        let mut chars = pattern.chars();
+        while let Some(letter) = chars.next() {
+            let item = match letter {
+                a if Some(a) == escape => match chars.next() {
+                    Some(next) if next == '%' || next == '_' || Some(next) == escape => Ast::Cost(CharCost::Singleton(next)),
+                    // escaping anything else, or nothing at all, is an error in SQL too
+                    Some(next) => return Err(ParseError::UnsupportedEscape(next)),
+                    None => return Err(ParseError::UnsupportedEscape(a))
+                },
+                '%' => Ast::Repeat(Box::new(any.clone()), Qualifier::ZeroOrMore),
+                '_' => any.clone(),
+                a => Ast::Cost(CharCost::Singleton(a))
+            };
+            items.push(item);
+        }
+        Ok(Ast::Concat(items))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_like() {
+            let like = Like::new("a%b_", None).unwrap();
+            for value in ["abc", "a\nxyzbé", "abbb"] {
+                assert!(like.is_match(value), "{:?}", value);
+            }
+            assert!(!like.is_match("ab") && !like.is_match("xabc") && !like.is_match("abcd"));
+            // unlike a regex, nothing but % and _ is special
+            assert!(Like::new("[a].*", None).unwrap().is_match("[a].*"));
+        }
+
+        #[test]
+        fn test_escape() {
+            let like = Like::new("100!%%", Some('!')).unwrap();
+            assert!(like.is_match("100%") && like.is_match("100% sure") && !like.is_match("1000"));
+            let like = Like::new("a!!_", Some('!')).unwrap();
+            assert!(like.is_match("a!x") && !like.is_match("a!"));
+            assert_eq!(Like::new("a!b", Some('!')).unwrap_err(), ParseError::UnsupportedEscape('b'));
+            assert_eq!(Like::new("a!", Some('!')).unwrap_err(), ParseError::UnsupportedEscape('!'));
+        }
+    }
+}
@@ -1,7 +1,3 @@
-mod graph;
-mod parser;
-mod automata;
-
 fn main() {
 
-}
\ No newline at end of file
+}
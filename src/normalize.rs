@@ -0,0 +1,51 @@
+pub mod normalize {
+    use crate::unicode::unicode::CANONICAL_DECOMPOSITION;
+
+    // what NFD splits letter into, if anything
+    pub fn decomposition(letter: char) -> Option<&'static str> {
+        let found = CANONICAL_DECOMPOSITION.binary_search_by_key(&(letter as u32), |a| a.0);
+        found.ok().map(|a| CANONICAL_DECOMPOSITION[a].1)
+    }
+
+    // neither of these puts combining marks into canonical order, so both assume text already has
+    // them the way they're usually written
+    pub fn nfd(text: &str) -> String {
+        text.chars().map(|a| decomposition(a).map_or(a.to_string(), str::to_string)).collect()
+    }
+
+    pub fn nfc(text: &str) -> String {
+        let text = nfd(text);
+        let mut composed = String::new();
+        let mut rest = text.as_str();
+        while let Some(first) = rest.chars().next() {
+            let longest = CANONICAL_DECOMPOSITION.iter().filter(|(_, a)| rest.starts_with(a)).max_by_key(|(_, a)| a.len());
+            match longest {
+                Some((letter, decomposed)) => {
+                    composed.push(char::from_u32(*letter).unwrap());
+                    rest = &rest[decomposed.len()..];
+                }
+                None => {
+                    composed.push(first);
+                    rest = &rest[first.len_utf8()..];
+                }
+            }
+        }
+        composed
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_normal_forms() {
+            assert_eq!(nfd("café"), "cafe\u{301}");
+            assert_eq!(nfc("cafe\u{301}"), "café");
+            // ǘ is u, a diaeresis and an acute, and composes all the way back up
+            assert_eq!(nfd("ǘ"), "u\u{308}\u{301}");
+            assert_eq!(nfc("u\u{308}\u{301}"), "ǘ");
+            assert_eq!(nfc("ü\u{301}"), "ǘ");
+            assert_eq!(nfc("plain"), "plain");
+        }
+    }
+}
@@ -0,0 +1,160 @@
+pub mod onepass {
+    use std::cmp::Ordering;
+
+    use crate::pikevm::pikevm::{Inst, PikeVm};
+    use crate::util::util::SparseSet;
+
+    // a state sits right before reading a char, at the start of the program or just past a Cost
+    struct State {
+        // sorted and disjoint (start, end exclusive, move) intervals of codepoints
+        ranges: Vec<(u32, u32, usize)>,
+        // the slots to save before reading the char, and the state after it
+        moves: Vec<(Vec<usize>, usize)>,
+        // the slots to save if the match ends here
+        matched: Option<Vec<usize>>
+    }
+
+    // when at most one path through the program can ever be followed, captures need no thread list
+    // at all: each char picks out exactly one move, saves its slots and carries on. Most patterns
+    // written for extraction, like ([a-z]+)@([a-z]+), are like this.
+    pub struct OnePass {
+        states: Vec<State>,
+        slot_count: usize
+    }
+
+    impl OnePass {
+        // None unless the program is one-pass: from every state, no two epsilon paths reach the same
+        // instruction, at most one reaches Match, and the Costs reached never share a char
+        pub fn new(pikevm: &PikeVm) -> Option<Self> {
+            let insts = &pikevm.insts;
+            // a look would need the haystack to decide on a move, and a count the counts so far,
+            // neither of which states here have
+            if insts.iter().any(|a| matches!(a, Inst::Cost(cost, _) if cost.reads_nothing())) {
+                return None;
+            }
+            let mut ids = vec![None; insts.len()];
+            ids[0] = Some(0);
+            let mut queue = vec![0];
+            let mut count = 1;
+            let mut states = vec![];
+            let mut seen = SparseSet::new(insts.len());
+            while let Some(index) = queue.pop() {
+                let mut reached = vec![];
+                seen.clear();
+                if !walk(insts, index, vec![], &mut seen, &mut reached) {
+                    return None;
+                }
+
+                let mut state = State { ranges: vec![], moves: vec![], matched: None };
+                for (target, saves) in reached {
+                    match &insts[target] {
+                        Inst::Match if state.matched.is_some() => return None,
+                        Inst::Match => state.matched = Some(saves),
+                        Inst::Cost(cost, next) => {
+                            let id = *ids[*next].get_or_insert_with(|| {
+                                queue.push(*next);
+                                count += 1;
+                                count - 1
+                            });
+                            state.ranges.extend(cost.intervals().into_iter().map(|(start, end)| (start, end, state.moves.len())));
+                            state.moves.push((saves, id));
+                        }
+                        _ => unreachable!()
+                    }
+                }
+                state.ranges.sort();
+                if state.ranges.windows(2).any(|a| a[0].1 > a[1].0) {
+                    return None;
+                }
+                let id = ids[index].unwrap();
+                if states.len() <= id {
+                    states.resize_with(id + 1, || None);
+                }
+                states[id] = Some(state);
+            }
+            Some(OnePass {
+                states: states.into_iter().map(|a| a.unwrap()).collect(),
+                slot_count: pikevm.slot_count
+            })
+        }
+
+        // same contract as PikeVm::captures_at
+        pub fn captures_at(&self, haystack: &str, start: usize, end: usize) -> Option<Vec<Option<usize>>> {
+            let mut slots = vec![None; self.slot_count];
+            let mut state = &self.states[0];
+            for (offset, letter) in haystack[start..end].char_indices() {
+                let letter = letter as u32;
+                let found = state.ranges.binary_search_by(|(low, high, _)| {
+                    if *high <= letter {
+                        Ordering::Less
+                    } else if *low > letter {
+                        Ordering::Greater
+                    } else {
+                        Ordering::Equal
+                    }
+                });
+                let (saves, next) = &state.moves[state.ranges[found.ok()?].2];
+                for slot in saves {
+                    slots[*slot] = Some(start + offset);
+                }
+                state = &self.states[*next];
+            }
+            for slot in state.matched.as_ref()? {
+                slots[*slot] = Some(end);
+            }
+            Some(slots)
+        }
+    }
+
+    // follows every epsilon path out of index, collecting the Cost and Match instructions it ends at
+    // along with the slots saved on the way. False if two paths meet.
+    fn walk(insts: &[Inst], index: usize, mut saves: Vec<usize>, seen: &mut SparseSet, reached: &mut Vec<(usize, Vec<usize>)>) -> bool {
+        if !seen.insert(index) {
+            return false;
+        }
+        match &insts[index] {
+            Inst::Jump(next) => walk(insts, *next, saves, seen, reached),
+            Inst::Split(first, second) => walk(insts, *first, saves.clone(), seen, reached) && walk(insts, *second, saves, seen, reached),
+            Inst::Save(slot, next) => {
+                saves.push(*slot);
+                walk(insts, *next, saves, seen, reached)
+            }
+            Inst::Cost(_, _) | Inst::Match => {
+                reached.push((index, saves));
+                true
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::parser::parser::parse_ast;
+
+        fn compile(pattern: &str) -> (PikeVm, Option<OnePass>) {
+            let pikevm = PikeVm::new(&parse_ast(pattern).unwrap());
+            let onepass = OnePass::new(&pikevm);
+            (pikevm, onepass)
+        }
+
+        #[test]
+        fn test_detects_one_pass() {
+            for pattern in ["([a-z]+)@([a-z]+)", "(a)(b)?c", "(a|b)*c", "x(y|z)", ""] {
+                assert!(compile(pattern).1.is_some(), "{}", pattern);
+            }
+            for pattern in ["(a*)(a*)", "(a|ab)c", "a*a", "(a|a)", "(|a)*"] {
+                assert!(compile(pattern).1.is_none(), "{}", pattern);
+            }
+        }
+
+        #[test]
+        fn test_agrees_with_pikevm() {
+            let cases = [("([a-z]+)@([a-z]+)", "bob@example"), ("(a)(b)?c", "ac"), ("(a)(b)?c", "abc"), ("((a)|b)*c", "abac"), ("x(y|z)", "xq")];
+            for (pattern, haystack) in cases {
+                let (pikevm, onepass) = compile(pattern);
+                let expected = pikevm.captures_at(haystack, 0, haystack.len());
+                assert_eq!(onepass.unwrap().captures_at(haystack, 0, haystack.len()), expected, "{} on {}", pattern, haystack);
+            }
+        }
+    }
+}
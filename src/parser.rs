@@ -1,28 +1,335 @@
 pub mod parser {
-    use std::{fmt::Error, ops::Range};
+    use std::{cmp::Ordering, fmt, iter::{Map, Peekable}, ops::Range, str::CharIndices, sync::Arc, vec::IntoIter};
+    #[cfg(feature = "unicode")]
+    use std::sync::OnceLock;
 
+    use crate::ast::ast::{Ast, Qualifier};
     use crate::graph::graph::{Graph, NodeIndex};
+    use crate::haystack::haystack::Haystack;
+    #[cfg(feature = "unicode")]
+    use crate::segment::segment::is_word_break;
+    #[cfg(feature = "unicode")]
+    use crate::unicode::unicode::{
+        Script, ALPHABETIC, BLOCKS, CASE_FOLDING, DECIMAL_NUMBER, GENERAL_CATEGORIES, HANGUL_LEADING, HANGUL_TRAILING, HANGUL_VOWEL,
+        LOWERCASE, NAMED, NAMES, NUMBERED, SCRIPTS, SCRIPT_EXTENSIONS, UPPERCASE, WHITE_SPACE, WORD_CHARACTER
+    };
     
     #[derive(Debug, Clone, PartialEq, Eq)]
     pub struct CharClass {
-        chars: Vec<char>,
-        ranges: Vec<Range<char>>
+        // inclusive codepoint intervals, always sorted with no two overlapping or touching, so
+        // membership is a binary search however big the class is
+        ranges: Vec<(u32, u32)>,
+        // matches everything ranges don't. The members stay as they are, so a negated class is no
+        // bigger than the plain one.
+        negated: bool
     }
 
-    #[derive(PartialEq, Eq, Debug)]
+    // a test for chars that no written class puts well, like membership of some table of the
+    // caller's, for patterns built up in code. Two are only equal if one's a clone of the other.
+    #[derive(Clone)]
+    pub struct Predicate(Arc<dyn Fn(char) -> bool + Send + Sync>);
+
+    impl Predicate {
+        pub fn new(test: impl Fn(char) -> bool + Send + Sync + 'static) -> Self {
+            Predicate(Arc::new(test))
+        }
+
+        pub fn matches(&self, letter: char) -> bool {
+            (self.0)(letter)
+        }
+
+        // every char it passes, found by trying them all, for whatever needs ranges rather than a
+        // test: the DFAs, case folding and writing the pattern out
+        pub fn class(&self) -> CharClass {
+            CharClass::from_predicate(|a| self.matches(a))
+        }
+    }
+
+    impl PartialEq for Predicate {
+        fn eq(&self, other: &Self) -> bool {
+            Arc::ptr_eq(&self.0, &other.0)
+        }
+    }
+
+    impl Eq for Predicate {}
+
+    impl fmt::Debug for Predicate {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Predicate")
+        }
+    }
+
+    #[derive(PartialEq, Eq, Debug, Clone)]
     pub enum CharCost {
         Singleton(char),
         Dot,
-        Class(CharClass)
+        Class(CharClass),
+        Predicate(Predicate),
+        // a fused run of singletons, see Graph::compile
+        Literal(String),
+        // reads nothing, only checks where it is, see Look::holds
+        Look(Look),
+        // reads nothing either, only keeps count for a counted repeat, see Count
+        Count(Count)
+    }
+
+    // one of the edges a counted repeat like x{2,5} is built with: Enter goes into the body for
+    // the first time, Again goes round it once more and Leave carries on past it, each only when
+    // the count of times round so far allows. That keeps the automaton the same size whatever the
+    // bounds, where writing the body out once for every time round would not.
+    #[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+    pub struct Count {
+        pub step: Step,
+        pub min: u32,
+        pub max: Option<u32>
+    }
+
+    #[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+    pub enum Step {
+        Enter,
+        Again,
+        Leave
+    }
+
+    #[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+    pub enum Look {
+        // ^ and $ normally
+        Start,
+        End,
+        // ^ and $ in multi line mode, where a line ends at the ASCII byte given, normally \n
+        StartLine(u8),
+        EndLine(u8),
+        // the same, but a line can also end at a \r or \r\n, and never between the two of those
+        StartLineCrlf,
+        EndLineCrlf,
+        // \b and \B, where a word char is one \w matches
+        WordBoundary,
+        NotWordBoundary,
+        // the same with only ASCII letters, digits and _ as word chars
+        WordBoundaryAscii,
+        NotWordBoundaryAscii,
+        // \b{wb} and \B{wb}, where the text splits into words as UAX #29 segments it
+        UnicodeWordBoundary,
+        NotUnicodeWordBoundary
+    }
+
+    impl Look {
+        pub fn holds<H: Haystack + ?Sized>(self, code: &H, offset: usize) -> bool {
+            let before = code.char_before(offset);
+            let after = code.char_at(offset).map(|(a, _)| a);
+            match self {
+                Look::Start => offset == 0,
+                Look::End => offset == code.len(),
+                Look::StartLine(terminator) => before.is_none_or(|a| a == terminator as char),
+                Look::EndLine(terminator) => after.is_none_or(|a| a == terminator as char),
+                Look::StartLineCrlf => match before {
+                    None | Some('\n') => true,
+                    Some('\r') => after != Some('\n'),
+                    _ => false
+                },
+                Look::EndLineCrlf => match after {
+                    None | Some('\r') => true,
+                    Some('\n') => before != Some('\r'),
+                    _ => false
+                },
+                Look::WordBoundary => before.is_some_and(is_word) != after.is_some_and(is_word),
+                Look::NotWordBoundary => before.is_some_and(is_word) == after.is_some_and(is_word),
+                Look::WordBoundaryAscii => before.is_some_and(is_ascii_word) != after.is_some_and(is_ascii_word),
+                Look::NotWordBoundaryAscii => before.is_some_and(is_ascii_word) == after.is_some_and(is_ascii_word),
+                #[cfg(feature = "unicode")]
+                Look::UnicodeWordBoundary => is_word_break(code, offset),
+                #[cfg(feature = "unicode")]
+                Look::NotUnicodeWordBoundary => !is_word_break(code, offset),
+                // only the unicode feature lets these be parsed
+                #[cfg(not(feature = "unicode"))]
+                Look::UnicodeWordBoundary | Look::NotUnicodeWordBoundary => unreachable!()
+            }
+        }
+
+        // the same check seen from the other end of the haystack
+        pub fn reversed(self) -> Self {
+            match self {
+                Look::Start => Look::End,
+                Look::End => Look::Start,
+                Look::StartLine(terminator) => Look::EndLine(terminator),
+                Look::EndLine(terminator) => Look::StartLine(terminator),
+                Look::StartLineCrlf => Look::EndLineCrlf,
+                Look::EndLineCrlf => Look::StartLineCrlf,
+                look => look
+            }
+        }
+
+        // the line anchor standing in for this one in multi line mode
+        pub fn multi_line(self, crlf: bool) -> Self {
+            match (self, crlf) {
+                (Look::Start, false) => Look::StartLine(b'\n'),
+                (Look::End, false) => Look::EndLine(b'\n'),
+                (Look::Start, true) => Look::StartLineCrlf,
+                (Look::End, true) => Look::EndLineCrlf,
+                (look, _) => look
+            }
+        }
+    }
+
+    impl Count {
+        // how many values a path's count for this repeat can take: which time round the body it's
+        // on, from 0 up to the last one max allows, or with no max up to the one min asks for,
+        // where it then stays
+        fn radix(&self) -> usize {
+            self.max.unwrap_or(self.min.max(1)) as usize
+        }
+
+        // the counts of every repeat a path is inside, read as one number with the innermost
+        // repeat's count as its lowest digit, once the path has crossed this edge, or None if the
+        // count so far doesn't let it
+        pub fn counts_after(&self, counts: usize) -> Option<usize> {
+            let radix = self.radix();
+            let count = counts % radix;
+            match self.step {
+                Step::Enter => Some(counts * radix),
+                Step::Again if self.max.is_some() => (count + 1 < radix).then_some(counts + 1),
+                Step::Again => Some(counts - count + (count + 1).min(radix - 1)),
+                Step::Leave => (count + 1 >= self.min as usize).then_some(counts / radix)
+            }
+        }
+
+        // how many different counts a path can have past this edge, given how many it could have before
+        pub fn size_after(&self, size: usize) -> usize {
+            match self.step {
+                Step::Enter => size.saturating_mul(self.radix()),
+                Step::Again => size,
+                Step::Leave => size / self.radix()
+            }
+        }
+
+        // the same edge for the reversed automaton, which goes into the body where it used to leave
+        pub fn reversed(self) -> Self {
+            let step = match self.step {
+                Step::Enter => Step::Leave,
+                Step::Again => Step::Again,
+                Step::Leave => Step::Enter
+            };
+            Count { step, ..self }
+        }
+    }
+
+    // which syntax a pattern is written in
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub enum Dialect {
+        #[default]
+        Default,
+        // POSIX basic syntax as sed and grep use it: groups are \( \), only * repeats and ^ and $
+        // are only anchors at the ends, with GNU's \| \+ and \? on top
+        PosixBasic,
+        // POSIX extended syntax as in grep -E, without any of the backslash classes
+        PosixExtended
+    }
+
+    // what a pattern is allowed to use, so hosts taking patterns from users can hold them to a
+    // safe subset. Everything but duplicate names is on by default.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub struct SyntaxConfig {
+        pub dialect: Dialect,
+        // \d, \w, \s and \p{...}, along with their negations
+        pub builtins: bool,
+        // ^, $, \b and \B
+        pub looks: bool,
+        // one name for more than one group, as PCRE's J flag allows, so long as no two of them
+        // can both take part in a match, like (?<n>a)|(?<n>b)
+        pub duplicate_names: bool,
+        // \w, \W, \b and \B only count ASCII letters, digits and _ as word chars, so the ï in
+        // naïve splits it in two. Without the unicode feature they do anyway.
+        pub ascii_words: bool,
+        // the biggest bound a counted repetition can have, so a{1,100000000} is turned away before
+        // it gets anywhere near the simulator, which keeps a state for every count
+        pub max_repeat: u32
+    }
+
+    impl Default for SyntaxConfig {
+        fn default() -> Self {
+            SyntaxConfig {
+                dialect: Dialect::Default,
+                builtins: true,
+                looks: true,
+                duplicate_names: false,
+                ascii_words: false,
+                // as RE2 has it
+                max_repeat: 1000
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum ParseError {
+        // a range running backwards, like z-a
+        ReversedRange(char, char),
+        // a '-' in a class with nothing to make a range with on one side
+        DanglingDash,
+        UnclosedClass,
+        UnclosedGroup,
+        UnmatchedParen,
+        // a backslash before a letter that doesn't mean anything there
+        UnsupportedEscape(char),
+        // a \p{...} naming no property we know
+        UnknownProperty(String),
+        // something that can't start an atom, like the * in *a
+        Unexpected(char),
+        // syntax the SyntaxConfig turned off, as it was written
+        Disabled(String),
+        // a group name that's empty or has something other than letters, digits and _ in it
+        InvalidGroupName(String),
+        DuplicateGroupName(String),
+        // a \N{...} naming no char
+        UnknownCharName(String),
+        // a \b{...} naming no kind of boundary
+        UnknownBoundary(String),
+        // a \ at the very end, with nothing left for it to escape
+        TrailingBackslash,
+        // a counted repetition like {5,2} whose bounds are the wrong way round
+        ReversedRepeat(u32, u32),
+        // a counted repetition with a bound bigger than the one given
+        RepeatTooLarge(u32)
     }
 
+    impl fmt::Display for ParseError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                ParseError::ReversedRange(start, end) => write!(f, "invalid range {}-{}: {} comes after {}", start, end, start, end),
+                ParseError::DanglingDash => write!(f, "'-' in a class needs a char on both sides"),
+                ParseError::UnclosedClass => write!(f, "unclosed character class"),
+                ParseError::UnclosedGroup => write!(f, "unclosed group"),
+                ParseError::UnmatchedParen => write!(f, "unmatched ')'"),
+                ParseError::UnsupportedEscape(a) => write!(f, "unsupported escape \\{}", a),
+                ParseError::UnknownProperty(a) => write!(f, "unknown property {}", a),
+                ParseError::Unexpected(a) => write!(f, "unexpected '{}'", a),
+                ParseError::Disabled(a) => write!(f, "{} is not allowed here", a),
+                ParseError::InvalidGroupName(a) => write!(f, "invalid group name '{}'", a),
+                ParseError::DuplicateGroupName(a) => write!(f, "more than one group is called '{}'", a),
+                ParseError::UnknownCharName(a) => write!(f, "no char is called {}", a),
+                ParseError::UnknownBoundary(a) => write!(f, "unknown boundary \\b{{{}}}", a),
+                ParseError::TrailingBackslash => write!(f, "trailing backslash with nothing to escape"),
+                ParseError::ReversedRepeat(min, max) => write!(f, "invalid repetition {{{},{}}}: {} is more than {}", min, max, min, max),
+                ParseError::RepeatTooLarge(a) => write!(f, "counted repetition over the limit of {}", a)
+            }
+        }
+    }
+
+    impl std::error::Error for ParseError {}
+
     #[derive(PartialEq, Eq, Debug, Clone, Copy)]
-    enum Lexeme {
+    pub enum Lexeme {
         Literal(char),
         OpenParen, CloseParen,
         OpenBracket, CloseBracket,
         Star, Question, Plus, Dot, Bar,
-        Builtin(char), Range(char, char)
+        Caret, Dollar,
+        Builtin(char), Range(char, char),
+        // && and -- between the items of a class
+        Intersect, Subtract,
+        // {n}, {n,} and {n,m}, with no upper bound for {n,}
+        Counted(u32, Option<u32>),
+        // (?#...), which parsing skips
+        Comment
     }
 
     impl Lexeme {
@@ -37,6 +344,8 @@ pub mod parser {
                 '+' => Lexeme::Plus,
                 '.' => Lexeme::Dot,
                 '|' => Lexeme::Bar,
+                '^' => Lexeme::Caret,
+                '$' => Lexeme::Dollar,
                 a => Lexeme::Literal(a)
             }
         }
@@ -54,193 +363,1259 @@ pub mod parser {
                 Lexeme::Plus => '+',
                 Lexeme::Question => '?',
                 Lexeme::Star => '*',
-                Lexeme::Range(_, _) => '-'
+                Lexeme::Caret => '^',
+                Lexeme::Dollar => '$',
+                Lexeme::Range(_, _) => '-',
+                Lexeme::Intersect => '&',
+                Lexeme::Subtract => '-',
+                Lexeme::Counted(_, _) => '{',
+                Lexeme::Comment => '#'
             }
         }
-
-        fn set(&mut self, new_lex: Lexeme) {
-            *self = new_lex;
-        }
     }
 
     impl CharCost {
         fn fromchar(singleton: char) -> Self {
             CharCost::Singleton(singleton)
         }
+
+        pub fn matches(&self, letter: char) -> bool {
+            match self {
+                CharCost::Singleton(a) => *a == letter,
+                CharCost::Dot => letter != '\n',
+                CharCost::Class(class) => class.is_in(letter),
+                CharCost::Predicate(predicate) => predicate.matches(letter),
+                // only meaningful when the literal is a single char, multi-char ones go through consumes
+                CharCost::Literal(a) => a.chars().eq([letter]),
+                CharCost::Look(_) | CharCost::Count(_) => false
+            }
+        }
+
+        // every (start, end) pair of codepoints, end exclusive, this cost can match. For a literal
+        // that's just its first char.
+        pub fn intervals(&self) -> Vec<(u32, u32)> {
+            match self {
+                CharCost::Singleton(a) => vec![(*a as u32, *a as u32 + 1)],
+                CharCost::Dot => vec![(0, '\n' as u32), ('\n' as u32 + 1, char::MAX as u32 + 1)],
+                CharCost::Class(class) => class.intervals(),
+                CharCost::Predicate(predicate) => predicate.class().intervals(),
+                CharCost::Literal(a) => a.chars().take(1).map(|x| (x as u32, x as u32 + 1)).collect(),
+                CharCost::Look(_) | CharCost::Count(_) => vec![]
+            }
+        }
+
+        // the cost matching the same strings backwards
+        pub fn reversed(self) -> Self {
+            match self {
+                CharCost::Literal(a) => CharCost::Literal(a.chars().rev().collect()),
+                CharCost::Look(look) => CharCost::Look(look.reversed()),
+                CharCost::Count(count) => CharCost::Count(count.reversed()),
+                cost => cost
+            }
+        }
+
+        // the cost matching the same chars in any case. A literal has no single cost that does
+        // that, see Ast::case_insensitive.
+        pub fn case_folded(self) -> Self {
+            match self {
+                CharCost::Singleton(a) => match case_variants(a).as_slice() {
+                    [_] => CharCost::Singleton(a),
+                    variants => {
+                        let mut class = CharClass::new();
+                        for variant in variants {
+                            class.plus_literal(*variant);
+                        }
+                        CharCost::Class(class)
+                    }
+                },
+                CharCost::Class(class) => CharCost::Class(class.case_folded()),
+                CharCost::Predicate(predicate) => CharCost::Class(predicate.class().case_folded()),
+                cost => cost
+            }
+        }
+
+        // the same, with only ASCII letters given their other case
+        pub fn ascii_case_folded(self) -> Self {
+            match self {
+                CharCost::Singleton(a) if a.is_ascii_alphabetic() => {
+                    let mut class = CharClass::new();
+                    class.plus_literal(a);
+                    class.plus_literal((a as u8 ^ 0x20) as char);
+                    CharCost::Class(class)
+                }
+                CharCost::Class(class) => CharCost::Class(class.ascii_case_folded()),
+                CharCost::Predicate(predicate) => CharCost::Class(predicate.class().ascii_case_folded()),
+                cost => cost
+            }
+        }
+
+        pub fn is_look(&self) -> bool {
+            matches!(self, CharCost::Look(_))
+        }
+
+        pub fn is_count(&self) -> bool {
+            matches!(self, CharCost::Count(_))
+        }
+
+        // a look or a count, which a path crosses without reading anything, when it can cross at all
+        pub fn reads_nothing(&self) -> bool {
+            self.is_look() || self.is_count()
+        }
+
+        // how many bytes from offset on this cost eats, if it matches there at all. A look or a
+        // count never does, see Look::holds and Count::counts_after instead.
+        pub fn consumes<H: Haystack + ?Sized>(&self, code: &H, offset: usize) -> Option<usize> {
+            match self {
+                CharCost::Literal(a) => code.starts_with_at(offset, a).then_some(a.len()),
+                cost => code.char_at(offset).filter(|(a, _)| cost.matches(*a)).map(|(_, length)| length)
+            }
+        }
     }
 
     impl CharClass {
         fn is_in(&self, letter: char) -> bool {
-            self.chars.contains(&letter) || self.ranges.iter().fold(false, |a,x| a | x.contains(&letter) )
+            let letter = letter as u32;
+            let found = self.ranges.binary_search_by(|(start, end)| {
+                if *end < letter {
+                    Ordering::Less
+                } else if *start > letter {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            });
+            found.is_ok() != self.negated
+        }
+
+        pub(crate) fn new() -> Self {
+            CharClass {
+                ranges: vec![],
+                negated: false
+            }
+        }
+
+        fn from_table(table: &[(u32, u32)]) -> Self {
+            CharClass {
+                ranges: table.to_vec(),
+                negated: false
+            }
         }
 
-        fn new() -> Self {
+        // every char passing test, found by trying them all, so best kept for things built once
+        fn from_predicate(test: impl Fn(char) -> bool) -> Self {
+            let mut class = CharClass::new();
+            for letter in (0..=char::MAX as u32).filter_map(char::from_u32).filter(|a| test(*a)) {
+                match class.ranges.last_mut() {
+                    Some(last) if last.1 + 1 == letter as u32 => last.1 += 1,
+                    _ => class.ranges.push((letter as u32, letter as u32))
+                }
+            }
+            class
+        }
+
+        pub fn complement(&self) -> CharClass {
             CharClass {
-                chars: vec![],
-                ranges: vec![]
+                negated: !self.negated,
+                ..self.clone()
             }
         }
 
-        fn plus_literal(&mut self, new_char: char) {
-            self.chars.push(new_char);
+        pub fn is_negated(&self) -> bool {
+            self.negated
         }
 
-        fn plus_range(&mut self, start_char: char, end_char: char) {
-            self.ranges.push(Range {start: start_char, end: end_char})
+        pub(crate) fn plus_literal(&mut self, new_char: char) {
+            self.insert(new_char as u32, new_char as u32);
         }
+
+        // both ends included
+        pub(crate) fn plus_range(&mut self, start_char: char, end_char: char) {
+            if start_char <= end_char {
+                self.insert(start_char as u32, end_char as u32);
+            }
+        }
+
+        // swaps every range start..=end overlaps or touches for their union
+        fn insert(&mut self, start: u32, end: u32) {
+            let first = self.ranges.partition_point(|a| a.1 + 1 < start);
+            let last = self.ranges.partition_point(|a| a.0 <= end + 1);
+            let merged = match &self.ranges[first..last] {
+                [] => (start, end),
+                touched => (touched[0].0.min(start), touched[touched.len() - 1].1.max(end))
+            };
+            self.ranges.splice(first..last, [merged]);
+        }
+
+        // puts ranges back in order after pushing to it directly
+        fn normalize(&mut self) {
+            self.ranges = merge(self.members()).into_iter().map(|(start, end)| (start, end - 1)).collect();
+        }
+
+        fn intervals(&self) -> Vec<(u32, u32)> {
+            if self.negated {
+                return gaps(self.members());
+            }
+            self.members()
+        }
+
+        // the members with exclusive ends, ignoring negation
+        fn members(&self) -> Vec<(u32, u32)> {
+            self.ranges.iter().map(|(start, end)| (*start, end + 1)).collect()
+        }
+
+        // the chars in either. Like the other two below, a negated class on either side is dealt
+        // with by turning the operation around rather than by listing out everything it matches.
+        pub fn union(&self, other: &CharClass) -> CharClass {
+            let (ours, theirs) = (self.members(), other.members());
+            match (self.negated, other.negated) {
+                (false, false) => from_intervals(merge([ours, theirs].concat()), false),
+                (true, true) => from_intervals(intersect(&ours, &theirs), true),
+                (false, true) => from_intervals(intersect(&theirs, &gaps(ours)), true),
+                (true, false) => from_intervals(intersect(&ours, &gaps(theirs)), true)
+            }
+        }
+
+        pub fn intersect(&self, other: &CharClass) -> CharClass {
+            let (ours, theirs) = (self.members(), other.members());
+            match (self.negated, other.negated) {
+                (false, false) => from_intervals(intersect(&ours, &theirs), false),
+                (true, true) => from_intervals(merge([ours, theirs].concat()), true),
+                (false, true) => from_intervals(intersect(&ours, &gaps(theirs)), false),
+                (true, false) => from_intervals(intersect(&theirs, &gaps(ours)), false)
+            }
+        }
+
+        pub fn difference(&self, other: &CharClass) -> CharClass {
+            self.intersect(&other.complement())
+        }
+
+        // adds every other case of every member, so a negated class leaves out every case of them
+        pub fn case_folded(&self) -> CharClass {
+            let mut class = self.clone();
+            let members = self.ranges.iter().flat_map(|(start, end)| *start..=*end).filter_map(char::from_u32);
+            for variant in members.flat_map(case_variants) {
+                class.ranges.push((variant as u32, variant as u32));
+            }
+            class.normalize();
+            class
+        }
+
+        // the same, only looking at ASCII letters, so it's quick however big the class is
+        pub fn ascii_case_folded(&self) -> CharClass {
+            let mut class = self.clone();
+            for letter in ('A'..='Z').chain('a'..='z').map(|a| a as u32) {
+                if self.ranges.iter().any(|(start, end)| (*start..=*end).contains(&letter)) {
+                    class.ranges.push((letter ^ 0x20, letter ^ 0x20));
+                }
+            }
+            class.normalize();
+            class
+        }
+
+        pub fn singleton(&self) -> Option<char> {
+            match self.ranges[..] {
+                [(start, end)] if start == end && !self.negated => char::from_u32(start),
+                _ => None
+            }
+        }
+    }
+
+    // sorted and with every overlapping or touching pair joined
+    fn merge(mut intervals: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
+        intervals.sort();
+        let mut merged: Vec<(u32, u32)> = vec![];
+        for (start, end) in intervals.into_iter().filter(|(start, end)| start < end) {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end))
+            }
+        }
+        merged
+    }
+
+    // both sides merged
+    fn intersect(ours: &[(u32, u32)], theirs: &[(u32, u32)]) -> Vec<(u32, u32)> {
+        let (mut i, mut j) = (0, 0);
+        let mut both = vec![];
+        while i < ours.len() && j < theirs.len() {
+            let start = ours[i].0.max(theirs[j].0);
+            let end = ours[i].1.min(theirs[j].1);
+            if start < end {
+                both.push((start, end));
+            }
+            if ours[i].1 < theirs[j].1 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        both
+    }
+
+    // every codepoint the merged intervals miss, worked out a whole interval at a time
+    fn gaps(intervals: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
+        let mut gaps = vec![];
+        let mut next = 0;
+        for (start, end) in intervals {
+            if start > next {
+                gaps.push((next, start));
+            }
+            next = next.max(end);
+        }
+        if next <= char::MAX as u32 {
+            gaps.push((next, char::MAX as u32 + 1));
+        }
+        gaps
+    }
+
+    // merged, with exclusive ends
+    fn from_intervals(intervals: Vec<(u32, u32)>, negated: bool) -> CharClass {
+        CharClass {
+            ranges: intervals.into_iter().map(|(start, end)| (start, end - 1)).collect(),
+            negated
+        }
+    }
+
+    // letter and every char that folds the same way it does, looked up in the simple case folding
+    // table
+    #[cfg(feature = "unicode")]
+    pub(crate) fn case_variants(letter: char) -> Vec<char> {
+        let mut variants = vec![letter];
+        if let Ok(index) = CASE_FOLDING.binary_search_by_key(&(letter as u32), |a| a.0) {
+            variants.extend(CASE_FOLDING[index].1.iter().filter_map(|a| char::from_u32(*a)));
+        }
+        variants
+    }
+
+    // without the unicode feature only ASCII letters have another case
+    #[cfg(not(feature = "unicode"))]
+    pub(crate) fn case_variants(letter: char) -> Vec<char> {
+        match letter.is_ascii_alphabetic() {
+            true => vec![letter, (letter as u8 ^ 0x20) as char],
+            false => vec![letter]
+        }
+    }
+
+    #[cfg(feature = "unicode")]
+    static WORD: OnceLock<CharClass> = OnceLock::new();
+
+    #[cfg(feature = "unicode")]
+    fn word() -> &'static CharClass {
+        WORD.get_or_init(|| CharClass::from_table(WORD_CHARACTER))
     }
 
-    // TODO: implement real errors
-    fn lexer(regex: String) -> Result<Vec<Lexeme>,Error> {
+    // what \w matches, and so what \b looks for on either side
+    #[cfg(feature = "unicode")]
+    fn is_word(letter: char) -> bool {
+        word().is_in(letter)
+    }
+
+    #[cfg(not(feature = "unicode"))]
+    fn is_word(letter: char) -> bool {
+        is_ascii_word(letter)
+    }
+
+    fn is_ascii_word(letter: char) -> bool {
+        letter.is_ascii_alphanumeric() || letter == '_'
+    }
+
+    // \w and \W as the ASCII classes they are under SyntaxConfig::ascii_words, spelled out the
+    // way the lexer spells out [:alpha:], which works in a class as well as out of one
+    fn ascii_word((lexeme, span): (Lexeme, Range<usize>)) -> Vec<(Lexeme, Range<usize>)> {
+        let negated = match lexeme {
+            Lexeme::Builtin('w') => false,
+            Lexeme::Builtin('W') => true,
+            lexeme => return vec![(lexeme, span)]
+        };
+        let ranges = [('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')].map(|(start, end)| Lexeme::Range(start, end));
+        let class = [Lexeme::OpenBracket].into_iter().chain(negated.then_some(Lexeme::Literal('^'))).chain(ranges).chain([Lexeme::CloseBracket]);
+        class.map(|a| (a, span.clone())).collect()
+    }
+
+    // \d, \w and \s, going by Unicode rather than just ASCII, and their negations
+    #[cfg(feature = "unicode")]
+    fn shorthand(letter: char) -> Option<CharClass> {
+        let class = match letter.to_ascii_lowercase() {
+            'd' => CharClass::from_table(DECIMAL_NUMBER),
+            'w' => word().clone(),
+            's' => CharClass::from_table(WHITE_SPACE),
+            // the horizontal and vertical whitespace PCRE means by these
+            'h' => CharClass::from_table(&[
+                (0x9, 0x9), (0x20, 0x20), (0xA0, 0xA0), (0x1680, 0x1680), (0x180E, 0x180E), (0x2000, 0x200A),
+                (0x202F, 0x202F), (0x205F, 0x205F), (0x3000, 0x3000)
+            ]),
+            'v' => CharClass::from_table(&[(0xA, 0xD), (0x85, 0x85), (0x2028, 0x2029)]),
+            _ => return None
+        };
+        Some(if letter.is_ascii_uppercase() { class.complement() } else { class })
+    }
+
+    // the same, going by ASCII alone
+    #[cfg(not(feature = "unicode"))]
+    fn shorthand(letter: char) -> Option<CharClass> {
+        let class = CharClass::from_table(match letter.to_ascii_lowercase() {
+            'd' => &[(0x30, 0x39)],
+            'w' => &[(0x30, 0x39), (0x41, 0x5a), (0x5f, 0x5f), (0x61, 0x7a)],
+            's' => &[(0x9, 0xd), (0x20, 0x20)],
+            'h' => &[(0x9, 0x9), (0x20, 0x20)],
+            'v' => &[(0xa, 0xd)],
+            _ => return None
+        });
+        Some(if letter.is_ascii_uppercase() { class.complement() } else { class })
+    }
+
+    // the binary properties there are tables for, then the general categories, scripts and blocks
+    #[cfg(feature = "unicode")]
+    fn property(name: &str) -> Option<CharClass> {
+        let table: &[(u32, u32)] = match name {
+            "Any" => &[(0, 0xD7FF), (0xE000, 0x10FFFF)],
+            "ASCII" => &[(0, 0x7F)],
+            "Alphabetic" | "Alpha" => ALPHABETIC,
+            "Lowercase" | "Lower" => LOWERCASE,
+            "Uppercase" | "Upper" => UPPERCASE,
+            "White_Space" | "Whitespace" | "Space" => WHITE_SPACE,
+            _ => return general_category(name).or_else(|| script_or_block(name))
+        };
+        Some(CharClass::from_table(table))
+    }
+
+    #[cfg(feature = "unicode")]
+    fn loose(name: &str) -> String {
+        name.chars().filter(|a| !matches!(a, ' ' | '-' | '_')).flat_map(char::to_lowercase).collect()
+    }
+
+    // \p{Lu} or \p{Uppercase_Letter} for one general category, and \pL or \p{Letter} for all of
+    // those starting with L, and so on for M, N, P, S, Z and C. LC is Lu, Ll and Lt together.
+    // Names go by the same loose matching as scripts.
+    #[cfg(feature = "unicode")]
+    fn general_category(name: &str) -> Option<CharClass> {
+        let group = |short: &str| {
+            let table = GENERAL_CATEGORIES.iter().filter(|a| a.1.starts_with(short));
+            table.fold(CharClass::new(), |class, a| class.union(&CharClass::from_table(a.2)))
+        };
+        match loose(name).as_str() {
+            "l" | "letter" => Some(group("L")),
+            "lc" | "casedletter" => Some(group("Lu").union(&group("Ll")).union(&group("Lt"))),
+            "m" | "mark" | "combiningmark" => Some(group("M")),
+            "n" | "number" => Some(group("N")),
+            "p" | "punctuation" | "punct" => Some(group("P")),
+            "s" | "symbol" => Some(group("S")),
+            "z" | "separator" => Some(group("Z")),
+            "c" | "other" => Some(group("C")),
+            "cntrl" => Some(group("Cc")),
+            "digit" => Some(group("Nd")),
+            name => GENERAL_CATEGORIES.iter().find(|a| loose(a.0) == name || loose(a.1) == name).map(|a| CharClass::from_table(a.2))
+        }
+    }
+
+    // \p{Greek} or \p{sc=Greek} for a script, \p{scx=Greek} for everything used in it, and
+    // \p{blk=Greek_and_Coptic} or \p{InGreek_and_Coptic} for a block, with : as good as =. Case,
+    // spaces, hyphens and underscores in these names are ignored, as Unicode says they may be.
+    #[cfg(feature = "unicode")]
+    fn script_or_block(name: &str) -> Option<CharClass> {
+        fn script(table: &[Script], name: &str) -> Option<CharClass> {
+            table.iter().find(|a| loose(a.0) == name || loose(a.1) == name).map(|a| CharClass::from_table(a.2))
+        }
+        fn block(name: &str) -> Option<CharClass> {
+            BLOCKS.iter().find(|a| loose(a.0) == name).map(|a| CharClass::from_table(&[(a.1, a.2)]))
+        }
+        match name.split_once([':', '=']) {
+            Some((key, value)) => match loose(key).as_str() {
+                "script" | "sc" => script(SCRIPTS, &loose(value)),
+                "scriptextensions" | "scx" => script(SCRIPT_EXTENSIONS, &loose(value)),
+                "block" | "blk" => block(&loose(value)),
+                "generalcategory" | "gc" => general_category(value),
+                _ => None
+            },
+            // a script first, so Inherited isn't taken for a block called herited
+            None => script(SCRIPTS, &loose(name)).or_else(|| block(loose(name).strip_prefix("in")?))
+        }
+    }
+
+    // without the unicode feature there's nothing to look a property up in besides these two
+    #[cfg(not(feature = "unicode"))]
+    fn property(name: &str) -> Option<CharClass> {
+        match name {
+            "Any" => Some(CharClass::new().complement()),
+            "ASCII" => Some(CharClass::from_table(&[(0, 0x7f)])),
+            _ => None
+        }
+    }
+
+    // what a backslash followed by next stands for: a control char, a builtin, or next itself
+    fn escaped(next: char) -> Lexeme {
+        match next {
+            'n' => Lexeme::Literal('\n'),
+            't' => Lexeme::Literal('\t'),
+            'r' => Lexeme::Literal('\r'),
+            'f' => Lexeme::Literal('\u{c}'),
+            a if a.is_alphanumeric() => Lexeme::Builtin(a),
+            a => Lexeme::Literal(a)
+        }
+    }
+
+    // escaped, reading the rest of an octal escape along with it: \0 and up to two more octal
+    // digits, or any three octal digits. Other digits are left alone, as elsewhere they'd be
+    // backreferences. \cA to \cZ, in either case, are the control chars 1 to 26, and \N{NAME} is
+    // the char Unicode calls NAME.
+    fn escape(next: char, chars: &mut Peekable<CharIndices>) -> Result<Lexeme, ParseError> {
+        if next == 'c' {
+            if let Some((_, letter)) = chars.next_if(|a| a.1.is_ascii_alphabetic()) {
+                return Ok(Lexeme::Literal((letter.to_ascii_uppercase() as u8 ^ 0x40) as char));
+            }
+        }
+        #[cfg(feature = "unicode")]
+        if next == 'N' && chars.next_if(|a| a.1 == '{').is_some() {
+            let mut name = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '}')) => break,
+                    Some((_, a)) => name.push(a),
+                    None => return Err(ParseError::UnknownCharName(name))
+                }
+            }
+            return char_named(&name).map(Lexeme::Literal).ok_or(ParseError::UnknownCharName(name));
+        }
+        let digits = chars.clone().take(2).take_while(|a| a.1.is_digit(8)).count();
+        if next != '0' && (digits < 2 || !next.is_digit(8)) {
+            return Ok(escaped(next));
+        }
+        let mut value = next.to_digit(8).unwrap();
+        for (_, digit) in chars.take(digits) {
+            value = value * 8 + digit.to_digit(8).unwrap();
+        }
+        Ok(Lexeme::Literal(char::from_u32(value).unwrap()))
+    }
+
+    // the char with this name, in any case
+    #[cfg(feature = "unicode")]
+    fn char_named(name: &str) -> Option<char> {
+        let name = name.to_ascii_uppercase();
+        if let Some((prefix, number)) = name.rsplit_once('-').filter(|a| a.1.chars().all(|a| a.is_ascii_hexdigit())) {
+            let number = u32::from_str_radix(number, 16).ok()?;
+            if NUMBERED.iter().any(|a| a.0 == prefix && (a.1..=a.2).contains(&number)) {
+                return char::from_u32(number);
+            }
+        }
+        if let Some(syllable) = name.strip_prefix("HANGUL SYLLABLE ") {
+            let named = |a: &u32| {
+                let (leading, vowel, trailing) = (*a as usize / 588, *a as usize % 588 / 28, *a as usize % 28);
+                [HANGUL_LEADING[leading], HANGUL_VOWEL[vowel], HANGUL_TRAILING[trailing]].concat() == syllable
+            };
+            return (0..11172).find(named).and_then(|a| char::from_u32(0xAC00 + a));
+        }
+        let codepoints = NAMED.iter().flat_map(|(start, end)| *start..=*end);
+        NAMES.lines().zip(codepoints).find(|a| a.0 == name).and_then(|a| char::from_u32(a.1))
+    }
+
+    // where the next unread char starts, or the end of regex when there isn't one
+    fn offset(regex: &str, chars: &mut Peekable<CharIndices>) -> usize {
+        chars.peek().map_or(regex.len(), |a| a.0)
+    }
+
+    // the lexemes of regex, each with the bytes it was read from, by the same rules parsing uses.
+    // A range in a class is one lexeme spanning both ends and the dash.
+    pub fn lexer(regex: &str) -> Result<Vec<(Lexeme, Range<usize>)>, ParseError> {
+        lexer_spanned(regex).map_err(|a| a.0)
+    }
+
+    // lexer, with an error's place: from the start of what was being read to where it gave up
+    fn lexer_spanned(regex: &str) -> Result<Vec<(Lexeme, Range<usize>)>, Located> {
         let mut lex_string = Vec::new();
-        let mut chars = regex.chars();
-        let mut in_class = false;
+        let mut chars = regex.char_indices().peekable();
+        let mut current = 0;
+        match lex(regex, &mut chars, &mut lex_string, &mut current) {
+            Ok(()) => Ok(lex_string),
+            Err(error) => Err((error, current..offset(regex, &mut chars)))
+        }
+    }
+
+    // lexes into lex_string, with current left at the start of the last thing read
+    fn lex(regex: &str, chars: &mut Peekable<CharIndices>, lex_string: &mut Vec<(Lexeme, Range<usize>)>, current: &mut usize) -> Result<(), ParseError> {
+        // how many classes deep we are, since they can nest
+        let mut depth = 0;
 
-        while let Some(character) = chars.next() {
+        while let Some((start, character)) = chars.next() {
+            *current = start;
             // some extra logic required to escape the reserved characters
-            if in_class && character != ']' && character != '-' {
-                lex_string.push(Lexeme::Literal(character));
+            if depth > 0 && character == '\\' {
+                let Some((_, next)) = chars.next() else { return Err(ParseError::UnclosedClass) };
+                let lexeme = escape(next, chars)?;
+                lex_string.push((lexeme, start..offset(regex, chars)));
+                continue;
+            } else if depth > 0 && matches!(character, '&' | '-') && chars.peek().is_some_and(|a| a.1 == character) {
+                // a -- right before the ] is a range ending in -, as in [!--]
+                if character == '&' || chars.clone().nth(1).is_some_and(|a| a.1 != ']') {
+                    chars.next();
+                    let lexeme = if character == '&' { Lexeme::Intersect } else { Lexeme::Subtract };
+                    lex_string.push((lexeme, start..offset(regex, chars)));
+                    continue;
+                }
+            }
+            if depth > 0 && character != ']' && character != '-' && character != '[' {
+                lex_string.push((Lexeme::Literal(character), start..offset(regex, chars)));
                 continue;
-            } else if in_class && character == '-' {
-                if let Some(last) = lex_string.pop() {
+            } else if depth > 0 && character == '-' {
+                if let Some((last, span)) = lex_string.pop() {
                     match last {
-                        Lexeme::OpenBracket => return Err(Error),
-                        Lexeme::Range(_, _) => return Err(Error),
+                        Lexeme::OpenBracket | Lexeme::CloseBracket => return Err(ParseError::DanglingDash),
+                        Lexeme::Range(_, _) | Lexeme::Builtin(_) => return Err(ParseError::DanglingDash),
                         _ => {}
                     }
-                    if let Some(next) = chars.next() {
-                        if next == ']' {
-                            return Err(Error)
-                        }
-                        lex_string.push(Lexeme::Range(last.lexeme_to_char(), next));
-                        continue;
-                    } else {
-                        return Err(Error)
+                    let next = match chars.next().map(|a| a.1) {
+                        Some('\\') => chars.next().map(|a| escape(a.1, chars)).transpose()?,
+                        Some(']' | '[') => return Err(ParseError::DanglingDash),
+                        next => next.map(Lexeme::Literal)
+                    };
+                    match next {
+                        Some(Lexeme::Literal(next)) => lex_string.push((Lexeme::Range(last.lexeme_to_char(), next), span.start..offset(regex, chars))),
+                        Some(_) => return Err(ParseError::DanglingDash),
+                        None => return Err(ParseError::UnclosedClass)
                     }
+                    continue;
                 } else {
-                    return Err(Error) // Should *never* happen!
+                    unreachable!() // depth > 0 means '[' at least was pushed
                 }
-            } else if in_class && character == ']' {
-                in_class = false;            
+            } else if depth > 0 && character == ']' {
+                depth -= 1;
             } else if character == '[' {
-                in_class = true;
+                depth += 1;
             } else if character == '\\' {
-                if let Some(next) = chars.next() {
-                    match Lexeme::match_char(next) {
-                        Lexeme::Literal(_) => {
-                            lex_string.push(Lexeme::Builtin(next));
-                        },
-                        _ => lex_string.push(Lexeme::Literal(next))
-
+                match chars.next() {
+                    // \Q starts a run of plain chars that lasts up to \E or the end
+                    Some((_, 'Q')) => {
+                        while let Some((start, character)) = chars.next() {
+                            if character == '\\' && chars.next_if(|a| a.1 == 'E').is_some() {
+                                break;
+                            }
+                            lex_string.push((Lexeme::Literal(character), start..offset(regex, chars)));
+                        }
+                    }
+                    Some((_, next)) => {
+                        let lexeme = escape(next, chars)?;
+                        lex_string.push((lexeme, start..offset(regex, chars)));
                     }
+                    None => return Err(ParseError::TrailingBackslash)
+                }
+                continue;
+            } else if character == '{' {
+                if let Some(counted) = counted(chars, false) {
+                    lex_string.push((counted?, start..offset(regex, chars)));
+                    continue;
+                }
+            } else if character == '(' && chars.clone().take(2).map(|a| a.1).eq(['?', '#']) {
+                // a comment, which runs to the first ) and is skipped by parsing
+                if !chars.any(|a| a.1 == ')') {
+                    return Err(ParseError::UnclosedGroup);
                 }
+                lex_string.push((Lexeme::Comment, start..offset(regex, chars)));
                 continue;
             }
-            lex_string.push(Lexeme::match_char(character));
+            lex_string.push((Lexeme::match_char(character), start..offset(regex, chars)));
         }
 
-        Ok(lex_string)
+        Ok(())
     }
 
-    enum ParserState {
-        OutOfClassWithoutQual,
-        InClass(NodeIndex, CharClass),
-        QualWithoutClass(NodeIndex)
+    // the rest of a {n}, {n,} or {n,m} once its { has been read, all of it taken from chars, or
+    // None with nothing taken when it isn't one of those, which leaves the { a plain char. Basic
+    // POSIX escapes the closing brace as it does the opening one.
+    fn counted(chars: &mut Peekable<CharIndices>, escaped: bool) -> Option<Result<Lexeme, ParseError>> {
+        let mut ahead = chars.clone();
+        let mut text = String::new();
+        loop {
+            match ahead.next()?.1 {
+                '\\' if escaped && ahead.next_if(|a| a.1 == '}').is_some() => break,
+                '}' if !escaped => break,
+                a @ ('0'..='9' | ',') => text.push(a),
+                _ => return None
+            }
+        }
+        let (min, max) = match text.split_once(',') {
+            Some((min, "")) => (min, None),
+            Some((min, max)) => (min, Some(max)),
+            None => (text.as_str(), Some(text.as_str()))
+        };
+        if min.is_empty() || max.is_some_and(|a| a.contains(',')) {
+            return None;
+        }
+        *chars = ahead;
+        let bound = |a: &str| a.parse::<u32>().map_err(|_| ParseError::RepeatTooLarge(u32::MAX));
+        let (min, max) = match (bound(min), max.map(bound).transpose()) {
+            (Ok(min), Ok(max)) => (min, max),
+            (Err(error), _) | (_, Err(error)) => return Some(Err(error))
+        };
+        Some(match max {
+            Some(max) if min > max => Err(ParseError::ReversedRepeat(min, max)),
+            max => Ok(Lexeme::Counted(min, max))
+        })
     }
 
-    impl ParserState {
-        fn add_cost(&mut self, new_char: char) {
-            match self {
-                ParserState::InClass(_, a) => {a.plus_literal(new_char);}
-                _ => {}
+    // lexes the POSIX dialects into the same lexemes as the default one, so parsing is shared. An
+    // error's place runs from the start of what was being read to where it gave up.
+    fn posix_lexer(regex: &str, basic: bool) -> Result<Vec<(Lexeme, Range<usize>)>, Located> {
+        let mut lex_string = Vec::new();
+        let mut chars = regex.char_indices().peekable();
+        // where a basic pattern's * is literal and ^ an anchor: at the start and after \( or \|
+        let mut at_start = true;
+        while let Some((start, character)) = chars.next() {
+            let escaped_brace = basic && character == '\\' && chars.peek().is_some_and(|a| a.1 == '{');
+            if (character == '{' && !basic) || escaped_brace {
+                let mut after = chars.clone();
+                if escaped_brace {
+                    after.next();
+                }
+                if let Some(counted) = counted(&mut after, basic) {
+                    chars = after;
+                    let counted = counted.map_err(|a| (a, start..offset(regex, &mut chars)))?;
+                    lex_string.push((counted, start..offset(regex, &mut chars)));
+                    at_start = false;
+                    continue;
+                }
+            }
+            let lexeme = match (character, basic) {
+                ('[', _) => {
+                    lex_string.push((Lexeme::OpenBracket, start..start + 1));
+                    posix_bracket(regex, &mut chars, &mut lex_string).map_err(|a| (a, start..offset(regex, &mut chars)))?;
+                    Lexeme::CloseBracket
+                }
+                ('\\', _) => match chars.next().map(|a| a.1) {
+                    None => return Err((ParseError::TrailingBackslash, start..regex.len())),
+                    Some('(') if basic => Lexeme::OpenParen,
+                    Some(')') if basic => Lexeme::CloseParen,
+                    Some('|') if basic => Lexeme::Bar,
+                    Some('+') if basic => Lexeme::Plus,
+                    Some('?') if basic => Lexeme::Question,
+                    Some(a) if a.is_alphanumeric() => return Err((ParseError::UnsupportedEscape(a), start..offset(regex, &mut chars))),
+                    Some(a) => Lexeme::Literal(a)
+                },
+                ('*', true) if at_start => Lexeme::Literal('*'),
+                ('^', true) if !at_start => Lexeme::Literal('^'),
+                ('$', true) if !at_end(chars.clone()) => Lexeme::Literal('$'),
+                ('+' | '?' | '|' | '(' | ')', true) => Lexeme::Literal(character),
+                // only a bracket expression's own ] closes anything
+                (']', _) => Lexeme::Literal(']'),
+                (a, _) => Lexeme::match_char(a)
+            };
+            // (? is how the default dialect's groups with flags or a name start, and POSIX has none
+            if lexeme == Lexeme::Question && lex_string.last().is_some_and(|a| a.0 == Lexeme::OpenParen) {
+                return Err((ParseError::Unexpected('?'), start..offset(regex, &mut chars)));
             }
+            at_start = matches!(lexeme, Lexeme::OpenParen | Lexeme::Bar) || (at_start && lexeme == Lexeme::Caret);
+            // a bracket's ] is the only lexeme not starting where the loop did
+            let end = offset(regex, &mut chars);
+            let start = if lexeme == Lexeme::CloseBracket { end - 1 } else { start };
+            lex_string.push((lexeme, start..end));
         }
+        Ok(lex_string)
+    }
 
-        fn add_cost_range(&mut self, start_char: char, end_char: char) {
-            match self {
-                ParserState::InClass(_, a) => {a.plus_range(start_char, end_char);}
-                _ => {}
+    // whether a basic pattern's $ is an anchor here: at the end, or before \) or \|
+    fn at_end(mut rest: Peekable<CharIndices>) -> bool {
+        match rest.next().map(|a| a.1) {
+            None => true,
+            Some('\\') => matches!(rest.next().map(|a| a.1), Some(')' | '|')),
+            _ => false
+        }
+    }
+
+    // a POSIX bracket expression after the '[', up to but not including the ']'. A backslash is
+    // literal in here, a ] first of all is a member, and [:name:] is one of the ASCII classes.
+    fn posix_bracket(regex: &str, chars: &mut Peekable<CharIndices>, lex_string: &mut Vec<(Lexeme, Range<usize>)>) -> Result<(), ParseError> {
+        if let Some((start, _)) = chars.next_if(|a| a.1 == '^') {
+            lex_string.push((Lexeme::Literal('^'), start..start + 1));
+        }
+        let mut first = true;
+        loop {
+            let (position, start) = match chars.next() {
+                None => return Err(ParseError::UnclosedClass),
+                Some((_, ']')) if !first => return Ok(()),
+                Some((position, '[')) if chars.next_if(|a| a.1 == ':').is_some() => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next().map(|a| a.1) {
+                            Some(':') if chars.next_if(|a| a.1 == ']').is_some() => break,
+                            Some(a) => name.push(a),
+                            None => return Err(ParseError::UnclosedClass)
+                        }
+                    }
+                    let ranges = posix_class(&name).ok_or(ParseError::UnknownProperty(name))?;
+                    let span = position..offset(regex, chars);
+                    lex_string.extend(ranges.iter().map(|(start, end)| (Lexeme::Range(*start, *end), span.clone())));
+                    first = false;
+                    continue;
+                }
+                Some(a) => a
+            };
+            first = false;
+            let mut ahead = chars.clone();
+            match (ahead.next().map(|a| a.1), ahead.next().map(|a| a.1)) {
+                (Some('-'), Some(end)) if end != ']' => {
+                    chars.nth(1);
+                    lex_string.push((Lexeme::Range(start, end), position..offset(regex, chars)));
+                }
+                _ => lex_string.push((Lexeme::Literal(start), position..offset(regex, chars)))
             }
         }
     }
 
-    pub fn parser(regex: String) -> Result<Graph<CharCost>, Error> {
-        let mut group_starts: Vec<NodeIndex> = vec![];
-        let mut state = ParserState::OutOfClassWithoutQual;
-        let mut graph = Graph::new();
-        let lex_string;
-        if let Ok(lexs) = lexer(regex) {
-            lex_string = lexs;
-        } else {
-            return Err(Error);
+    // the POSIX character classes, as the C locale has them
+    fn posix_class(name: &str) -> Option<&'static [(char, char)]> {
+        Some(match name {
+            "alpha" => &[('A', 'Z'), ('a', 'z')],
+            "digit" => &[('0', '9')],
+            "alnum" => &[('0', '9'), ('A', 'Z'), ('a', 'z')],
+            "upper" => &[('A', 'Z')],
+            "lower" => &[('a', 'z')],
+            "space" => &[('\t', '\r'), (' ', ' ')],
+            "blank" => &[('\t', '\t'), (' ', ' ')],
+            "punct" => &[('!', '/'), (':', '@'), ('[', '`'), ('{', '~')],
+            "print" => &[(' ', '~')],
+            "graph" => &[('!', '~')],
+            "cntrl" => &[('\0', '\u{1f}'), ('\u{7f}', '\u{7f}')],
+            "xdigit" => &[('0', '9'), ('A', 'F'), ('a', 'f')],
+            _ => return None
+        })
+    }
+
+    // an error and the bytes of the pattern it's about
+    pub type Located = (ParseError, Range<usize>);
+
+    // the spans are only wanted by lexer's callers, parsing drops them as it goes
+    type Lexemes = Peekable<Map<IntoIter<(Lexeme, Range<usize>)>, fn((Lexeme, Range<usize>)) -> Lexeme>>;
+
+    fn first((lexeme, _): (Lexeme, Range<usize>)) -> Lexeme {
+        lexeme
+    }
+
+    // names gets where each group name was read, see parse_group_name
+    fn parse_alternation(lexemes: &mut Lexemes, names: &mut Vec<(usize, usize)>) -> Result<Ast, ParseError> {
+        let mut branches = vec![parse_concat(lexemes, names)?];
+        while lexemes.next_if_eq(&Lexeme::Bar).is_some() {
+            branches.push(parse_concat(lexemes, names)?);
         }
+        if branches.len() == 1 {
+            return Ok(branches.pop().unwrap());
+        }
+        Ok(Ast::Alternation(branches))
+    }
 
-        for lexeme in lex_string {
-            match (lexeme, &mut state) {
-                (Lexeme::Bar, ParserState::OutOfClassWithoutQual) | (Lexeme::Bar, ParserState::QualWithoutClass(_)) => {
-                    graph.add_junction(*group_starts.last().unwrap_or(&0));
-                }
-                (Lexeme::OpenParen, ParserState::OutOfClassWithoutQual) | (Lexeme::OpenParen, ParserState::QualWithoutClass(_)) => {
-                    group_starts.push(graph.active);
+    fn parse_concat(lexemes: &mut Lexemes, names: &mut Vec<(usize, usize)>) -> Result<Ast, ParseError> {
+        let mut items = vec![];
+        while let Some(lexeme) = lexemes.peek() {
+            match lexeme {
+                Lexeme::Bar | Lexeme::CloseParen => break,
+                _ => items.push(parse_repeat(lexemes, names)?)
+            }
+        }
+        match items.len() {
+            0 => Ok(Ast::Empty),
+            1 => Ok(items.pop().unwrap()),
+            _ => Ok(Ast::Concat(items))
+        }
+    }
+
+    fn parse_repeat(lexemes: &mut Lexemes, names: &mut Vec<(usize, usize)>) -> Result<Ast, ParseError> {
+        let atom = parse_atom(lexemes, names)?;
+        let qualifier = match lexemes.peek() {
+            Some(Lexeme::Star) => Qualifier::ZeroOrMore,
+            Some(Lexeme::Plus) => Qualifier::OneOrMore,
+            Some(Lexeme::Question) => Qualifier::ZeroOrOne,
+            Some(Lexeme::Counted(min, max)) => Qualifier::Counted(*min, *max),
+            _ => return Ok(atom)
+        };
+        lexemes.next();
+        Ok(Ast::Repeat(Box::new(atom), qualifier))
+    }
+
+    fn parse_atom(lexemes: &mut Lexemes, names: &mut Vec<(usize, usize)>) -> Result<Ast, ParseError> {
+        match lexemes.next() {
+            Some(Lexeme::Literal(character)) => Ok(Ast::Cost(CharCost::fromchar(character))),
+            Some(Lexeme::Dot) => Ok(Ast::Cost(CharCost::Dot)),
+            Some(Lexeme::Caret) => Ok(Ast::Cost(CharCost::Look(Look::Start))),
+            Some(Lexeme::Dollar) => Ok(Ast::Cost(CharCost::Look(Look::End))),
+            Some(Lexeme::Builtin(letter @ ('b' | 'B'))) => Ok(Ast::Cost(CharCost::Look(parse_boundary(letter, lexemes)?))),
+            // any line break, a \r\n taken whole
+            Some(Lexeme::Builtin('R')) => Ok(Ast::Alternation(vec![
+                Ast::Concat(vec![Ast::Cost(CharCost::Singleton('\r')), Ast::Cost(CharCost::Singleton('\n'))]),
+                Ast::Cost(CharCost::Class(shorthand('v').unwrap()))
+            ])),
+            Some(Lexeme::Builtin(letter)) => Ok(Ast::Cost(CharCost::Class(parse_builtin(letter, lexemes)?))),
+            Some(Lexeme::OpenParen) => {
+                // (?:...) only groups, (?<name>...) and (?P<name>...) capture under a name
+                let (capturing, name) = match lexemes.next_if_eq(&Lexeme::Question) {
+                    Some(_) => match lexemes.next() {
+                        Some(Lexeme::Literal(':')) => (false, None),
+                        Some(Lexeme::Literal('<')) => (true, Some(parse_group_name(lexemes, names)?)),
+                        Some(Lexeme::Literal('P')) if lexemes.next_if_eq(&Lexeme::Literal('<')).is_some() => (true, Some(parse_group_name(lexemes, names)?)),
+                        Some(lexeme) => return Err(ParseError::Unexpected(lexeme.lexeme_to_char())),
+                        None => return Err(ParseError::UnclosedGroup)
+                    },
+                    None => (true, None)
+                };
+                let inner = parse_alternation(lexemes, names)?;
+                if lexemes.next() != Some(Lexeme::CloseParen) {
+                    return Err(ParseError::UnclosedGroup)
                 }
-                (Lexeme::OpenBracket, ParserState::OutOfClassWithoutQual) | (Lexeme::OpenBracket, ParserState::QualWithoutClass(_)) => {
-                    state = ParserState::InClass(graph.active, CharClass::new());
+                Ok(if capturing { Ast::Group(Box::new(inner), name) } else { inner })
+            }
+            Some(Lexeme::OpenBracket) => Ok(Ast::Cost(CharCost::Class(parse_class(lexemes)?))),
+            Some(lexeme) => Err(ParseError::Unexpected(lexeme.lexeme_to_char())),
+            None => unreachable!() // parse_concat only comes down here with something left to read
+        }
+    }
+
+    // everything after the '<', up to and including the '>'. Names are made of letters, digits and
+    // underscores, and don't start with a digit. How many lexemes were left from the '<' on and
+    // after the '>' goes on the end of names, which is as much as parse_ast_spanned needs to find
+    // where the name was.
+    fn parse_group_name(lexemes: &mut Lexemes, names: &mut Vec<(usize, usize)>) -> Result<String, ParseError> {
+        let left = lexemes.len() + 1;
+        let mut name = String::new();
+        loop {
+            match lexemes.next() {
+                Some(Lexeme::Literal('>')) => break,
+                Some(Lexeme::Literal(a)) => name.push(a),
+                Some(lexeme) => {
+                    name.push(lexeme.lexeme_to_char());
+                    return Err(ParseError::InvalidGroupName(name));
                 }
-                (Lexeme::CloseParen, ParserState::OutOfClassWithoutQual) | (Lexeme::CloseParen, ParserState::QualWithoutClass(_)) => {
-                    if let Some(start) = group_starts.pop() {
-                        graph.close_junction(start);
-                        state = ParserState::QualWithoutClass(start)
-                    } else {
-                        return Err(Error)
+                None => return Err(ParseError::UnclosedGroup)
+            }
+        }
+        let valid = name.chars().next().is_some_and(|a| !a.is_ascii_digit()) && name.chars().all(|a| a.is_alphanumeric() || a == '_');
+        if !valid {
+            return Err(ParseError::InvalidGroupName(name));
+        }
+        names.push((left, lexemes.len()));
+        Ok(name)
+    }
+
+    // \b or \B, or with a {name} after it the kind of boundary named: {wb} for one between words as
+    // UAX #29 has it, which needs the unicode feature
+    fn parse_boundary(letter: char, lexemes: &mut Lexemes) -> Result<Look, ParseError> {
+        if lexemes.next_if_eq(&Lexeme::Literal('{')).is_none() {
+            return Ok(if letter == 'b' { Look::WordBoundary } else { Look::NotWordBoundary });
+        }
+        let mut name = String::new();
+        loop {
+            match lexemes.next() {
+                Some(Lexeme::Literal('}')) => break,
+                Some(Lexeme::Literal(a)) => name.push(a),
+                _ => return Err(ParseError::UnknownBoundary(name))
+            }
+        }
+        match (name.as_str(), letter) {
+            #[cfg(feature = "unicode")]
+            ("wb", 'b') => Ok(Look::UnicodeWordBoundary),
+            #[cfg(feature = "unicode")]
+            ("wb", _) => Ok(Look::NotUnicodeWordBoundary),
+            _ => Err(ParseError::UnknownBoundary(name))
+        }
+    }
+
+    // a shorthand, or a property written \pL or \p{Name}, with \P for its complement
+    fn parse_builtin(letter: char, lexemes: &mut Lexemes) -> Result<CharClass, ParseError> {
+        if letter != 'p' && letter != 'P' {
+            return shorthand(letter).ok_or(ParseError::UnsupportedEscape(letter));
+        }
+        let name = match lexemes.next() {
+            Some(Lexeme::Literal('{')) => {
+                let mut name = String::new();
+                loop {
+                    match lexemes.next() {
+                        Some(Lexeme::Literal('}')) => break name,
+                        Some(Lexeme::Literal(a)) => name.push(a),
+                        _ => return Err(ParseError::UnknownProperty(name))
                     }
                 }
-                (Lexeme::Literal(character), ParserState::OutOfClassWithoutQual) | (Lexeme::Literal(character), ParserState::QualWithoutClass(_)) => {
-                    graph.add_cost(CharCost::fromchar(character));
-                    state = ParserState::QualWithoutClass(graph.active);
-                }
-                (Lexeme::Dot, ParserState::OutOfClassWithoutQual) | (Lexeme::Dot, ParserState::QualWithoutClass(_)) => {
-                    graph.add_cost(CharCost::Dot);
-                    state = ParserState::QualWithoutClass(graph.active);
-                }
-                (Lexeme::Builtin(char), ParserState::QualWithoutClass(_)) | (Lexeme::Builtin(char), ParserState::OutOfClassWithoutQual) => {
-                    // TODO!!!! Do builtins
+            }
+            Some(Lexeme::Literal(a)) => a.to_string(),
+            _ => return Err(ParseError::UnknownProperty(String::new()))
+        };
+        let class = property(&name).ok_or(ParseError::UnknownProperty(name))?;
+        Ok(if letter == 'P' { class.complement() } else { class })
+    }
+
+    // everything after the '[', up to and including the matching ']'. && and -- bind loosest, left
+    // to right, so [a-z&&[^aeiou]--y] is the lowercase consonants but y, and a ^ negates the lot.
+    fn parse_class(lexemes: &mut Lexemes) -> Result<CharClass, ParseError> {
+        let negated = lexemes.next_if_eq(&Lexeme::Literal('^')).is_some();
+        // what's left of the last && or -- and which it was
+        let mut left: Option<(CharClass, Lexeme)> = None;
+        let mut class = CharClass::new();
+        loop {
+            if let Some(Lexeme::Intersect | Lexeme::Subtract | Lexeme::CloseBracket) = lexemes.peek() {
+                class = match left.take() {
+                    Some((left, Lexeme::Intersect)) => left.intersect(&class),
+                    Some((left, _)) => left.difference(&class),
+                    None => class
+                };
+            }
+            match lexemes.next() {
+                Some(operator @ (Lexeme::Intersect | Lexeme::Subtract)) => left = Some((std::mem::replace(&mut class, CharClass::new()), operator)),
+                Some(Lexeme::Literal(new_char)) => class.plus_literal(new_char),
+                Some(Lexeme::Range(start_char, end_char)) if start_char > end_char => {
+                    return Err(ParseError::ReversedRange(start_char, end_char))
                 }
-                (_, ParserState::OutOfClassWithoutQual) => {
-                    return Err(Error);
+                Some(Lexeme::Range(start_char, end_char)) => class.plus_range(start_char, end_char),
+                // a nested class adds everything it matches, negated or not
+                Some(Lexeme::OpenBracket) => class = class.union(&parse_class(lexemes)?),
+                Some(Lexeme::Builtin(letter)) => class = class.union(&parse_builtin(letter, lexemes)?),
+                Some(Lexeme::CloseBracket) if negated => return Ok(class.complement()),
+                Some(Lexeme::CloseBracket) => return Ok(class),
+                _ => return Err(ParseError::UnclosedClass)
+            }
+        }
+    }
+
+    pub fn parse_ast(regex: &str) -> Result<Ast, ParseError> {
+        parse_ast_with(regex, &SyntaxConfig::default())
+    }
+
+    // lexer, for whichever dialect regex is written in
+    pub fn lex_dialect(regex: &str, dialect: Dialect) -> Result<Vec<(Lexeme, Range<usize>)>, ParseError> {
+        lex_dialect_spanned(regex, dialect).map_err(|a| a.0)
+    }
+
+    // lexer, going by whichever dialect config is for, so a highlighter sees a pattern the way
+    // parse_ast_with does
+    pub fn lexer_with(regex: &str, config: &SyntaxConfig) -> Result<Vec<(Lexeme, Range<usize>)>, ParseError> {
+        lex_dialect(regex, config.dialect)
+    }
+
+    fn lex_dialect_spanned(regex: &str, dialect: Dialect) -> Result<Vec<(Lexeme, Range<usize>)>, Located> {
+        match dialect {
+            Dialect::Default => lexer_spanned(regex),
+            Dialect::PosixBasic => posix_lexer(regex, true),
+            Dialect::PosixExtended => posix_lexer(regex, false)
+        }
+    }
+
+    // whether regex spells out an uppercase char itself, rather than only through an escape like
+    // \W or in a property or group name, which is what smart case goes by
+    pub fn has_uppercase_literal(regex: &str, dialect: Dialect) -> Result<bool, ParseError> {
+        let lexemes: Vec<Lexeme> = lex_dialect(regex, dialect)?.into_iter().map(|a| a.0).collect();
+        // just past a name starting at start and running up to closer
+        let past = |start: usize, closer: char| start + lexemes[start..].iter().take_while(|a| **a != Lexeme::Literal(closer)).count();
+        let mut index = 0;
+        while index < lexemes.len() {
+            index = match lexemes[index..] {
+                [Lexeme::Literal(a) | Lexeme::Range(a, _) | Lexeme::Range(_, a), ..] if a.is_uppercase() => return Ok(true),
+                [Lexeme::Builtin('p' | 'P'), Lexeme::Literal('{'), ..] => past(index + 2, '}'),
+                [Lexeme::Builtin('p' | 'P'), ..] => index + 2,
+                [Lexeme::OpenParen, Lexeme::Question, Lexeme::Literal('<'), ..] => past(index + 3, '>'),
+                [Lexeme::OpenParen, Lexeme::Question, Lexeme::Literal('P'), Lexeme::Literal('<'), ..] => past(index + 4, '>'),
+                _ => index + 1
+            };
+        }
+        Ok(false)
+    }
+
+    pub fn parse_ast_with(regex: &str, config: &SyntaxConfig) -> Result<Ast, ParseError> {
+        parse_ast_spanned(regex, config).map_err(|a| a.0)
+    }
+
+    // parse_ast_with, with the bytes of regex an error is about. That's the lexeme it came up at,
+    // or nothing at the end for something left open. The POSIX dialects only point at the lot.
+    pub fn parse_ast_spanned(regex: &str, config: &SyntaxConfig) -> Result<Ast, Located> {
+        let lexemes = lex_dialect_spanned(regex, config.dialect)?;
+        for (lexeme, span) in lexemes.iter() {
+            let allowed = match lexeme {
+                Lexeme::Caret | Lexeme::Dollar | Lexeme::Builtin('b' | 'B') => config.looks,
+                Lexeme::Builtin(_) => config.builtins,
+                _ => true
+            };
+            if !allowed {
+                return Err((ParseError::Disabled(regex[span.clone()].to_string()), span.clone()));
+            }
+            if let Lexeme::Counted(min, max) = lexeme {
+                if max.unwrap_or(*min) > config.max_repeat {
+                    return Err((ParseError::RepeatTooLarge(config.max_repeat), span.clone()));
                 }
-                (Lexeme::CloseBracket, ParserState::InClass(_, class)) => {
-                    graph.add_cost(CharCost::Class(class.clone()));
-                    state = ParserState::OutOfClassWithoutQual;
+            }
+        }
+        let lexemes = lexemes.into_iter().filter(|a| a.0 != Lexeme::Comment);
+        let lexemes: Vec<_> = match config.ascii_words {
+            true => lexemes.flat_map(ascii_word).collect(),
+            false => lexemes.collect()
+        };
+        let all = lexemes.clone();
+        let mut lexemes: Lexemes = lexemes.into_iter().map(first as fn(_) -> _).peekable();
+        let located = |error: ParseError, lexemes: &Lexemes| {
+            let span = match all.len() - lexemes.len() {
+                read if read == all.len() && matches!(error, ParseError::UnclosedClass | ParseError::UnclosedGroup) => regex.len()..regex.len(),
+                0 => 0..0,
+                // all of a \p{...}, not just how far into it parsing got
+                read if matches!(error, ParseError::UnknownProperty(_)) => {
+                    let start = all[..read].iter().rposition(|a| matches!(a.0, Lexeme::Builtin('p' | 'P'))).unwrap_or(read - 1);
+                    all[start].1.start..all[read - 1].1.end
                 }
-                (Lexeme::Literal(new_char), ParserState::InClass(_, _)) => {
-                    state.add_cost(new_char);
+                read => all[read - 1].1.clone()
+            };
+            (error, span)
+        };
+        let mut names = vec![];
+        let mut ast = parse_alternation(&mut lexemes, &mut names).map_err(|a| located(a, &lexemes))?;
+        // the only thing that can stop the descent early is an unmatched ')'
+        if lexemes.next().is_some() {
+            return Err(located(ParseError::UnmatchedParen, &lexemes));
+        }
+        if config.ascii_words {
+            ast = ast.map_costs(&mut |cost| Ast::Cost(match cost {
+                CharCost::Look(Look::WordBoundary) => CharCost::Look(Look::WordBoundaryAscii),
+                CharCost::Look(Look::NotWordBoundary) => CharCost::Look(Look::NotWordBoundaryAscii),
+                cost => cost
+            }));
+        }
+        // which named group, counting them in the order they open, shares its name with one before it
+        let named: Vec<String> = ast.groups().into_iter().filter_map(|a| a.name).collect();
+        let duplicate = match config.duplicate_names {
+            true => exclusive_names(&ast, &mut 0).err(),
+            false => (0..named.len()).find(|index| named[..*index].contains(&named[*index]))
+        };
+        match duplicate {
+            // from its < to its >
+            Some(index) => {
+                let (from, after) = names[index];
+                let span = all[all.len() - from].1.start..all[all.len() - after - 1].1.end;
+                Err((ParseError::DuplicateGroupName(named[index].clone()), span))
+            }
+            None => Ok(ast)
+        }
+    }
+
+    // the named groups in ast, each with its number counting from next in the order they open, or
+    // the number of the later of two sharing a name that could both take part in a match, which is
+    // any two not in different branches of an alternation
+    fn exclusive_names(ast: &Ast, next: &mut usize) -> Result<Vec<(String, usize)>, usize> {
+        let (parts, alternatives) = match ast {
+            Ast::Concat(items) => (items, false),
+            Ast::Alternation(branches) => (branches, true),
+            Ast::Repeat(inner, _) => return exclusive_names(inner, next),
+            Ast::Group(inner, name) => {
+                // numbered before the groups inside it, which open after it does
+                let own = name.clone().map(|a| {
+                    *next += 1;
+                    (a, *next - 1)
+                });
+                let mut names = exclusive_names(inner, next)?;
+                if let Some(own) = own {
+                    if let Some((_, index)) = names.iter().find(|a| a.0 == own.0) {
+                        return Err(*index);
+                    }
+                    names.push(own);
                 }
-                (Lexeme::Range(start_char, end_char), ParserState::InClass(_, _)) => {
-                    state.add_cost_range(start_char,end_char);
-                    state.add_cost(end_char);
+                return Ok(names);
+            }
+            Ast::Empty | Ast::Cost(_) => return Ok(vec![])
+        };
+        let mut names: Vec<(String, usize)> = vec![];
+        for part in parts {
+            for (name, index) in exclusive_names(part, next)? {
+                match names.iter().any(|a| a.0 == name) {
+                    true if alternatives => {}
+                    true => return Err(index),
+                    false => names.push((name, index))
                 }
-                (_, ParserState::InClass(_, _)) => {
-                    return Err(Error)
+            }
+        }
+        Ok(names)
+    }
+
+    // shared is set while the active node is the start of an alternation that still has branches to
+    // come or of an enclosing repeat, since a loop closed back onto it would leak into those
+    fn build(ast: &Ast, graph: &mut Graph<CharCost>, shared: bool) {
+        match ast {
+            Ast::Empty => {}
+            Ast::Cost(cost) => graph.add_cost(cost.clone()),
+            Ast::Concat(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    build(item, graph, shared && index == 0);
                 }
-                (Lexeme::Plus, ParserState::QualWithoutClass(start)) => {
-                    graph.one_or_more(*start);
-                    state = ParserState::OutOfClassWithoutQual;
+            }
+            Ast::Group(inner, _) => build(inner, graph, shared),
+            Ast::Alternation(branches) => {
+                let start = graph.fresh_start();
+                for (index, branch) in branches.iter().enumerate() {
+                    if index > 0 {
+                        graph.add_junction(start);
+                    }
+                    let last = index + 1 == branches.len();
+                    build(branch, graph, shared || !last);
+                    // a branch that never left the start would get swallowed by the next one
+                    if graph.active == start && !last {
+                        graph.add_epsilon();
+                    }
                 }
-                (Lexeme::Question, ParserState::QualWithoutClass(start)) => {
-                    graph.zero_or_one(*start);
-                    state = ParserState::OutOfClassWithoutQual;
+                graph.close_junction(start);
+            }
+            Ast::Repeat(inner, qualifier) => {
+                if shared {
+                    graph.add_epsilon();
                 }
-                (Lexeme::Star, ParserState::QualWithoutClass(start)) => {
-                    graph.zero_or_more(*start);
-                    state = ParserState::OutOfClassWithoutQual;
+                let start = graph.fresh_start();
+                if let Qualifier::Counted(min, max) = *qualifier {
+                    build_counted(inner, graph, start, min, max);
+                    return;
                 }
-                (_, ParserState::QualWithoutClass(_)) => {
-                    return Err(Error)
+                // start is also where this repeat exits from, so a loop nested right at it would leak too
+                build(inner, graph, true);
+                match qualifier {
+                    Qualifier::ZeroOrOne => graph.zero_or_one(start),
+                    Qualifier::ZeroOrMore => graph.zero_or_more(start),
+                    Qualifier::OneOrMore => graph.one_or_more(start),
+                    Qualifier::Counted(..) => unreachable!()
                 }
             }
         }
-        
-        Ok(graph)
+    }
+
+    // the body gets built once, between a Count edge into it and one out, with a third going back
+    // round from its end, so the bounds only ever show up in those edges. Nothing from outside
+    // reaches into the body but the edge in, so every path in it has the same repeats to count.
+    fn build_counted(inner: &Ast, graph: &mut Graph<CharCost>, start: NodeIndex, min: u32, max: Option<u32>) {
+        if max == Some(0) {
+            return;
+        }
+        let count = |step| CharCost::Count(Count { step, min, max });
+        graph.add_cost(count(Step::Enter));
+        let body = graph.active;
+        build(inner, graph, true);
+        // one end to go round from, however many the body was left with
+        graph.close_junction(body);
+        let end = graph.active;
+        graph.node_mut(end).unwrap().edges.push((body, Some(count(Step::Again))));
+        graph.add_cost(count(Step::Leave));
+        if min == 0 {
+            graph.zero_or_one(start);
+        }
+    }
+
+    pub fn parser(regex: &str) -> Result<Graph<CharCost>, ParseError> {
+        parser_with(regex, &SyntaxConfig::default())
+    }
+
+    pub fn parser_with(regex: &str, config: &SyntaxConfig) -> Result<Graph<CharCost>, ParseError> {
+        Ok(graph_from_ast(parse_ast_with(regex, config)?))
+    }
+
+    pub fn graph_from_ast(ast: Ast) -> Graph<CharCost> {
+        let ast = ast.simplify();
+        let mut graph = Graph::new();
+        build(&ast, &mut graph, false);
+        graph.mark_accepting();
+        graph
+    }
+
+    // one automaton for all of patterns, each accepting node carrying the index of the pattern it
+    // came from
+    pub fn graph_from_patterns(patterns: &[&str]) -> Result<Graph<CharCost>, ParseError> {
+        let mut graphs = vec![];
+        for (index, pattern) in patterns.iter().enumerate() {
+            graphs.push(graph_from_ast(parse_ast(pattern)?).with_pattern(index));
+        }
+        Ok(graphs.into_iter().reduce(Graph::union).unwrap_or_default())
     }
 
 
@@ -250,23 +1625,331 @@ pub mod parser {
 
         #[test]
         fn test_charclass_is_in() {
-            let char_range = Range {
-                start: 'a',
-                end: 'z'
-            };
-            let class = CharClass {
-                chars: vec!['z'],
-                ranges: vec![char_range]
-            };
+            let mut class = CharClass::new();
+            class.plus_range('a', 'z');
             for letter in 'a'..='z' {
                 assert!(class.is_in(letter));
+                assert!(!class.complement().is_in(letter));
+            }
+            assert!(class.complement().is_in('A'));
+        }
+
+        #[test]
+        fn test_class_stays_normalized() {
+            let mut class = CharClass::new();
+            for letter in ['x', 'c', 'a', 'b', 'z'] {
+                class.plus_literal(letter);
+            }
+            class.plus_range('m', 'p');
+            class.plus_range('n', 'w');
+            assert_eq!(class.ranges, vec![('a' as u32, 'c' as u32), ('m' as u32, 'x' as u32), ('z' as u32, 'z' as u32)]);
+
+            let mut every_other = CharClass::new();
+            for letter in (0..0x3000).step_by(2).filter_map(char::from_u32) {
+                every_other.plus_literal(letter);
+            }
+            assert!(every_other.is_in('\u{2000}') && !every_other.is_in('\u{2001}') && !every_other.is_in('\u{3000}'));
+        }
+
+        #[test]
+        fn test_inclusive_ranges() {
+            let graph = parser("[a-c]").unwrap();
+            assert!(graph.accepts("a") && graph.accepts("c") && !graph.accepts("d"));
+            assert!(parser("[a-a]").unwrap().accepts("a"));
+            assert_eq!(parse_ast("[z-a]"), Err(ParseError::ReversedRange('z', 'a')));
+            assert_eq!(ParseError::ReversedRange('z', 'a').to_string(), "invalid range z-a: z comes after a");
+            assert_eq!(parse_ast("[a-]"), Err(ParseError::DanglingDash));
+            assert_eq!(parse_ast("[ab"), Err(ParseError::UnclosedClass));
+            assert_eq!(parse_ast("(ab"), Err(ParseError::UnclosedGroup));
+            assert_eq!(parse_ast("ab)"), Err(ParseError::UnmatchedParen));
+            assert_eq!(parse_ast("*a"), Err(ParseError::Unexpected('*')));
+        }
+
+        #[test]
+        fn test_nested_classes() {
+            let graph = parser("[[a-c][x-z]_]").unwrap();
+            for (text, accepted) in [("b", true), ("y", true), ("_", true), ("m", false)] {
+                assert_eq!(graph.accepts(text), accepted, "{}", text);
+            }
+            let graph = parser("[^[a-c]x]").unwrap();
+            assert!(graph.accepts("d") && !graph.accepts("b") && !graph.accepts("x"));
+            // a negated class nested inside is unioned as it stands
+            let graph = parser("[a[^ab]]").unwrap();
+            assert!(graph.accepts("a") && graph.accepts("z") && !graph.accepts("b"));
+            assert_eq!(parse_ast("[a[b]"), Err(ParseError::UnclosedClass));
+        }
+
+        #[test]
+        fn test_escapes_in_classes() {
+            for (pattern, inside, outside) in [("[\\]]", "]", "a"), ("[\\\\]", "\\", "a"), ("[\\n]", "\n", "n"), ("[a\\-z]", "-", "b"), ("[\\[x]", "[", "\\")] {
+                let graph = parser(pattern).unwrap();
+                assert!(graph.accepts(inside), "{} on {:?}", pattern, inside);
+                assert!(!graph.accepts(outside), "{} on {:?}", pattern, outside);
+            }
+            let graph = parser("[!-\\]]").unwrap();
+            assert!(graph.accepts("A") && graph.accepts("]") && !graph.accepts("a"));
+            assert_eq!(parse_ast("[\\"), Err(ParseError::UnclosedClass));
+            assert_eq!(parse_ast("[\\q]"), Err(ParseError::UnsupportedEscape('q')));
+            assert_eq!(parse_ast("\\q"), Err(ParseError::UnsupportedEscape('q')));
+            // the same escapes work outside a class too
+            assert!(parser("a\\-\\t").unwrap().accepts("a-\t"));
+        }
+
+        #[test]
+        #[cfg(feature = "unicode")]
+        fn test_shorthands() {
+            let graph = parser("[\\d\\s_]+").unwrap();
+            for text in ["1 _", "٣\t", "__"] {
+                assert!(graph.accepts(text), "{:?}", text);
+            }
+            assert!(!graph.accepts("a") && !graph.accepts("-"));
+
+            let graph = parser("\\w+").unwrap();
+            assert!(graph.accepts("héllo_wörld9") && graph.accepts("日本") && !graph.accepts("a b"));
+            let graph = parser("[^\\W\\d]").unwrap();
+            assert!(graph.accepts("a") && !graph.accepts("1") && !graph.accepts("-"));
+            let graph = parser("a\\R+b").unwrap();
+            assert!(graph.accepts("a\r\n\u{2028}\rb") && graph.accepts("a\nb") && !graph.accepts("ab"));
+            let graph = parser("\\h+\\v\\H").unwrap();
+            assert!(graph.accepts(" \t\u{3000}\u{2028}x") && !graph.accepts(" \n\t") && !graph.accepts("\n\nx"));
+
+            let graph = parser("[\\p{Klingon}]");
+            assert_eq!(graph, Err(ParseError::UnknownProperty("Klingon".to_string())));
+            let graph = parser("[\\p{Uppercase}\\pN]\\P{Alphabetic}").unwrap();
+            assert!(graph.accepts("É!") && graph.accepts("½ ") && !graph.accepts("é!") && !graph.accepts("Aa"));
+        }
+
+        #[test]
+        #[cfg(feature = "unicode")]
+        fn test_general_categories() {
+            let graph = parser("\\pL+").unwrap();
+            assert!(graph.accepts("héllo日本ǅ") && !graph.accepts("a1") && !graph.accepts("a\u{301}"));
+            assert_eq!(parser("\\p{Letter}+").unwrap(), graph);
+            let graph = parser("\\p{Lu}\\p{ll}").unwrap();
+            assert!(graph.accepts("Éa") && !graph.accepts("ǅa") && !graph.accepts("aE"));
+            assert_eq!(parser("\\p{gc=Uppercase_Letter}\\p{Lowercase Letter}").unwrap(), graph);
+            let graph = parser("\\p{P}+").unwrap();
+            assert!(graph.accepts("!-(«_»)¿") && !graph.accepts("+") && !graph.accepts("$"));
+            let graph = parser("\\p{Sm}\\p{Sc}\\p{Zs}\\p{Mn}\\p{No}\\p{Cf}").unwrap();
+            assert!(graph.accepts("+$\u{3000}\u{301}½\u{200b}") && !graph.accepts("+$ \u{301}5\u{200b}"));
+            assert!(parser("\\p{LC}").unwrap().accepts("ǅ") && !parser("\\p{LC}").unwrap().accepts("ʰ"));
+        }
+
+        #[test]
+        #[cfg(feature = "unicode")]
+        fn test_one_unicode_version() {
+            // U+11F50, a Kawi digit, came in Unicode 15, so none of the classes know it, whatever
+            // version std's char predicates are on
+            for class in ["\\d", "\\w", "\\pN", "\\p{Nd}", "\\pL", "\\p{Alphabetic}"] {
+                assert!(!parser(class).unwrap().accepts("\u{11F50}"), "{}", class);
             }
+            assert!(parser("\\p{Cn}").unwrap().accepts("\u{11F50}"));
+            let graph = parser("\\w+\\s\\p{Alpha}\\p{Lower}\\p{Upper}\\p{Space}").unwrap();
+            assert!(graph.accepts("a\u{301}_١\u{200d}\u{85}ªª\u{24B6}\u{2028}") && !graph.accepts("a\u{301}_١\u{200d}\u{85}1ª\u{24B6}\u{2028}"));
+        }
+
+        #[test]
+        #[cfg(feature = "unicode")]
+        fn test_scripts_and_blocks() {
+            let graph = parser("\\p{Greek}+").unwrap();
+            assert!(graph.accepts("αβγ") && !graph.accepts("αb"));
+            assert_eq!(parser("\\p{sc=Grek}+").unwrap(), graph);
+            // U+0342, a combining mark, is Inherited by script but Greek by script extension
+            assert!(!graph.accepts("α\u{342}") && parser("\\p{scx:greek}+").unwrap().accepts("α\u{342}"));
+            assert!(parser("\\p{Script_Extensions=Han}").unwrap().accepts("\u{3001}"));
+            let graph = parser("\\p{InGreek_and_Coptic}\\p{Block=Latin-1 Supplement}").unwrap();
+            assert!(graph.accepts("\u{3E2}é") && !graph.accepts("αe"));
+            assert!(parser("\\p{Inherited}").unwrap().accepts("\u{300}"));
+            assert_eq!(parser("\\p{blk=Greek}"), Err(ParseError::UnknownProperty("blk=Greek".to_string())));
+        }
+
+        #[test]
+        fn test_class_set_operations() {
+            let graph = parser("[a-z&&[^aeiou]]+").unwrap();
+            assert!(graph.accepts("rhythm") && !graph.accepts("vowel") && !graph.accepts("R"));
+            let graph = parser("[\\w--\\d_]+").unwrap();
+            assert!(graph.accepts("abc") && !graph.accepts("a1") && !graph.accepts("a_"));
+            // left to right, with the ^ over all of it
+            let graph = parser("[^a-z&&[^aeiou]--y]").unwrap();
+            assert!(graph.accepts("a") && graph.accepts("y") && graph.accepts("1") && !graph.accepts("b"));
+            assert!(parser("[!--]").unwrap().accepts("-") && parser("[a&b]").unwrap().accepts("&"));
+            assert!(!parser("[a-c&&]").unwrap().accepts("a"));
+        }
+
+        #[test]
+        fn test_negated_class() {
+            let Ast::Cost(CharCost::Class(class)) = parse_ast("[^b-dx]").unwrap() else { panic!() };
+            assert!(class.is_negated());
+            assert_eq!(class.intervals(), vec![(0, 'b' as u32), ('e' as u32, 'x' as u32), ('y' as u32, char::MAX as u32 + 1)]);
+            assert_eq!(class.complement().complement(), class);
+            // never mistaken for a single char
+            assert!(matches!(parse_ast("[^a]").unwrap().simplify(), Ast::Cost(CharCost::Class(_))));
+            let graph = parser("[^a]").unwrap();
+            assert!(graph.accepts("b") && graph.accepts("\n") && !graph.accepts("a"));
+        }
+
+        #[test]
+        fn test_set_algebra() {
+            let class = |pattern: &str| match parse_ast(pattern).unwrap() {
+                Ast::Cost(CharCost::Class(class)) => class,
+                _ => panic!()
+            };
+            let (letters, vowels) = (class("[a-z]"), class("[aeiou]"));
+            assert_eq!(letters.union(&class("[0-9]")), class("[a-z0-9]"));
+            assert_eq!(vowels.intersect(&class("[a-f]")), class("[ae]"));
+            let consonants = letters.difference(&vowels);
+            assert!(consonants.is_in('b') && !consonants.is_in('e') && !consonants.is_in('B'));
+
+            // a negated side never has to be spelled out
+            let not_vowels = vowels.complement();
+            assert_eq!(letters.intersect(&not_vowels), consonants);
+            assert_eq!(letters.union(&not_vowels), class("[^aeiou]").union(&letters));
+            assert!(letters.union(&not_vowels).intervals() == vec![(0, char::MAX as u32 + 1)]);
+            assert_eq!(not_vowels.intersect(&class("[^x-z]")), class("[^aeioux-z]"));
+            assert!(vowels.difference(&letters).intervals().is_empty());
+        }
+
+        #[test]
+        #[cfg(not(feature = "unicode"))]
+        fn test_ascii_only() {
+            let graph = parser("[\\d\\s_]+").unwrap();
+            assert!(graph.accepts("1 _\u{b}") && !graph.accepts("٣") && !graph.accepts("\u{a0}"));
+            let graph = parser("\\w+").unwrap();
+            assert!(graph.accepts("hello_world9") && !graph.accepts("héllo"));
+            assert!(parser("\\h\\v").unwrap().accepts("\t\r") && !parser("\\h").unwrap().accepts("\u{a0}"));
+            assert!(parser("\\pL").is_err() && parser("\\p{ASCII}").is_ok());
+            let folded = CharCost::Singleton('a').case_folded();
+            assert!(folded.matches('A') && !CharCost::Singleton('é').case_folded().matches('É'));
+        }
+
+        #[test]
+        #[cfg(feature = "unicode")]
+        fn test_case_folded() {
+            assert_eq!(CharCost::Singleton('1').case_folded(), CharCost::Singleton('1'));
+            for (letter, other) in [('a', 'A'), ('é', 'É'), ('Σ', 'σ'), ('ς', 'Σ'), ('ǅ', 'ǆ')] {
+                assert!(CharCost::Singleton(letter).case_folded().matches(other), "{} {}", letter, other);
+            }
+            let mut class = CharClass::new();
+            class.plus_range('à', 'þ');
+            let folded = class.case_folded();
+            assert!(folded.is_in('É') && folded.is_in('é') && folded.is_in('À'));
+            assert!(!folded.is_in('a'));
+        }
+
+        #[test]
+        #[cfg(feature = "unicode")]
+        fn test_case_folding_table() {
+            let mut sorted = case_variants('k');
+            sorted.sort();
+            assert_eq!(sorted, vec!['K', 'k', '\u{212A}']);
+            assert!(case_variants('s').contains(&'ſ') && case_variants('ẞ').contains(&'ß'));
+            assert!(case_variants('ᾈ').contains(&'ᾀ'));
+            // dotless i only uppercases to I, it doesn't fold to i
+            assert_eq!(case_variants('ı'), vec!['ı']);
+            assert!(!case_variants('I').contains(&'ı'));
+            assert!(CASE_FOLDING.windows(2).all(|a| a[0].0 < a[1].0));
+        }
+
+        #[test]
+        fn test_posix_basic() {
+            let config = SyntaxConfig { dialect: Dialect::PosixBasic, ..SyntaxConfig::default() };
+            let basic = |pattern: &str| parse_ast_with(pattern, &config);
+            let default = |pattern: &str| parse_ast(pattern);
+            assert_eq!(basic("\\(ab\\)*c\\|d+"), default("(ab)*c|d\\+"));
+            // * at the start and ^ or $ away from the ends are literal
+            assert_eq!(basic("*a^b$c$"), default("\\*a\\^b\\$c$"));
+            assert_eq!(basic("^*x\\(^y$\\)"), default("^\\*x(^y$)"));
+            assert_eq!(basic("a{1}?"), default("a\\{1}\\?"));
+            assert_eq!(basic("\\d"), Err(ParseError::UnsupportedEscape('d')));
+            assert_eq!(basic("\\(\\?:a\\)"), Err(ParseError::Unexpected('?')));
+            // a plain ? is just a ?, group or not
+            assert_eq!(basic("\\(?:a\\)"), default("(\\?:a)"));
+        }
+
+        #[test]
+        fn test_posix_extended() {
+            let config = SyntaxConfig { dialect: Dialect::PosixExtended, ..SyntaxConfig::default() };
+            let extended = |pattern: &str| parse_ast_with(pattern, &config);
+            let default = |pattern: &str| parse_ast(pattern);
+            assert_eq!(extended("(a|b)+c?$"), default("(a|b)+c?$"));
+            assert_eq!(extended("\\w"), Err(ParseError::UnsupportedEscape('w')));
+            // brackets go by POSIX rather than the default syntax
+            assert_eq!(extended("[]\\a-c]"), default("[\\]\\\\a-c]"));
+            assert_eq!(extended("[^[:digit:][:upper:]_]"), default("[^0-9A-Z_]"));
+            assert_eq!(extended("[[:alpha:]-]"), default("[a-zA-Z\\-]"));
+            assert_eq!(extended("[[:nope:]]"), Err(ParseError::UnknownProperty("nope".to_string())));
+            assert_eq!(extended("[a"), Err(ParseError::UnclosedClass));
+            assert_eq!(extended("a]"), default("a\\]"));
+            assert_eq!(extended("(?:a)"), Err(ParseError::Unexpected('?')));
+            assert_eq!(extended("a(?<name>b)"), Err(ParseError::Unexpected('?')));
+            assert_eq!(extended("(\\?:a)"), default("(\\?:a)"));
+        }
+
+        #[test]
+        fn test_trailing_backslash() {
+            for dialect in [Dialect::Default, Dialect::PosixBasic, Dialect::PosixExtended] {
+                let config = SyntaxConfig { dialect, ..SyntaxConfig::default() };
+                assert_eq!(parse_ast_spanned("ab\\", &config).err(), Some((ParseError::TrailingBackslash, 2..3)), "{:?}", dialect);
+                assert_eq!(parse_ast_spanned("é\\\\\\", &config).err(), Some((ParseError::TrailingBackslash, 4..5)), "{:?}", dialect);
+                assert!(parse_ast_with("a\\\\", &config).is_ok());
+            }
+        }
+
+        #[test]
+        fn test_syntax_config() {
+            let config = SyntaxConfig { builtins: false, looks: false, ..SyntaxConfig::default() };
+            assert!(parser_with("(a|[b-d])+", &config).is_ok());
+            assert_eq!(parser_with("a\\d", &config), Err(ParseError::Disabled("\\d".to_string())));
+            assert_eq!(parser_with("[\\p{L}]", &config), Err(ParseError::Disabled("\\p".to_string())));
+            assert_eq!(parser_with("a$", &config), Err(ParseError::Disabled("$".to_string())));
+            assert_eq!(parser_with("\\bx", &config), Err(ParseError::Disabled("\\b".to_string())));
+            // a ^ that isn't an anchor is fine
+            assert!(parser_with("[^a]", &config).is_ok());
+            let config = SyntaxConfig { looks: false, ..SyntaxConfig::default() };
+            assert!(parser_with("\\w", &config).is_ok());
+            // the bound over the limit is pointed at, in any dialect
+            let config = SyntaxConfig { max_repeat: 100, ..SyntaxConfig::default() };
+            assert!(parser_with("a{100}", &config).is_ok());
+            assert_eq!(parse_ast_spanned("xa{1,100000000}", &config).err(), Some((ParseError::RepeatTooLarge(100), 2..15)));
+            assert_eq!(parse_ast_spanned("a{101,}", &config).err(), Some((ParseError::RepeatTooLarge(100), 1..7)));
+            let config = SyntaxConfig { dialect: Dialect::PosixBasic, ..config };
+            assert_eq!(parse_ast_spanned("a\\{200\\}", &config).err(), Some((ParseError::RepeatTooLarge(100), 1..8)));
+            assert_eq!(parse_ast_spanned("a{1,100000000}", &SyntaxConfig::default()).err(), Some((ParseError::RepeatTooLarge(1000), 1..14)));
+        }
+
+        #[test]
+        fn test_group_syntax() {
+            let ast = parse_ast("(?<year>[0-9]+)-(?:a|b)(?P<c>x)").unwrap();
+            let digits = parse_ast("[0-9]+").unwrap();
+            assert_eq!(ast, Ast::Concat(vec![
+                Ast::Group(Box::new(digits), Some("year".to_string())),
+                Ast::Cost(CharCost::Singleton('-')),
+                parse_ast("a|b").unwrap(),
+                Ast::Group(Box::new(Ast::Cost(CharCost::Singleton('x'))), Some("c".to_string()))
+            ]));
+            assert_eq!(parse_ast("(?<1a>x)"), Err(ParseError::InvalidGroupName("1a".to_string())));
+            assert_eq!(parse_ast("(?<a b>x)"), Err(ParseError::InvalidGroupName("a b".to_string())));
+            assert_eq!(parse_ast("(?<a>x)|(?<a>y)"), Err(ParseError::DuplicateGroupName("a".to_string())));
+            let config = SyntaxConfig { duplicate_names: true, ..SyntaxConfig::default() };
+            assert!(parse_ast_with("(?<a>x)|((?<a>y)|z(?<b>w))+", &config).is_ok());
+            assert_eq!(parse_ast_with("((?<a>x)|y)(?<a>z)", &config), Err(ParseError::DuplicateGroupName("a".to_string())));
+            assert_eq!(parse_ast_with("(?<a>(?<a>x)|y)", &config), Err(ParseError::DuplicateGroupName("a".to_string())));
+            // the group pointed at is the one that clashes, wherever else the name turns up
+            let clash = |name: &str, span| Some((ParseError::DuplicateGroupName(name.to_string()), span));
+            let default = SyntaxConfig::default();
+            assert_eq!(parse_ast_spanned("[<n>](?<n>a)(?<n>b)", &default).err(), clash("n", 14..17));
+            assert_eq!(parse_ast_spanned("[<n>](?<n>a)(?<n>b)", &config).err(), clash("n", 14..17));
+            assert_eq!(parse_ast_spanned("(?<n>a)|(?<n>b)(?<n>c)", &default).err(), clash("n", 10..13));
+            assert_eq!(parse_ast_spanned("(?<n>a)|(?<n>b)(?<n>c)", &config).err(), clash("n", 17..20));
+            assert_eq!(parse_ast_spanned("(?<a>(?P<a>x)|y)", &config).err(), clash("a", 8..11));
+            assert_eq!(parse_ast("(?x)"), Err(ParseError::Unexpected('x')));
+            assert_eq!(parse_ast("(?<a"), Err(ParseError::UnclosedGroup));
         }
 
         #[test]
         fn test_lexer() {
-            let string = "(ac\\||[ab])?".to_string();
-            let lex_string = lexer(string).ok().unwrap();
+            let string = "(ac\\||[ab])?";
+            let lex_string: Vec<Lexeme> = lexer(string).unwrap().into_iter().map(|a| a.0).collect();
             let goal = vec![
                 Lexeme::OpenParen,
                 Lexeme::Literal('a'),
@@ -283,10 +1966,81 @@ pub mod parser {
             assert_eq!(goal, lex_string);
         }
 
+        #[test]
+        fn test_lexer_quoted() {
+            let lex_string: Vec<Lexeme> = lexer("\\Q(a\\\\E|").unwrap().into_iter().map(|a| a.0).collect();
+            assert_eq!(lex_string, vec![Lexeme::Literal('('), Lexeme::Literal('a'), Lexeme::Literal('\\'), Lexeme::Bar]);
+            assert_eq!(lexer("\\Q[").unwrap().len(), 1);
+        }
+
+        #[test]
+        fn test_lexer_comments() {
+            assert_eq!(lexer("a(?#b|c(d)e").unwrap(), vec![(Lexeme::Literal('a'), 0..1), (Lexeme::Comment, 1..10), (Lexeme::Literal('e'), 10..11)]);
+            assert_eq!(parse_ast("a(?#b|c(d)e"), parse_ast("ae"));
+            assert!(parser("x(?# any number of them )+").unwrap().accepts("xxx"));
+            assert_eq!(parse_ast("a(?#b"), Err(ParseError::UnclosedGroup));
+            assert_eq!(parse_ast("[(?#]"), parse_ast("[#(?]"));
+        }
+
+        #[test]
+        fn test_counted_repetition() {
+            let lexemes = |pattern: &str| lexer(pattern).map(|a| a.into_iter().map(|x| x.0).collect::<Vec<_>>());
+            assert_eq!(lexemes("a{2}b{1,}c{0,3}"), Ok(vec![
+                Lexeme::Literal('a'),
+                Lexeme::Counted(2, Some(2)),
+                Lexeme::Literal('b'),
+                Lexeme::Counted(1, None),
+                Lexeme::Literal('c'),
+                Lexeme::Counted(0, Some(3))
+            ]));
+            // a brace that doesn't start a count is just a brace
+            assert_eq!(parse_ast("a{,2}b{x}"), parse_ast("a\\{,2\\}b\\{x\\}"));
+            assert_eq!(lexemes("a{3,2}"), Err(ParseError::ReversedRepeat(3, 2)));
+            assert_eq!(lexemes("a{99999999999}"), Err(ParseError::RepeatTooLarge(u32::MAX)));
+            let graph = parser("x\\d{2,4}").unwrap();
+            assert!(graph.accepts("x12") && graph.accepts("x1234"));
+            assert!(!graph.accepts("x1") && !graph.accepts("x12345"));
+            // however many times \d is counted, it's compiled once
+            assert_eq!(parser("\\d{1,1000}").unwrap().arena.len(), parser("\\d{1,5}").unwrap().arena.len());
+            let basic = SyntaxConfig { dialect: Dialect::PosixBasic, ..SyntaxConfig::default() };
+            assert_eq!(parse_ast_with("a\\{2,\\}", &basic), parse_ast("a{2,}"));
+        }
+
+        #[test]
+        fn test_lexer_octal() {
+            let lex_string: Vec<Lexeme> = lexer("\\0\\07\\1018\\0123").unwrap().into_iter().map(|a| a.0).collect();
+            let expected = ['\0', '\u{7}', 'A', '8', '\u{a}', '3'].map(Lexeme::Literal);
+            assert_eq!(lex_string, expected);
+            assert!(parser("[\\060-\\071]+").unwrap().accepts("0129"));
+            // too short for octal, so a backreference, which isn't supported
+            assert_eq!(parse_ast("(a)\\1"), Err(ParseError::UnsupportedEscape('1')));
+            assert_eq!(parse_ast("\\18"), Err(ParseError::UnsupportedEscape('1')));
+        }
+
+        #[test]
+        fn test_lexer_control() {
+            let lex_string: Vec<Lexeme> = lexer("\\cA\\cz").unwrap().into_iter().map(|a| a.0).collect();
+            assert_eq!(lex_string, ['\u{1}', '\u{1a}'].map(Lexeme::Literal));
+            assert!(parser("[\\cH\\cI]+").unwrap().accepts("\u{8}\t"));
+            assert_eq!(parse_ast("\\c1"), Err(ParseError::UnsupportedEscape('c')));
+        }
+
+        #[test]
+        #[cfg(feature = "unicode")]
+        fn test_lexer_char_names() {
+            let lex_string: Vec<Lexeme> = lexer("\\N{BULLET}\\N{latin small letter a}\\N{CJK UNIFIED IDEOGRAPH-4E2D}").unwrap().into_iter().map(|a| a.0).collect();
+            assert_eq!(lex_string, ['•', 'a', '中'].map(Lexeme::Literal));
+            assert!(parser("[\\N{HANGUL SYLLABLE GAG}-\\N{HANGUL SYLLABLE HIH}]+").unwrap().accepts("각한힣"));
+            assert!(parser("\\N{ZERO WIDTH JOINER}").unwrap().accepts("\u{200d}"));
+            assert_eq!(parse_ast("\\N{NOT A CHAR}"), Err(ParseError::UnknownCharName("NOT A CHAR".to_string())));
+            assert_eq!(parse_ast("\\N{CJK UNIFIED IDEOGRAPH-0041}"), Err(ParseError::UnknownCharName("CJK UNIFIED IDEOGRAPH-0041".to_string())));
+            assert_eq!(parse_ast("\\N{BULLET"), Err(ParseError::UnknownCharName("BULLET".to_string())));
+        }
+
         #[test]
         fn test_lexer_class() {
-            let string = "(()[a?b[])".to_string();
-            let lex_string = lexer(string).ok().unwrap();
+            let string = "(()[a?b[(]])";
+            let lex_string: Vec<Lexeme> = lexer(string).unwrap().into_iter().map(|a| a.0).collect();
             let goal = vec![
                 Lexeme::OpenParen,
                 Lexeme::OpenParen,
@@ -295,7 +2049,9 @@ pub mod parser {
                 Lexeme::Literal('a'),
                 Lexeme::Literal('?'),
                 Lexeme::Literal('b'),
-                Lexeme::Literal('['),
+                Lexeme::OpenBracket,
+                Lexeme::Literal('('),
+                Lexeme::CloseBracket,
                 Lexeme::CloseBracket,
                 Lexeme::CloseParen
             ];
@@ -304,8 +2060,8 @@ pub mod parser {
 
         #[test]
         fn test_lexer_in_class_ranges() {
-            let string = "[][a-zssA-)]".to_string();
-            let lex_string = lexer(string).ok().unwrap();
+            let string = "[][a-zssA-)]";
+            let lex_string: Vec<Lexeme> = lexer(string).unwrap().into_iter().map(|a| a.0).collect();
             let goal = vec![
                 Lexeme::OpenBracket,
                 Lexeme::CloseBracket,
@@ -319,22 +2075,55 @@ pub mod parser {
             assert_eq!(goal, lex_string)
         }
 
+        #[test]
+        fn test_lexer_spans() {
+            fn spans(regex: &str, dialect: Dialect) -> Vec<(Lexeme, &str)> {
+                lex_dialect(regex, dialect).unwrap().into_iter().map(|(a, x)| (a, &regex[x])).collect()
+            }
+            assert_eq!(spans("é\\d[^a-cé\\]]+", Dialect::Default), vec![
+                (Lexeme::Literal('é'), "é"),
+                (Lexeme::Builtin('d'), "\\d"),
+                (Lexeme::OpenBracket, "["),
+                (Lexeme::Literal('^'), "^"),
+                (Lexeme::Range('a', 'c'), "a-c"),
+                (Lexeme::Literal('é'), "é"),
+                (Lexeme::Literal(']'), "\\]"),
+                (Lexeme::CloseBracket, "]"),
+                (Lexeme::Plus, "+")
+            ]);
+            assert_eq!(spans("\\(x\\)[[:digit:]]", Dialect::PosixBasic), vec![
+                (Lexeme::OpenParen, "\\("),
+                (Lexeme::Literal('x'), "x"),
+                (Lexeme::CloseParen, "\\)"),
+                (Lexeme::OpenBracket, "["),
+                (Lexeme::Range('0', '9'), "[:digit:]"),
+                (Lexeme::CloseBracket, "]")
+            ]);
+            // the same pattern is a count in a basic one and plain text in the default syntax
+            let basic = SyntaxConfig { dialect: Dialect::PosixBasic, ..SyntaxConfig::default() };
+            assert_eq!(lexer_with("a\\{2\\}", &basic), Ok(vec![(Lexeme::Literal('a'), 0..1), (Lexeme::Counted(2, Some(2)), 1..6)]));
+            assert_eq!(lexer_with("a\\{2\\}", &SyntaxConfig::default()), lexer("a\\{2\\}"));
+            assert_eq!(lexer("a\\{2\\}").unwrap().len(), 4);
+        }
+
         #[test]
         fn test_parser() {
-            let regex = "([abcd]|a|b|c|d)+".to_string();
+            let regex = "([abcd]|a|b|c|d)+";
             let graph = parser(regex).ok().unwrap();
             let mut goal = Graph::new();
-            goal.add_cost(CharCost::Class(CharClass {chars: vec!['a','b','c','d'], ranges: vec![]}));
-            goal.add_junction(0);
+            let start = goal.start;
+            goal.add_cost(CharCost::Class(CharClass {ranges: vec![('a' as u32, 'd' as u32)], negated: false}));
+            goal.add_junction(start);
             goal.add_cost(CharCost::Singleton('a'));
-            goal.add_junction(0);
+            goal.add_junction(start);
             goal.add_cost(CharCost::Singleton('b'));
-            goal.add_junction(0);
+            goal.add_junction(start);
             goal.add_cost(CharCost::Singleton('c'));
-            goal.add_junction(0);
+            goal.add_junction(start);
             goal.add_cost(CharCost::Singleton('d'));
-            goal.close_junction(0);
-            goal.one_or_more(0);
+            goal.close_junction(start);
+            goal.one_or_more(start);
+            goal.mark_accepting();
 
             assert_eq!(goal, graph);
         }
@@ -0,0 +1,368 @@
+pub mod pikevm {
+    use crate::ast::ast::{Ast, Qualifier};
+    use crate::automata::automata::Numbering;
+    use crate::engine::engine::{Engine, Metrics};
+    use crate::haystack::haystack::Haystack;
+    use crate::parser::parser::{CharCost, Count, Step};
+    use crate::util::util::SparseSet;
+
+    // the graph forgets where groups were, so capturing runs over its own little program instead,
+    // compiled straight from the unsimplified ast. Targets are instruction indices.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub(crate) enum Inst {
+        Cost(CharCost, usize),
+        // try the first target before the second
+        Split(usize, usize),
+        Jump(usize),
+        // record the current offset in a slot; group i starts in slot 2i and ends in 2i + 1
+        Save(usize, usize),
+        Match
+    }
+
+    // how far some input got, for checking it as it's typed
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub enum PrefixMatch {
+        // the whole of the input matches
+        Match,
+        // it doesn't yet, but some more input could make it
+        Partial,
+        // nothing added on the end will ever make it match
+        Dead
+    }
+
+    // a target to be filled in once the code it points past has been emitted
+    const HOLE: usize = usize::MAX;
+
+    // a path through the program: the instruction it's at, the counts of the counted repeats it's
+    // inside, and its slots
+    type Thread = (usize, usize, Vec<Option<usize>>);
+
+    #[derive(Debug, Clone)]
+    pub struct PikeVm {
+        pub(crate) insts: Vec<Inst>,
+        pub(crate) slot_count: usize,
+        // a state is an instruction and the counts it's at, numbered as the simulator's are
+        numbering: Numbering
+    }
+
+    impl PikeVm {
+        pub fn new(ast: &Ast) -> Self {
+            let mut pikevm = PikeVm {
+                insts: vec![Inst::Save(0, 1)],
+                slot_count: 2,
+                numbering: Numbering::default()
+            };
+            pikevm.emit(ast);
+            pikevm.push(Inst::Save(1, pikevm.insts.len() + 1));
+            pikevm.push(Inst::Match);
+            let insts = &pikevm.insts;
+            pikevm.numbering = Numbering::new(insts.len(), 0, |index| match &insts[index] {
+                Inst::Cost(cost, next) => vec![(*next, match cost {
+                    CharCost::Count(count) => Some(*count),
+                    _ => None
+                })],
+                Inst::Split(first, second) => vec![(*first, None), (*second, None)],
+                Inst::Jump(next) | Inst::Save(_, next) => vec![(*next, None)],
+                Inst::Match => vec![]
+            });
+            pikevm
+        }
+
+        fn push(&mut self, inst: Inst) -> usize {
+            self.insts.push(inst);
+            self.insts.len() - 1
+        }
+
+        // points every HOLE in the instruction at index to where the next instruction will go
+        fn patch(&mut self, index: usize) {
+            let here = self.insts.len();
+            match &mut self.insts[index] {
+                Inst::Split(_, a) | Inst::Jump(a) if *a == HOLE => *a = here,
+                _ => unreachable!()
+            }
+        }
+
+        // appends code for ast which falls through to whatever comes after it
+        fn emit(&mut self, ast: &Ast) {
+            match ast {
+                Ast::Empty => {}
+                Ast::Cost(CharCost::Literal(literal)) => {
+                    for letter in literal.chars() {
+                        self.push(Inst::Cost(CharCost::Singleton(letter), self.insts.len() + 1));
+                    }
+                }
+                Ast::Cost(cost) => {
+                    self.push(Inst::Cost(cost.clone(), self.insts.len() + 1));
+                }
+                Ast::Concat(items) => {
+                    for item in items {
+                        self.emit(item);
+                    }
+                }
+                Ast::Group(inner, _) => {
+                    // numbered by where the open paren is, so claim the number before the inside
+                    let group = self.slot_count / 2;
+                    self.slot_count += 2;
+                    self.push(Inst::Save(2 * group, self.insts.len() + 1));
+                    self.emit(inner);
+                    self.push(Inst::Save(2 * group + 1, self.insts.len() + 1));
+                }
+                Ast::Alternation(branches) => {
+                    let mut jumps = vec![];
+                    for (index, branch) in branches.iter().enumerate() {
+                        if index + 1 == branches.len() {
+                            self.emit(branch);
+                            break;
+                        }
+                        let split = self.push(Inst::Split(self.insts.len() + 1, HOLE));
+                        self.emit(branch);
+                        jumps.push(self.push(Inst::Jump(HOLE)));
+                        self.patch(split);
+                    }
+                    for jump in jumps {
+                        self.patch(jump);
+                    }
+                }
+                Ast::Repeat(inner, Qualifier::ZeroOrOne) => {
+                    let split = self.push(Inst::Split(self.insts.len() + 1, HOLE));
+                    self.emit(inner);
+                    self.patch(split);
+                }
+                Ast::Repeat(inner, Qualifier::ZeroOrMore) => {
+                    let split = self.push(Inst::Split(self.insts.len() + 1, HOLE));
+                    self.emit(inner);
+                    self.push(Inst::Jump(split));
+                    self.patch(split);
+                }
+                Ast::Repeat(inner, Qualifier::OneOrMore) => {
+                    let body = self.insts.len();
+                    self.emit(inner);
+                    self.push(Inst::Split(body, self.insts.len() + 1));
+                }
+                // nothing gets in, but the groups inside still take their numbers
+                Ast::Repeat(inner, Qualifier::Counted(_, Some(0))) => {
+                    let jump = self.push(Inst::Jump(HOLE));
+                    self.emit(inner);
+                    self.patch(jump);
+                }
+                // the same Count edges as the graph gets, with going round again tried first
+                Ast::Repeat(inner, Qualifier::Counted(min, max)) => {
+                    let count = |step| CharCost::Count(Count { step, min: *min, max: *max });
+                    let split = (*min == 0).then(|| self.push(Inst::Split(self.insts.len() + 1, HOLE)));
+                    let body = self.push(Inst::Cost(count(Step::Enter), self.insts.len() + 1)) + 1;
+                    self.emit(inner);
+                    self.push(Inst::Split(self.insts.len() + 1, self.insts.len() + 2));
+                    self.push(Inst::Cost(count(Step::Again), body));
+                    self.push(Inst::Cost(count(Step::Leave), self.insts.len() + 1));
+                    if let Some(split) = split {
+                        self.patch(split);
+                    }
+                }
+            }
+        }
+
+        // group 0 included
+        pub fn group_count(&self) -> usize {
+            self.slot_count / 2
+        }
+
+        // the slots of the highest priority path that matches exactly haystack[start..end], where
+        // the span itself comes from one of the matching engines. Threads are kept in priority order
+        // and a state only gets its first thread, so this is linear in the span times the program.
+        pub fn captures_at(&self, haystack: &str, start: usize, end: usize) -> Option<Vec<Option<usize>>> {
+            let mut seen = SparseSet::new(self.numbering.capacity());
+            let mut threads = vec![];
+            self.add_thread(&mut threads, &mut seen, haystack, (0, 0, vec![None; self.slot_count]), start);
+            for (offset, letter) in haystack[start..end].char_indices() {
+                let position = start + offset + letter.len_utf8();
+                let mut next_threads = vec![];
+                seen.clear();
+                for (index, counts, slots) in threads {
+                    if let Inst::Cost(cost, next) = &self.insts[index] {
+                        if cost.matches(letter) {
+                            self.add_thread(&mut next_threads, &mut seen, haystack, (*next, counts, slots), position);
+                        }
+                    }
+                }
+                threads = next_threads;
+            }
+            threads.into_iter().find(|(index, _, _)| self.insts[*index] == Inst::Match).map(|(_, _, slots)| slots)
+        }
+
+        // whether the whole of haystack matches, or is the start of something that would. Looks
+        // are checked against haystack as it stands, except those at its very end, which more
+        // input could change, so a few dead ends get called Partial, but never the other way round.
+        pub fn prefix_match(&self, haystack: &str) -> PrefixMatch {
+            let mut seen = SparseSet::new(self.numbering.capacity());
+            let mut threads = vec![];
+            // where the threads went before the looks at the end were checked
+            let mut heads = vec![0];
+            self.add_thread(&mut threads, &mut seen, haystack, (0, 0, vec![None; self.slot_count]), 0);
+            for (offset, letter) in haystack.char_indices() {
+                let position = offset + letter.len_utf8();
+                let mut next_threads = vec![];
+                seen.clear();
+                heads.clear();
+                for (index, counts, slots) in threads {
+                    if let Inst::Cost(cost, next) = &self.insts[index] {
+                        if cost.matches(letter) {
+                            heads.push(*next);
+                            self.add_thread(&mut next_threads, &mut seen, haystack, (*next, counts, slots), position);
+                        }
+                    }
+                }
+                if heads.is_empty() {
+                    return PrefixMatch::Dead;
+                }
+                threads = next_threads;
+            }
+            if threads.iter().any(|(index, _, _)| self.insts[*index] == Inst::Match) {
+                return PrefixMatch::Match;
+            }
+            match self.can_finish(&heads) {
+                true => PrefixMatch::Partial,
+                false => PrefixMatch::Dead
+            }
+        }
+
+        // some path from one of heads reaches the Match, taking every look as one that holds and
+        // every count as one that lets it through
+        fn can_finish(&self, heads: &[usize]) -> bool {
+            let mut seen = SparseSet::new(self.insts.len());
+            let mut stack = heads.to_vec();
+            while let Some(index) = stack.pop() {
+                if !seen.insert(index) {
+                    continue;
+                }
+                match &self.insts[index] {
+                    Inst::Match => return true,
+                    Inst::Jump(next) | Inst::Save(_, next) => stack.push(*next),
+                    Inst::Split(first, second) => stack.extend([*first, *second]),
+                    // an empty class is an edge nothing can cross
+                    Inst::Cost(cost, next) => {
+                        if cost.reads_nothing() || !cost.intervals().is_empty() {
+                            stack.push(*next);
+                        }
+                    }
+                }
+            }
+            false
+        }
+
+        // follows every path from thread that reads nothing, in priority order, adding a thread for
+        // each Cost or Match it comes to. Only the first path to reach a state goes on from it. The
+        // paths are kept on a stack rather than followed by recursion, as a counted repeat whose
+        // body can be empty can go round as many times as it allows without reading anything.
+        fn add_thread<H: Haystack + ?Sized>(&self, threads: &mut Vec<Thread>, seen: &mut SparseSet, haystack: &H, thread: Thread, position: usize) {
+            let mut stack = vec![thread];
+            while let Some((index, counts, mut slots)) = stack.pop() {
+                if !seen.insert(self.numbering.id(index, counts)) {
+                    continue;
+                }
+                match &self.insts[index] {
+                    Inst::Jump(next) => stack.push((*next, counts, slots)),
+                    Inst::Split(first, second) => {
+                        stack.push((*second, counts, slots.clone()));
+                        stack.push((*first, counts, slots));
+                    }
+                    Inst::Save(slot, next) => {
+                        slots[*slot] = Some(position);
+                        stack.push((*next, counts, slots));
+                    }
+                    Inst::Cost(CharCost::Look(look), next) => {
+                        if look.holds(haystack, position) {
+                            stack.push((*next, counts, slots));
+                        }
+                    }
+                    Inst::Cost(CharCost::Count(count), next) => {
+                        if let Some(counts) = count.counts_after(counts) {
+                            stack.push((*next, counts, slots));
+                        }
+                    }
+                    Inst::Cost(_, _) | Inst::Match => threads.push((index, counts, slots))
+                }
+            }
+        }
+    }
+
+    impl Engine for PikeVm {
+        // where the longest match from position ends, whatever the priorities say, so it finds the
+        // same matches as the automata. Every thread carries its slots along for nothing here, so
+        // this is only worth it when no other engine will do.
+        fn longest_match_counted<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, metrics: &mut Metrics) -> Option<usize> {
+            let mut seen = SparseSet::new(self.numbering.capacity());
+            let mut threads = vec![];
+            self.add_thread(&mut threads, &mut seen, haystack, (0, 0, vec![None; self.slot_count]), position);
+            let mut offset = position;
+            let mut last = None;
+            while !threads.is_empty() {
+                if threads.iter().any(|(index, _, _)| self.insts[*index] == Inst::Match) {
+                    last = Some(offset);
+                }
+                let Some((letter, length)) = haystack.char_at(offset) else { break };
+                offset += length;
+                let mut next_threads = vec![];
+                seen.clear();
+                for (index, counts, slots) in threads {
+                    if let Inst::Cost(cost, next) = &self.insts[index] {
+                        if cost.matches(letter) {
+                            metrics.transitions += 1;
+                            self.add_thread(&mut next_threads, &mut seen, haystack, (*next, counts, slots), offset);
+                        }
+                    }
+                }
+                threads = next_threads;
+            }
+            last
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::parser::parser::parse_ast;
+
+        fn captures(pattern: &str, haystack: &str) -> Option<Vec<Option<usize>>> {
+            PikeVm::new(&parse_ast(pattern).unwrap()).captures_at(haystack, 0, haystack.len())
+        }
+
+        #[test]
+        fn test_captures() {
+            assert_eq!(captures("(a+)(b*)", "aab"), Some(vec![Some(0), Some(3), Some(0), Some(2), Some(2), Some(3)]));
+            // the group in the branch not taken never gets set
+            assert_eq!(captures("(a)|(b)", "b"), Some(vec![Some(0), Some(1), None, None, Some(0), Some(1)]));
+            // nested groups are numbered by their open paren, and a repeat keeps the last iteration
+            assert_eq!(captures("((a)|b)*", "ab"), Some(vec![Some(0), Some(2), Some(1), Some(2), Some(0), Some(1)]));
+            // ambiguity is settled in favour of the greedy choice
+            assert_eq!(captures("(a*)(a*)", "aa"), Some(vec![Some(0), Some(2), Some(0), Some(2), Some(2), Some(2)]));
+            assert_eq!(captures("(a)b", "ab c"), None);
+            assert_eq!(captures("(^a|b)+", "ba"), None);
+            assert_eq!(captures("(^a|b)+", "ab"), Some(vec![Some(0), Some(2), Some(1), Some(2)]));
+        }
+
+        #[test]
+        fn test_counted_captures() {
+            // the second time round a* only matches empty, which is what the group keeps
+            assert_eq!(captures("(a*){2}(x)", "ax"), Some(vec![Some(0), Some(2), Some(1), Some(1), Some(1), Some(2)]));
+            assert_eq!(captures("(a|b){2,3}", "abba"), None);
+            assert_eq!(captures("(a|b){2,3}", "aba"), Some(vec![Some(0), Some(3), Some(2), Some(3)]));
+            assert_eq!(captures("a{0}(b)", "b"), Some(vec![Some(0), Some(1), Some(0), Some(1)]));
+            // the program is the same size however high the count goes
+            let len = |pattern: &str| PikeVm::new(&parse_ast(pattern).unwrap()).insts.len();
+            assert_eq!(len("\\d{1,1000}"), len("\\d{1,5}"));
+        }
+
+        #[test]
+        fn test_prefix_match() {
+            let prefix = |pattern: &str, haystack: &str| PikeVm::new(&parse_ast(pattern).unwrap()).prefix_match(haystack);
+            assert_eq!(prefix("[0-9]+-[0-9]+", ""), PrefixMatch::Partial);
+            assert_eq!(prefix("[0-9]+-[0-9]+", "12-"), PrefixMatch::Partial);
+            assert_eq!(prefix("[0-9]+-[0-9]+", "12-3"), PrefixMatch::Match);
+            assert_eq!(prefix("[0-9]+-[0-9]+", "12x"), PrefixMatch::Dead);
+            // the \B doesn't hold after the a yet, but will once a b comes
+            assert_eq!(prefix("a\\Bb", "a"), PrefixMatch::Partial);
+            assert_eq!(prefix("a\\bb", "ab"), PrefixMatch::Dead);
+            assert_eq!(prefix("a[^\\s\\S]", "a"), PrefixMatch::Dead);
+        }
+    }
+}
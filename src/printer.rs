@@ -0,0 +1,124 @@
+pub mod printer {
+    use std::io::{self, Write};
+
+    use crate::regex::regex::Regex;
+
+    const HIGHLIGHT: &str = "\x1b[1;31m";
+    const RESET: &str = "\x1b[0m";
+
+    // prints the lines a regex matches the way grep does: matches highlighted, N: before a
+    // matching line and N- before a context line, and -- wherever lines were skipped between
+    // two runs of context
+    #[derive(Debug, Clone, PartialEq, Eq, Default)]
+    pub struct Printer {
+        color: bool,
+        line_numbers: bool,
+        // lines shown before and after each matching line
+        context: usize
+    }
+
+    impl Printer {
+        pub fn new() -> Self {
+            Printer::default()
+        }
+
+        // wrap every match in ANSI escapes, for a terminal
+        pub fn color(&mut self, yes: bool) -> &mut Self {
+            self.color = yes;
+            self
+        }
+
+        pub fn line_numbers(&mut self, yes: bool) -> &mut Self {
+            self.line_numbers = yes;
+            self
+        }
+
+        pub fn context(&mut self, lines: usize) -> &mut Self {
+            self.context = lines;
+            self
+        }
+
+        pub fn print(&self, regex: &Regex, haystack: &str, out: &mut impl Write) -> io::Result<()> {
+            let found = regex.find_lines(haystack);
+            // lines are split and written out ending with whatever regex ends them with, as grep -z
+            // does with \0
+            let terminator = regex.line_terminator();
+            let mut last = None;
+            for (index, line) in haystack.split_inclusive(terminator).enumerate() {
+                let line = line.strip_suffix(terminator).unwrap_or(line);
+                let number = index + 1;
+                // the first matching line that isn't too far back, if it isn't too far ahead either
+                let next = found.partition_point(|a| a.number + self.context < number);
+                if found.get(next).is_none_or(|a| a.number > number + self.context) {
+                    continue;
+                }
+                if self.context > 0 && last.is_some_and(|a| a + 1 < number) {
+                    writeln!(out, "--")?;
+                }
+                last = Some(number);
+                let matched = found[next..].iter().take_while(|a| a.number <= number).find(|a| a.number == number);
+                let Some(matched) = matched else {
+                    self.prefix(out, number, '-')?;
+                    write!(out, "{}{}", line, terminator)?;
+                    continue;
+                };
+                self.prefix(out, number, ':')?;
+                let line_start = matched.span.start;
+                let mut written = 0;
+                for range in matched.matches.iter().map(|a| a.start() - line_start..a.end() - line_start) {
+                    match self.color && !range.is_empty() {
+                        true => write!(out, "{}{}{}{}", &line[written..range.start], HIGHLIGHT, &line[range.clone()], RESET)?,
+                        false => write!(out, "{}", &line[written..range.end])?
+                    }
+                    written = range.end;
+                }
+                write!(out, "{}{}", &line[written..], terminator)?;
+            }
+            Ok(())
+        }
+
+        fn prefix(&self, out: &mut impl Write, number: usize, separator: char) -> io::Result<()> {
+            if self.line_numbers {
+                write!(out, "{}{}", number, separator)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::regex::regex::RegexBuilder;
+
+        fn printed(printer: &Printer, pattern: &str, haystack: &str) -> String {
+            let mut out = vec![];
+            printer.print(&Regex::new(pattern).unwrap(), haystack, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        }
+
+        #[test]
+        fn test_color() {
+            let haystack = "one cat\ndog\ncat and cat\n";
+            assert_eq!(printed(&Printer::new(), "cat", haystack), "one cat\ncat and cat\n");
+            let colored = printed(Printer::new().color(true), "cat", haystack);
+            assert_eq!(colored, "one \x1b[1;31mcat\x1b[0m\n\x1b[1;31mcat\x1b[0m and \x1b[1;31mcat\x1b[0m\n");
+        }
+
+        #[test]
+        fn test_context() {
+            let haystack = "a\nb\nc\nx\nd\ne\nf\ng\nx";
+            let printer = Printer::new().line_numbers(true).context(1).clone();
+            assert_eq!(printed(&printer, "x", haystack), "3-c\n4:x\n5-d\n--\n8-g\n9:x\n");
+            let printer = Printer::new().line_numbers(true).context(2).clone();
+            assert_eq!(printed(&printer, "x", haystack), "2-b\n3-c\n4:x\n5-d\n6-e\n7-f\n8-g\n9:x\n");
+        }
+
+        #[test]
+        fn test_line_terminator() {
+            let regex = RegexBuilder::new("^a").multi_line(true).line_terminator(b'\0').build().unwrap();
+            let mut out = vec![];
+            Printer::new().line_numbers(true).print(&regex, "ab\nc\0d\0a\0", &mut out).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), "1:ab\nc\x003:a\x00");
+        }
+    }
+}
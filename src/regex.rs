@@ -0,0 +1,1662 @@
+pub mod regex {
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use std::fmt;
+    use std::io::{self, Read, Write};
+    use std::ops::Range;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use crate::ast::ast::{Ast, GroupInfo};
+    use crate::automata::automata::{char_range, find_all, has_inner_occurrence, match_spans, next_boundary, EmptyMatches, MatchData};
+    use crate::dfa::dfa::{start_bytes, DenseDfa, LazyDfa, SparseDfa};
+    use crate::engine::engine::{Engine, Metrics, Nfa, SearchConfig};
+    use crate::haystack::haystack::{Haystack, Reversed};
+    use crate::onepass::onepass::OnePass;
+    use crate::parser::parser::{graph_from_ast, has_uppercase_literal, parse_ast_with, CharCost, Dialect, Look, ParseError, SyntaxConfig};
+    use crate::pikevm::pikevm::{PikeVm, PrefixMatch};
+    use crate::util::util::{memchr, with_spares, SparseSet, Spares};
+
+    // which automaton, if any, gets built up front to do the matching
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub enum DfaKind {
+        // simulate the NFA directly
+        #[default]
+        Off,
+        Dense,
+        // slower per byte than Dense but much smaller
+        Sparse,
+        // built as searches go, and kept, so only the states a haystack needs ever get made
+        Lazy
+    }
+
+    enum Program {
+        Nfa(Nfa),
+        Dense(Box<DenseDfa>),
+        Sparse(SparseDfa),
+        Lazy(Box<LazyDfa>),
+        // a fixed string, searched for directly
+        Literal(String)
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub struct RegexBuilder {
+        pattern: String,
+        syntax: SyntaxConfig,
+        dfa: DfaKind,
+        case_insensitive: bool,
+        ascii_case: bool,
+        smart_case: bool,
+        multi_line: bool,
+        crlf: bool,
+        line_terminator: u8,
+        whole_word: bool,
+        fixed_string: bool,
+        empty_matches: EmptyMatches,
+        earliest: bool,
+        dfa_size_limit: usize,
+        step_limit: Option<usize>,
+        #[cfg(feature = "unicode")]
+        normalization_insensitive: bool
+    }
+
+    impl RegexBuilder {
+        pub fn new(pattern: &str) -> Self {
+            RegexBuilder {
+                pattern: pattern.to_string(),
+                syntax: SyntaxConfig::default(),
+                dfa: DfaKind::default(),
+                case_insensitive: false,
+                ascii_case: false,
+                smart_case: false,
+                multi_line: false,
+                crlf: false,
+                line_terminator: b'\n',
+                whole_word: false,
+                fixed_string: false,
+                empty_matches: EmptyMatches::default(),
+                earliest: false,
+                dfa_size_limit: 10 << 20,
+                step_limit: None,
+                #[cfg(feature = "unicode")]
+                normalization_insensitive: false
+            }
+        }
+
+        // what the pattern may use; the fixed string mode doesn't look at it
+        pub fn syntax(&mut self, syntax: SyntaxConfig) -> &mut Self {
+            self.syntax = syntax;
+            self
+        }
+
+        pub fn dialect(&mut self, dialect: Dialect) -> &mut Self {
+            self.syntax.dialect = dialect;
+            self
+        }
+
+        pub fn dfa(&mut self, kind: DfaKind) -> &mut Self {
+            self.dfa = kind;
+            self
+        }
+
+        // the most bytes a DFA may take. One that would need more isn't built, or for a lazy DFA
+        // isn't grown any further, and the graph is simulated instead. 10 MiB unless set.
+        pub fn dfa_size_limit(&mut self, bytes: usize) -> &mut Self {
+            self.dfa_size_limit = bytes;
+            self
+        }
+
+        // the most steps, transitions and epsilon expansions as Metrics counts them, a search by
+        // one of the try_ methods may take before giving up. The rest never give up.
+        pub fn step_limit(&mut self, steps: Option<usize>) -> &mut Self {
+            self.step_limit = steps;
+            self
+        }
+
+        pub fn case_insensitive(&mut self, yes: bool) -> &mut Self {
+            self.case_insensitive = yes;
+            self
+        }
+
+        // with case_insensitive, only ASCII letters match in either case, for haystacks known to
+        // be ASCII, where the full Unicode folding would only make bigger classes for nothing
+        pub fn ascii_case(&mut self, yes: bool) -> &mut Self {
+            self.ascii_case = yes;
+            self
+        }
+
+        // case insensitive unless the pattern has an uppercase char in it, like ripgrep's
+        // --smart-case. Only chars written out count, not \W or \p{Lu}.
+        pub fn smart_case(&mut self, yes: bool) -> &mut Self {
+            self.smart_case = yes;
+            self
+        }
+
+        // ^ and $ match at the start and end of every line, not just the haystack
+        pub fn multi_line(&mut self, yes: bool) -> &mut Self {
+            self.multi_line = yes;
+            self
+        }
+
+        // in multi line mode, lines end at \r\n or \r as well as \n
+        pub fn crlf(&mut self, yes: bool) -> &mut Self {
+            self.crlf = yes;
+            self
+        }
+
+        // what ends a line for . and for ^ and $ in multi line mode, in place of \n, like \0 for
+        // grep -z. It has to be ASCII, as a byte past that is never a whole char of UTF-8.
+        pub fn line_terminator(&mut self, terminator: u8) -> &mut Self {
+            assert!(terminator.is_ascii(), "line terminator {:#x} isn't ASCII", terminator);
+            self.line_terminator = terminator;
+            self
+        }
+
+        // only matches with a word boundary at both ends, like grep -w. Same as wrapping the
+        // pattern in \b(?:...)\b, without touching the group numbers.
+        pub fn whole_word(&mut self, yes: bool) -> &mut Self {
+            self.whole_word = yes;
+            self
+        }
+
+        // the whole pattern is one literal string, like grep -F, so nothing in it is special
+        pub fn fixed_string(&mut self, yes: bool) -> &mut Self {
+            self.fixed_string = yes;
+            self
+        }
+
+        // whether find_all and count keep an empty match right after the previous match
+        pub fn empty_matches(&mut self, policy: EmptyMatches) -> &mut Self {
+            self.empty_matches = policy;
+            self
+        }
+
+        // every match ends as soon as the pattern has matched anything, rather than running on
+        // to the longest it could, which is quicker when all that matters is where matches start
+        // or whether there are any. Whole-haystack checks like full_match are unaffected.
+        pub fn earliest(&mut self, yes: bool) -> &mut Self {
+            self.earliest = yes;
+            self
+        }
+
+        // lets groups in different branches of an alternation share a name, for Captures::name to
+        // find under it whichever one matched
+        pub fn duplicate_names(&mut self, yes: bool) -> &mut Self {
+            self.syntax.duplicate_names = yes;
+            self
+        }
+
+        // the biggest bound a counted repetition is let have, see SyntaxConfig::max_repeat
+        pub fn max_repeat(&mut self, max: u32) -> &mut Self {
+            self.syntax.max_repeat = max;
+            self
+        }
+
+        // only ASCII letters, digits and _ are word chars for \w and \b, see SyntaxConfig::ascii_words
+        pub fn ascii_words(&mut self, yes: bool) -> &mut Self {
+            self.syntax.ascii_words = yes;
+            self
+        }
+
+        // é matches whether it's written precomposed or as e and a combining accent
+        #[cfg(feature = "unicode")]
+        pub fn normalization_insensitive(&mut self, yes: bool) -> &mut Self {
+            self.normalization_insensitive = yes;
+            self
+        }
+
+        pub fn build(&self) -> Result<Regex, ParseError> {
+            // a \G is only understood at the very start, which is where a tokenizer puts it: each
+            // match has to start right where the one before it ended, the first at the start
+            let (pattern, continued) = match self.pattern.strip_prefix("\\G") {
+                Some(rest) if !self.fixed_string => (rest, true),
+                _ => (self.pattern.as_str(), false)
+            };
+            let mut ast = match self.fixed_string {
+                true if self.pattern.is_empty() => Ast::Empty,
+                true => Ast::Cost(CharCost::Literal(self.pattern.clone())),
+                false => parse_ast_with(pattern, &self.syntax)?
+            };
+            let has_uppercase = match self.fixed_string {
+                true => self.pattern.chars().any(char::is_uppercase),
+                false => has_uppercase_literal(&self.pattern, self.syntax.dialect)?
+            };
+            if self.case_insensitive || self.smart_case && !has_uppercase {
+                ast = match self.ascii_case {
+                    true => ast.ascii_case_insensitive(),
+                    false => ast.case_insensitive()
+                };
+            }
+            if self.multi_line {
+                ast = ast.multi_line(self.crlf);
+            }
+            if self.line_terminator != b'\n' {
+                ast = ast.line_terminator(self.line_terminator);
+            }
+            #[cfg(feature = "unicode")]
+            if self.normalization_insensitive {
+                ast = ast.normalization_insensitive();
+            }
+            if self.whole_word {
+                let boundary = match self.syntax.ascii_words {
+                    true => Ast::Cost(CharCost::Look(Look::WordBoundaryAscii)),
+                    false => Ast::Cost(CharCost::Look(Look::WordBoundary))
+                };
+                ast = Ast::Concat(vec![boundary.clone(), ast, boundary]);
+            }
+            let whole = GroupInfo { name: None, in_alternation: false, in_repetition: false };
+            let groups = [whole].into_iter().chain(ast.groups()).collect();
+            let static_captures_len = ast.static_captures_len().map(|a| a + 1);
+            let pikevm = PikeVm::new(&ast);
+            let onepass = OnePass::new(&pikevm);
+            // a pattern that only ever matches one string, like a fixed string the options above
+            // left alone, needs no automaton at all: str's own substring search, which is Two-Way,
+            // finds it
+            if let Some(literal) = ast.literal().filter(|a| !a.is_empty() && !continued) {
+                let program = Program::Literal(literal);
+                return Ok(Regex {
+                    compiled: Arc::new(Compiled {
+                        program, pikevm, onepass, groups, static_captures_len, start_bytes: None, factor: None, empty_matches: self.empty_matches, earliest: self.earliest, step_limit: self.step_limit, dfa_fallback: false, continued, line_terminator: self.line_terminator
+                    })
+                });
+            }
+            let factor = ast.required_factor().and_then(|(before, literal)| {
+                let before = graph_from_ast(before).compile();
+                // the reversed text would need looks turned round too, so those go without
+                let skips = !has_inner_occurrence(&before.clone().freeze(), &literal);
+                (!before.has_looks()).then(|| Factor { literal, reversed: Nfa::new(before.reverse().map(CharCost::reversed)), skips })
+            });
+            let graph = graph_from_ast(ast).compile();
+            // only worth it when a match can start with few enough bytes to look for them all at once
+            let start_bytes = start_bytes(&graph).filter(|a| a.len() <= 3);
+            // a DFA has no way to check a look or keep a count, so those patterns are always simulated
+            let dfa = if graph.has_looks() || graph.has_counters() { DfaKind::Off } else { self.dfa };
+            let limit = self.dfa_size_limit;
+            let program = match dfa {
+                DfaKind::Off => None,
+                DfaKind::Dense => DenseDfa::with_limit(&graph, limit).map(|a| Program::Dense(Box::new(a))),
+                DfaKind::Sparse => SparseDfa::with_limit(&graph, limit).map(Program::Sparse),
+                DfaKind::Lazy => Some(Program::Lazy(Box::new(LazyDfa::with_limit(&graph, limit))))
+            };
+            let dfa_fallback = program.is_none() && self.dfa != DfaKind::Off;
+            let program = program.unwrap_or_else(|| Program::Nfa(Nfa::new(graph)));
+            Ok(Regex {
+                compiled: Arc::new(Compiled {
+                    program, pikevm, onepass, groups, static_captures_len, start_bytes, factor, empty_matches: self.empty_matches, earliest: self.earliest, step_limit: self.step_limit, dfa_fallback, continued, line_terminator: self.line_terminator
+                })
+            })
+        }
+    }
+
+    struct Compiled {
+        program: Program,
+        // captures are only worked out once a match has been found, over just its span
+        pikevm: PikeVm,
+        onepass: Option<OnePass>,
+        // group 0, the whole match, first
+        groups: Vec<GroupInfo>,
+        static_captures_len: Option<usize>,
+        // every byte a match can start with, when there are at most three
+        start_bytes: Option<Vec<u8>>,
+        factor: Option<Factor>,
+        empty_matches: EmptyMatches,
+        earliest: bool,
+        step_limit: Option<usize>,
+        // a DFA was asked for but the pattern needs looks or counts, or the DFA more room than it
+        // was allowed, so it gets simulated after all
+        dfa_fallback: bool,
+        // the pattern started with \G
+        continued: bool,
+        // what find_lines splits at, from RegexBuilder::line_terminator
+        line_terminator: u8
+    }
+
+    // text every match has in it, and the pattern up to the end of it backwards, to check where a
+    // match through an occurrence of it could have started
+    struct Factor {
+        literal: String,
+        reversed: Nfa,
+        // no text the pattern matches up to the end of the literal has it anywhere before that end,
+        // so first_start is never past the leftmost match and a search can skip straight to it
+        skips: bool
+    }
+
+    impl Factor {
+        // the first start at or after position of text matching the pattern as far as the end of
+        // an occurrence of the literal, trying occurrences in order. When the literal ends the
+        // pattern that's a whole match, though not necessarily the leftmost, which may go through
+        // a later occurrence, unless skips says it can't. None at all means nothing from position
+        // on can match.
+        fn first_start(&self, haystack: &str, position: usize) -> Option<usize> {
+            let mut from = position;
+            while let Some(found) = haystack[from..].find(self.literal.as_str()) {
+                let end = from + found + self.literal.len();
+                if let Some(length) = self.reversed.longest_match_at(&Reversed::new(&haystack[position..end]), 0) {
+                    return Some(end - length);
+                }
+                from = next_boundary(haystack, from + found);
+            }
+            None
+        }
+    }
+
+    // where a search in could_start last said something next starts, kept for as long as it's
+    // still ahead, since positions only go up
+    #[derive(Default)]
+    struct NextStart(Cell<Option<Option<usize>>>);
+
+    impl NextStart {
+        fn get(&self, position: usize, search: impl FnOnce(usize) -> Option<usize>) -> Option<usize> {
+            let start = match self.0.get() {
+                Some(start) if start.is_none_or(|a| a >= position) => start,
+                _ => search(position)
+            };
+            self.0.set(Some(start));
+            start
+        }
+    }
+
+    // what the prefilters found ahead in one search
+    #[derive(Default)]
+    struct Prefilters {
+        next_literal: NextStart,
+        next_byte: NextStart,
+        next_factor: NextStart
+    }
+
+    // nothing is mutated once built, so clones share one Compiled and can go to other threads
+    #[derive(Clone)]
+    pub struct Regex {
+        compiled: Arc<Compiled>
+    }
+
+    impl Regex {
+        pub fn new(pattern: &str) -> Result<Regex, ParseError> {
+            RegexBuilder::new(pattern).build()
+        }
+
+        pub fn is_match(&self, haystack: &str) -> bool {
+            if let Program::Literal(literal) = &self.compiled.program {
+                return haystack.contains(literal.as_str());
+            }
+            // where a match ends can't change whether there is one
+            self.search(haystack, &SearchConfig { earliest: true, anchored: self.compiled.continued, ..SearchConfig::default() }).is_some()
+        }
+
+        // the pattern has to use up all of haystack, as a validator wants, rather than just some
+        // of it. The longest match from the start reaches the end whenever any match does.
+        pub fn is_full_match(&self, haystack: &str) -> bool {
+            self.longest_match_at(haystack, 0) == Some(haystack.len())
+        }
+
+        pub fn full_match<'h>(&self, haystack: &'h str) -> Option<Match<'h>> {
+            self.is_full_match(haystack).then_some(Match { haystack, start: 0, end: haystack.len() })
+        }
+
+        // is_full_match for input that isn't finished yet, like a form field being typed into:
+        // whether it matches already, could with more typed on the end, or never will
+        pub fn prefix_match(&self, input: &str) -> PrefixMatch {
+            self.compiled.pikevm.prefix_match(input)
+        }
+
+        // the longest match starting exactly at start, for callers stepping through input
+        // themselves. What comes before start still counts for ^ and \b, so this isn't the same as
+        // matching against haystack[start..]. Nothing matches off a char boundary.
+        pub fn match_at<'h>(&self, haystack: &'h str, start: usize) -> Option<Match<'h>> {
+            if !haystack.is_char_boundary(start) {
+                return None;
+            }
+            let end = self.longest_match_at(haystack, start)?;
+            Some(Match { haystack, start, end })
+        }
+
+        pub fn is_match_at(&self, haystack: &str, start: usize) -> bool {
+            self.match_at(haystack, start).is_some()
+        }
+
+        pub fn find(&self, haystack: &str) -> Option<MatchData> {
+            if let Program::Literal(literal) = &self.compiled.program {
+                return haystack.find(literal.as_str()).map(|location| MatchData { matched_string: literal.clone(), location });
+            }
+            match_spans(haystack, self.compiled.empty_matches, self.str_match_end(haystack)).next().map(|(start, end)| MatchData {
+                matched_string: haystack[start..end].to_string(),
+                location: start
+            })
+        }
+
+        // the first match in any kind of haystack, like a &[u8] or the pieces of a Chunked,
+        // as a byte range
+        pub fn find_in<H: Haystack + ?Sized>(&self, haystack: &H) -> Option<Range<usize>> {
+            match_spans(haystack, self.compiled.empty_matches, self.continuing(|position| self.match_end(haystack, position))).next().map(|(start, end)| start..end)
+        }
+
+        // somewhere for search_with to keep what it allocates, to be used again next time
+        pub fn create_cache(&self) -> Cache {
+            let sets = match &self.compiled.program {
+                Program::Nfa(nfa) => (0..2).map(|_| SparseSet::new(nfa.state_count())).collect(),
+                _ => vec![]
+            };
+            Cache { spares: Spares::with_sets(sets) }
+        }
+
+        // the first match, like find and with the same searching ahead, with the state the search
+        // needs taken from cache rather than allocated, and left there for the next one. Once a
+        // cache has been through a search with a Regex, searching with the two doesn't allocate. A
+        // cache can go with any Regex.
+        pub fn search_with<'h>(&self, cache: &mut Cache, haystack: &'h str) -> Option<Match<'h>> {
+            let (start, end) = with_spares(&mut cache.spares, || {
+                match_spans(haystack, self.compiled.empty_matches, self.str_match_end(haystack)).next()
+            })?;
+            Some(Match { haystack, start, end })
+        }
+
+        pub fn is_match_in<H: Haystack + ?Sized>(&self, haystack: &H) -> bool {
+            self.find_in(haystack).is_some()
+        }
+
+        // match_end for a str, with only the positions could_start lets through tried
+        fn str_match_end<'a>(&'a self, haystack: &'a str) -> impl Fn(usize) -> Option<usize> + 'a {
+            let prefilters = Prefilters::default();
+            self.continuing(move |position| {
+                self.could_start(haystack, position, &prefilters).then(|| self.match_end(haystack, position)).flatten()
+            })
+        }
+
+        // whether a match could start at position going by what it has to have, which can be
+        // searched for first. A literal's next occurrence is where its next match is, a match can't
+        // start anywhere but at one of the start bytes, and once no occurrence of the factor is
+        // left that a match could go through, no position can. Where the factor skips, nothing
+        // before the start it finds can either.
+        fn could_start(&self, haystack: &str, position: usize, prefilters: &Prefilters) -> bool {
+            if let Program::Literal(literal) = &self.compiled.program {
+                return prefilters.next_literal.get(position, |a| haystack[a..].find(literal.as_str()).map(|x| a + x)) == Some(position);
+            }
+            if let Some(bytes) = &self.compiled.start_bytes {
+                if prefilters.next_byte.get(position, |a| memchr(bytes, &haystack.as_bytes()[a..]).map(|x| a + x)) != Some(position) {
+                    return false;
+                }
+            }
+            match &self.compiled.factor {
+                Some(factor) => match prefilters.next_factor.get(position, |a| factor.first_start(haystack, a)) {
+                    Some(start) => start == position || !factor.skips,
+                    None => false
+                },
+                None => true
+            }
+        }
+
+        // match_end for a pattern starting with \G, which only matches where the last match ended,
+        // or at 0 for the first. A search stepping through positions stops finding anything as soon
+        // as one fails or is empty.
+        fn continuing<'a>(&'a self, match_end: impl Fn(usize) -> Option<usize> + 'a) -> impl Fn(usize) -> Option<usize> + 'a {
+            let expected = Cell::new(Some(0));
+            move |position| {
+                if !self.compiled.continued {
+                    return match_end(position);
+                }
+                if expected.get() != Some(position) {
+                    return None;
+                }
+                let end = match_end(position);
+                expected.set(end.filter(|a| *a > position));
+                end
+            }
+        }
+
+        // where the match from position ends, the earliest or the longest as built
+        fn match_end<H: Haystack + ?Sized>(&self, haystack: &H, position: usize) -> Option<usize> {
+            self.match_end_counted(haystack, position, self.compiled.earliest, &mut Metrics::default())
+        }
+
+        fn match_end_counted<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, earliest: bool, metrics: &mut Metrics) -> Option<usize> {
+            match earliest {
+                true => self.earliest_match_counted(haystack, position, metrics),
+                false => self.longest_match_counted(haystack, position, metrics)
+            }
+        }
+
+        // is_match, except that it gives up with an error past the step limit rather than go on
+        pub fn try_is_match(&self, haystack: &str) -> Result<bool, MatchError> {
+            Ok(self.try_search(haystack, true)?.is_some())
+        }
+
+        // find, giving up like try_is_match
+        pub fn try_find<'h>(&self, haystack: &'h str) -> Result<Option<Match<'h>>, MatchError> {
+            let found = self.try_search(haystack, self.compiled.earliest)?;
+            Ok(found.map(|a| Match { haystack, start: a.start, end: a.end }))
+        }
+
+        fn try_search(&self, haystack: &str, earliest: bool) -> Result<Option<Range<usize>>, MatchError> {
+            let mut metrics = Metrics::default();
+            let mut position = 0;
+            while position <= haystack.len() {
+                if let Some(end) = self.match_end_counted(haystack, position, earliest, &mut metrics) {
+                    return Ok(Some(position..end));
+                }
+                if self.compiled.continued {
+                    break;
+                }
+                if let Some(limit) = self.compiled.step_limit.filter(|a| metrics.transitions + metrics.epsilon_expansions > *a) {
+                    return Err(MatchError::StepLimit { limit, offset: position });
+                }
+                position = next_boundary(haystack, position);
+            }
+            Ok(None)
+        }
+
+        pub fn find_all(&self, haystack: &str) -> Vec<MatchData> {
+            if let Program::Literal(literal) = &self.compiled.program {
+                return haystack.match_indices(literal.as_str()).map(|(location, _)| MatchData { matched_string: literal.clone(), location }).collect();
+            }
+            find_all(haystack, self.compiled.empty_matches, self.str_match_end(haystack))
+        }
+
+        // how many groups every result of captures has room for, group 0 included
+        pub fn captures_len(&self) -> usize {
+            self.compiled.groups.len()
+        }
+
+        // how many groups, group 0 included, take part in every single match, when that never
+        // changes. If it's captures_len, no group ever comes back empty.
+        pub fn static_captures_len(&self) -> Option<usize> {
+            self.compiled.static_captures_len
+        }
+
+        pub fn group_names(&self) -> impl Iterator<Item = Option<&str>> {
+            self.compiled.groups.iter().map(|a| a.name.as_deref())
+        }
+
+        pub fn group_info(&self) -> &[GroupInfo] {
+            &self.compiled.groups
+        }
+
+        // the char lines end at, see RegexBuilder::line_terminator
+        pub fn line_terminator(&self) -> char {
+            char::from(self.compiled.line_terminator)
+        }
+
+        // how many matches find_all would find, without building any of them
+        pub fn count(&self, haystack: &str) -> usize {
+            if let Program::Literal(literal) = &self.compiled.program {
+                return haystack.matches(literal.as_str()).count();
+            }
+            match_spans(haystack, self.compiled.empty_matches, self.str_match_end(haystack)).count()
+        }
+
+        // haystack cut at every match: each piece paired with the match that ends it, and the
+        // last piece with nothing. Pieces and delimiters together cover haystack exactly once.
+        pub fn split_spans(&self, haystack: &str) -> Vec<(Range<usize>, Option<Range<usize>>)> {
+            let mut pieces = vec![];
+            let mut start = 0;
+            for (a, x) in match_spans(haystack, self.compiled.empty_matches, self.str_match_end(haystack)) {
+                pieces.push((start..a, Some(a..x)));
+                start = x;
+            }
+            pieces.push((start..haystack.len(), None));
+            pieces
+        }
+
+        pub fn split<'h>(&self, haystack: &'h str) -> Vec<&'h str> {
+            self.split_spans(haystack).into_iter().map(|(a, _)| &haystack[a]).collect()
+        }
+
+        // like split, but every piece keeps the match after it on its end. As with
+        // str::split_inclusive, nothing after the last match means no empty piece for it.
+        pub fn split_inclusive<'h>(&self, haystack: &'h str) -> Vec<&'h str> {
+            let mut pieces: Vec<&str> = self.split_spans(haystack).into_iter().map(|(a, x)| &haystack[a.start..x.map_or(a.end, |y| y.end)]).collect();
+            if pieces.last() == Some(&"") {
+                pieces.pop();
+            }
+            pieces
+        }
+
+        // every line with a match in it, and the matches, like grep. Lines end at the line
+        // terminator, \n unless the builder said otherwise, which isn't part of them, and each one
+        // is searched on its own so ^ and $ hold at its ends.
+        pub fn find_lines<'h>(&self, haystack: &'h str) -> Vec<LineMatch<'h>> {
+            let terminator = self.line_terminator();
+            let mut lines = vec![];
+            let mut start = 0;
+            for (index, line) in haystack.split_inclusive(terminator).enumerate() {
+                let text = line.strip_suffix(terminator).unwrap_or(line);
+                let matches: Vec<Match> = match_spans(text, self.compiled.empty_matches, self.str_match_end(text))
+                    .map(|(a, x)| Match { haystack, start: start + a, end: start + x })
+                    .collect();
+                if !matches.is_empty() {
+                    lines.push(LineMatch { number: index + 1, span: start..start + text.len(), matches });
+                }
+                start += line.len();
+            }
+            lines
+        }
+
+        // the groups of the first match
+        pub fn captures<'h>(&self, haystack: &'h str) -> Option<Captures<'h>> {
+            let found = self.find(haystack)?;
+            self.captures_at(haystack, found.location, found.location + found.matched_string.len())
+        }
+
+        fn captures_at<'h>(&self, haystack: &'h str, start: usize, end: usize) -> Option<Captures<'h>> {
+            let slots = match &self.compiled.onepass {
+                Some(onepass) => onepass.captures_at(haystack, start, end),
+                None => self.compiled.pikevm.captures_at(haystack, start, end)
+            }?;
+            Some(Captures {
+                haystack,
+                spans: slots.chunks(2).map(|a| Some(a[0]?..a[1]?)).collect(),
+                compiled: self.compiled.clone()
+            })
+        }
+
+        // copies reader to writer with every match replaced by replacement, expanded as in
+        // Captures::expand, and says how many there were. Input is read a block at a time and
+        // written out as soon as no match can still be running through it, so memory only grows
+        // with the longest match rather than the whole input.
+        pub fn replace_stream(&self, mut reader: impl Read, mut writer: impl Write, replacement: &str) -> io::Result<usize> {
+            let mut text = String::new();
+            // the end of a UTF-8 sequence a read split in two
+            let mut partial = vec![];
+            let mut block = vec![0; STREAM_BLOCK];
+            let mut count = 0;
+            // text before written is already out, and matches are looked for from position on
+            let (mut written, mut position) = (0, 0);
+            let mut last_end = None;
+            // whether a pattern starting with \G can still match, which it can't once it hasn't
+            let mut continuing = true;
+            loop {
+                let read = reader.read(&mut block)?;
+                let eof = read == 0;
+                partial.extend_from_slice(&block[..read]);
+                let valid = match std::str::from_utf8(&partial) {
+                    Ok(a) => a.len(),
+                    Err(a) if a.error_len().is_none() && !eof => a.valid_up_to(),
+                    Err(a) => return Err(io::Error::new(io::ErrorKind::InvalidData, a))
+                };
+                text.push_str(std::str::from_utf8(&partial[..valid]).unwrap());
+                partial.drain(..valid);
+                // the end of what's been read is only the end of the text once there's no more
+                while position < text.len() || (eof && position == text.len()) {
+                    let probe = Probe { text: &text, reached_end: Cell::new(false) };
+                    let end = if continuing { self.match_end(&probe, position) } else { None };
+                    // with more to come the match might be longer, or only there at all, once it's read
+                    if probe.reached_end.get() && !eof {
+                        break;
+                    }
+                    continuing &= !self.compiled.continued || end.is_some_and(|a| a > position);
+                    let skip = end == Some(position) && last_end == Some(position) && self.compiled.empty_matches == EmptyMatches::SkipAdjacent;
+                    match end.filter(|_| !skip) {
+                        Some(end) => {
+                            writer.write_all(&text.as_bytes()[written..position])?;
+                            let mut replaced = String::new();
+                            if let Some(captures) = self.captures_at(&text, position, end) {
+                                captures.expand(replacement, &mut replaced);
+                            }
+                            writer.write_all(replaced.as_bytes())?;
+                            count += 1;
+                            written = end;
+                            last_end = Some(end);
+                            position = if end > position { end } else { next_boundary(text.as_str(), position) };
+                        }
+                        None if !continuing && position < text.len() => position = text.len(),
+                        None => position = next_boundary(text.as_str(), position)
+                    }
+                }
+                if eof {
+                    break;
+                }
+                // everything before position is settled, but a look there might need the char
+                // just before it
+                let cut = text[..position].char_indices().next_back().map_or(0, |(a, _)| a);
+                if written < cut {
+                    writer.write_all(&text.as_bytes()[written..cut])?;
+                    written = cut;
+                }
+                text.drain(..cut);
+                written -= cut;
+                position -= cut;
+                last_end = last_end.filter(|a| *a >= cut).map(|a| a - cut);
+            }
+            writer.write_all(&text.as_bytes()[written..])?;
+            writer.flush()?;
+            Ok(count)
+        }
+    }
+
+    const STREAM_BLOCK: usize = 8 * 1024;
+
+    // the text read so far in replace_stream, which notes when a search runs into its end, since
+    // that search can't be trusted until more has been read
+    struct Probe<'a> {
+        text: &'a str,
+        reached_end: Cell<bool>
+    }
+
+    impl Haystack for Probe<'_> {
+        fn len(&self) -> usize {
+            self.text.len()
+        }
+
+        fn chunk_at(&self, offset: usize) -> &[u8] {
+            if offset >= self.text.len() {
+                self.reached_end.set(true);
+            }
+            &self.text.as_bytes()[offset..]
+        }
+
+        fn chunk_before(&self, offset: usize) -> &[u8] {
+            &self.text.as_bytes()[..offset]
+        }
+    }
+
+    // what a search allocates, kept by the caller between searches, see Regex::create_cache
+    #[derive(Debug, Default)]
+    pub struct Cache {
+        spares: Spares
+    }
+
+    // the part of a haystack a match or a group covered
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Match<'h> {
+        haystack: &'h str,
+        start: usize,
+        end: usize
+    }
+
+    // a line find_lines found something in. Every offset is into the whole haystack.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct LineMatch<'h> {
+        // counting from 1
+        pub number: usize,
+        pub span: Range<usize>,
+        pub matches: Vec<Match<'h>>
+    }
+
+    impl<'h> Match<'h> {
+        pub fn start(&self) -> usize {
+            self.start
+        }
+
+        pub fn end(&self) -> usize {
+            self.end
+        }
+
+        pub fn range(&self) -> Range<usize> {
+            self.start..self.end
+        }
+
+        // range() counts bytes, this counts chars
+        pub fn char_range(&self) -> Range<usize> {
+            char_range(self.haystack, self.range())
+        }
+
+        pub fn as_str(&self) -> &'h str {
+            &self.haystack[self.start..self.end]
+        }
+    }
+
+    // a Regex can stand in wherever an engine is wanted, running whichever one it was built with
+    impl Engine for Regex {
+        fn longest_match_counted<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, metrics: &mut Metrics) -> Option<usize> {
+            metrics.dfa_fallbacks += self.compiled.dfa_fallback as usize;
+            match &self.compiled.program {
+                Program::Nfa(nfa) => nfa.longest_match_counted(haystack, position, metrics),
+                Program::Dense(dfa) => dfa.longest_match_counted(haystack, position, metrics),
+                Program::Sparse(dfa) => dfa.longest_match_counted(haystack, position, metrics),
+                Program::Lazy(dfa) => dfa.longest_match_counted(haystack, position, metrics),
+                Program::Literal(literal) => haystack.starts_with_at(position, literal).then_some(position + literal.len())
+            }
+        }
+
+        fn earliest_match_counted<H: Haystack + ?Sized>(&self, haystack: &H, position: usize, metrics: &mut Metrics) -> Option<usize> {
+            metrics.dfa_fallbacks += self.compiled.dfa_fallback as usize;
+            match &self.compiled.program {
+                Program::Nfa(nfa) => nfa.earliest_match_counted(haystack, position, metrics),
+                Program::Dense(dfa) => dfa.earliest_match_counted(haystack, position, metrics),
+                Program::Sparse(dfa) => dfa.earliest_match_counted(haystack, position, metrics),
+                Program::Lazy(dfa) => dfa.earliest_match_counted(haystack, position, metrics),
+                Program::Literal(_) => self.longest_match_counted(haystack, position, metrics)
+            }
+        }
+
+        // the default search, but with the prefilters find uses skipping positions whenever the
+        // haystack is a str
+        fn search_counted<H: Haystack + ?Sized>(&self, haystack: &H, config: &SearchConfig, metrics: &mut Metrics) -> Option<Range<usize>> {
+            let prefilters = Prefilters::default();
+            let mut position = config.start;
+            while position <= haystack.len() {
+                if haystack.as_str().is_some_and(|a| !self.could_start(a, position, &prefilters)) {
+                    metrics.skipped_positions += 1;
+                } else {
+                    metrics.positions += 1;
+                    if let Some(end) = self.match_end_counted(haystack, position, config.earliest, metrics) {
+                        return Some(position..end);
+                    }
+                }
+                if config.anchored {
+                    break;
+                }
+                position = next_boundary(haystack, position);
+            }
+            None
+        }
+    }
+
+    // where every group of one match ended up, group 0 being the whole match. A group that took
+    // no part in the match, like the unused side of (a)|(b), has nothing.
+    pub struct Captures<'h> {
+        haystack: &'h str,
+        spans: Vec<Option<Range<usize>>>,
+        // for the group names
+        compiled: Arc<Compiled>
+    }
+
+    impl<'h> Captures<'h> {
+        // None for a group that didn't take part, and for one the pattern doesn't have
+        pub fn get(&self, index: usize) -> Option<Match<'h>> {
+            let span = self.spans.get(index)?.clone()?;
+            Some(Match { haystack: self.haystack, start: span.start, end: span.end })
+        }
+
+        pub fn name(&self, name: &str) -> Option<Match<'h>> {
+            // with duplicate names allowed, whichever group of that name took part
+            let mut indices = self.compiled.groups.iter().enumerate().filter(|(_, a)| a.name.as_deref() == Some(name));
+            indices.find_map(|(index, _)| self.get(index))
+        }
+
+        // the same as Regex::captures_len
+        pub fn len(&self) -> usize {
+            self.spans.len()
+        }
+
+        // group index converted with FromStr
+        pub fn parse_group<T: FromStr>(&self, index: usize) -> Result<T, CaptureError> where T::Err: fmt::Display {
+            let name = self.compiled.groups.get(index).and_then(|a| a.name.clone());
+            let error = |problem| CaptureError { group: index, name: name.clone(), problem };
+            let text = self.get(index).ok_or_else(|| error(CaptureProblem::Missing))?.as_str();
+            text.parse().map_err(|a: T::Err| error(CaptureProblem::Invalid { text: text.to_string(), expected: std::any::type_name::<T>(), reason: a.to_string() }))
+        }
+
+        // groups 1, 2 and on converted into a tuple in one go, like parse::<(u32, String)>()
+        pub fn parse<T: FromCaptures>(&self) -> Result<T, CaptureError> {
+            T::from_captures(self)
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.spans.is_empty()
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = Option<Match<'h>>> + '_ {
+            (0..self.len()).map(|a| self.get(a))
+        }
+
+        // every group's byte range in the haystack, as get(i).range() would give it, for slicing
+        // or splicing the haystack without going through the matched text
+        pub fn ranges(&self) -> &[Option<Range<usize>>] {
+            &self.spans
+        }
+
+        // appends template to out with $1 or $name replaced by that group, or by nothing if there's
+        // no such group or it didn't match. The name is as many letters, digits and _ as follow,
+        // so ${1}st is needed to put a letter straight after one, and $$ is a plain $.
+        pub fn expand(&self, template: &str, out: &mut String) {
+            let mut rest = template;
+            while let Some(dollar) = rest.find('$') {
+                out.push_str(&rest[..dollar]);
+                rest = &rest[dollar + 1..];
+                let (name, after) = if let Some(braced) = rest.strip_prefix('{').filter(|a| a.contains('}')) {
+                    braced.split_once('}').unwrap()
+                } else {
+                    let end = rest.find(|a: char| !(a.is_alphanumeric() || a == '_')).unwrap_or(rest.len());
+                    rest.split_at(end)
+                };
+                if name.is_empty() {
+                    // $$, or a $ with nothing after it to name a group, stays a $
+                    out.push('$');
+                    rest = rest.strip_prefix('$').unwrap_or(rest);
+                    continue;
+                }
+                let found = match name.parse::<usize>() {
+                    Ok(index) => self.get(index),
+                    Err(_) => self.name(name)
+                };
+                out.push_str(found.map_or("", |a| a.as_str()));
+                rest = after;
+            }
+            out.push_str(rest);
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct CaptureError {
+        pub group: usize,
+        pub name: Option<String>,
+        pub problem: CaptureProblem
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum CaptureProblem {
+        // the group took no part in the match, or there's no such group
+        Missing,
+        // what the group matched didn't convert, with what FromStr said about it
+        Invalid { text: String, expected: &'static str, reason: String }
+    }
+
+    impl fmt::Display for CaptureError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "group {}", self.group)?;
+            if let Some(name) = &self.name {
+                write!(f, " ({})", name)?;
+            }
+            match &self.problem {
+                CaptureProblem::Missing => write!(f, " didn't match"),
+                CaptureProblem::Invalid { text, expected, reason } => write!(f, " matched {:?}, which isn't a valid {}: {}", text, expected, reason)
+            }
+        }
+    }
+
+    impl std::error::Error for CaptureError {}
+
+    // why a try_ search stopped without saying whether there's a match
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum MatchError {
+        // it took more steps than the limit allowed, having tried everything before offset
+        StepLimit { limit: usize, offset: usize }
+    }
+
+    impl fmt::Display for MatchError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                MatchError::StepLimit { limit, offset } => write!(f, "gave up at offset {} after more than {} steps", offset, limit)
+            }
+        }
+    }
+
+    impl std::error::Error for MatchError {}
+
+    // a type that can be built from the groups of a match, see Captures::parse
+    pub trait FromCaptures: Sized {
+        fn from_captures(captures: &Captures) -> Result<Self, CaptureError>;
+    }
+
+    // tuples of FromStr types take groups 1, 2 and on, in order
+    macro_rules! tuple_from_captures {
+        ($($index:tt $name:ident),+) => {
+            impl<$($name: FromStr),+> FromCaptures for ($($name,)+) where $($name::Err: fmt::Display),+ {
+                fn from_captures(captures: &Captures) -> Result<Self, CaptureError> {
+                    Ok(($(captures.parse_group::<$name>($index + 1)?,)+))
+                }
+            }
+        };
+    }
+
+    tuple_from_captures!(0 A);
+    tuple_from_captures!(0 A, 1 B);
+    tuple_from_captures!(0 A, 1 B, 2 C);
+    tuple_from_captures!(0 A, 1 B, 2 C, 3 D);
+    tuple_from_captures!(0 A, 1 B, 2 C, 3 D, 4 E);
+    tuple_from_captures!(0 A, 1 B, 2 C, 3 D, 4 E, 5 F);
+
+    impl fmt::Debug for Captures<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_list().entries(self.iter().map(|a| a.map(|x| x.as_str()))).finish()
+        }
+    }
+
+    // compiled regexes keyed by everything that went into building them, for callers that keep
+    // compiling the same handful of user supplied patterns. Past capacity the least recently used
+    // one goes.
+    pub struct RegexCache {
+        capacity: usize,
+        entries: HashMap<RegexBuilder, (Regex, u64)>,
+        clock: u64
+    }
+
+    impl RegexCache {
+        pub fn new(capacity: usize) -> Self {
+            RegexCache {
+                capacity,
+                entries: HashMap::new(),
+                clock: 0
+            }
+        }
+
+        pub fn len(&self) -> usize {
+            self.entries.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.entries.is_empty()
+        }
+
+        pub fn get(&mut self, pattern: &str) -> Result<Regex, ParseError> {
+            self.get_with(&RegexBuilder::new(pattern))
+        }
+
+        pub fn get_with(&mut self, builder: &RegexBuilder) -> Result<Regex, ParseError> {
+            self.clock += 1;
+            if let Some((regex, used)) = self.entries.get_mut(builder) {
+                *used = self.clock;
+                return Ok(regex.clone());
+            }
+            let regex = builder.build()?;
+            if self.capacity == 0 {
+                return Ok(regex);
+            }
+            if self.entries.len() == self.capacity {
+                let oldest = self.entries.iter().min_by_key(|(_, (_, used))| *used).map(|(a, _)| a.clone()).unwrap();
+                self.entries.remove(&oldest);
+            }
+            self.entries.insert(builder.clone(), (regex.clone(), self.clock));
+            Ok(regex)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::alloc::{GlobalAlloc, Layout, System};
+
+        use super::*;
+        use crate::haystack::haystack::Chunked;
+        use crate::util::util::ScratchPool;
+
+        fn spans(captures: Option<Captures>) -> Option<Vec<Option<Range<usize>>>> {
+            captures.map(|a| a.iter().map(|x| x.map(|y| y.range())).collect())
+        }
+
+        #[test]
+        fn test_engines_agree() {
+            let haystack = "abbc acd é日本 aaab";
+            for pattern in ["ab*", "a(b|c)d?", "[a-c]+", ".", "日本|é", "x"] {
+                let expected: Vec<(String, usize)> = Regex::new(pattern).unwrap().find_all(haystack).into_iter().map(|a| (a.matched_string, a.location)).collect();
+                for kind in [DfaKind::Dense, DfaKind::Sparse, DfaKind::Lazy] {
+                    let regex = RegexBuilder::new(pattern).dfa(kind).build().unwrap();
+                    let found: Vec<(String, usize)> = regex.find_all(haystack).into_iter().map(|a| (a.matched_string, a.location)).collect();
+                    assert_eq!(expected, found, "{} with {:?}", pattern, kind);
+                }
+            }
+        }
+
+        #[test]
+        fn test_find() {
+            let regex = RegexBuilder::new("b+c").dfa(DfaKind::Sparse).build().unwrap();
+            let found = regex.find("abbbcbc").unwrap();
+            assert_eq!((found.matched_string.as_str(), found.location), ("bbbc", 1));
+            assert!(regex.is_match("bc"));
+            assert!(!regex.is_match("cb"));
+            let found: Vec<String> = Regex::new("x\\R").unwrap().find_all("x\r\nx\n").into_iter().map(|a| a.matched_string).collect();
+            assert_eq!(found, vec!["x\r\n", "x\n"]);
+        }
+
+        #[test]
+        fn test_captures() {
+            let regex = Regex::new("([a-z]+)@([a-z]+)").unwrap();
+            assert!(regex.compiled.onepass.is_some());
+            assert_eq!(spans(regex.captures("mail bob@example now")), Some(vec![Some(5..16), Some(5..8), Some(9..16)]));
+
+            // not one-pass, so the pikevm does it
+            let regex = Regex::new("(a|ab)(c|bcd)").unwrap();
+            assert!(regex.compiled.onepass.is_none());
+            assert_eq!(spans(regex.captures("xabcd")), Some(vec![Some(1..5), Some(1..2), Some(2..5)]));
+            assert_eq!(spans(regex.captures("xyz")), None);
+            assert_eq!(spans(Regex::new("(a)|(b)").unwrap().captures("b")), Some(vec![Some(0..1), None, Some(0..1)]));
+        }
+
+        #[test]
+        #[cfg(feature = "unicode")]
+        fn test_case_insensitive() {
+            let regex = RegexBuilder::new("café [à-þ]+").case_insensitive(true).build().unwrap();
+            let found: Vec<String> = regex.find_all("CAFÉ ÉÈ, Café àÀ").into_iter().map(|a| a.matched_string).collect();
+            assert_eq!(found, vec!["CAFÉ ÉÈ", "Café àÀ"]);
+            assert!(!Regex::new("café").unwrap().is_match("CAFÉ"));
+        }
+
+        #[test]
+        fn test_group_ranges() {
+            let haystack = "key = value";
+            let captures = Regex::new("([a-z]+) *= *([a-z]+)|(x)").unwrap().captures(haystack).unwrap();
+            assert_eq!(captures.ranges(), [Some(0..11), Some(0..3), Some(6..11), None]);
+            assert_eq!(captures.get(2).map(|a| a.range()), captures.ranges()[2].clone());
+            let mut spliced = haystack.to_string();
+            for range in captures.ranges()[1..].iter().rev().flatten() {
+                spliced.replace_range(range.clone(), &haystack[range.clone()].to_uppercase());
+            }
+            assert_eq!(spliced, "KEY = VALUE");
+        }
+
+        #[test]
+        fn test_duplicate_names() {
+            let regex = RegexBuilder::new("(?<n>[0-9]+)s|(?<n>[a-z]+)!").duplicate_names(true).build().unwrap();
+            assert_eq!(regex.captures_len(), 3);
+            let captures = regex.captures("say hi!").unwrap();
+            assert_eq!(captures.name("n").map(|a| a.as_str()), Some("hi"));
+            assert_eq!(regex.captures("in 10s").unwrap().name("n").map(|a| a.as_str()), Some("10"));
+            assert!(RegexBuilder::new("(?<n>a)|(?<n>b)").build().is_err());
+        }
+
+        #[test]
+        fn test_continuation() {
+            let regex = Regex::new("\\G *([a-z]+|=|;)").unwrap();
+            let found: Vec<String> = regex.find_all("x = y; ?z;").into_iter().map(|a| a.matched_string).collect();
+            assert_eq!(found, vec!["x", " =", " y", ";"]);
+            assert!(regex.is_match(" x") && !regex.is_match("?x") && regex.is_match_at("?x", 1));
+            let regex = Regex::new("\\Gab").unwrap();
+            assert_eq!(regex.count("ababxab"), 2);
+            let mut out = vec![];
+            assert_eq!(regex.replace_stream("ababxab".as_bytes(), &mut out, "-").unwrap(), 2);
+            assert_eq!(out, b"--xab");
+            assert_eq!(Regex::new("a\\G").err(), Some(ParseError::UnsupportedEscape('G')));
+        }
+
+        #[test]
+        fn test_smart_case() {
+            let smart = |pattern: &str| RegexBuilder::new(pattern).smart_case(true).build().unwrap();
+            assert!(smart("foo\\w").is_match("FOOD") && !smart("Foo").is_match("FOO"));
+            assert!(smart("[a-z]+\\W\\p{ASCII}").is_match("ABC D") && !smart("[A-Z]").is_match("a"));
+            assert!(smart("(?<Name>x)").is_match("X") && !smart("\\d|X").is_match("x"));
+        }
+
+        #[test]
+        #[cfg(feature = "unicode")]
+        fn test_ascii_case() {
+            let regex = RegexBuilder::new("café [k-m]").case_insensitive(true).ascii_case(true).build().unwrap();
+            assert!(regex.is_match("CAFé L") && !regex.is_match("CAFÉ L"));
+            // the Kelvin sign only folds to k under Unicode rules
+            assert!(!regex.is_match("café \u{212A}"));
+            assert!(RegexBuilder::new("[k-m]").case_insensitive(true).build().unwrap().is_match("\u{212A}"));
+            let regex = RegexBuilder::new("[^a]").case_insensitive(true).ascii_case(true).build().unwrap();
+            assert!(!regex.is_match("A") && regex.is_match("é"));
+        }
+
+        #[test]
+        fn test_anchors() {
+            let found = |regex: &Regex, haystack: &str| -> Vec<usize> { regex.find_all(haystack).into_iter().map(|a| a.location).collect() };
+            // the dfa asked for is quietly left out
+            let regex = RegexBuilder::new("^ab|ab$").dfa(DfaKind::Dense).build().unwrap();
+            assert_eq!(found(&regex, "ab\nab\nab"), vec![0, 6]);
+            let regex = RegexBuilder::new("^ab|ab$").multi_line(true).build().unwrap();
+            assert_eq!(found(&regex, "ab\nab\nab"), vec![0, 3, 6]);
+            assert_eq!(found(&regex, "xab\rab"), vec![4]);
+            let regex = RegexBuilder::new("^ab|ab$").multi_line(true).crlf(true).build().unwrap();
+            assert_eq!(found(&regex, "xab\rab"), vec![1, 4]);
+
+            // a \r\n is one line end, so nothing falls between its two halves
+            let regex = RegexBuilder::new("^$").multi_line(true).crlf(true).build().unwrap();
+            assert_eq!(found(&regex, "a\r\n\r\nb"), vec![3]);
+            let regex = RegexBuilder::new("^$").multi_line(true).build().unwrap();
+            assert_eq!(found(&regex, "a\r\n\r\nb"), vec![]);
+            let regex = RegexBuilder::new("(^[a-z]+$)").multi_line(true).build().unwrap();
+            assert_eq!(spans(regex.captures("1\nab\n")), Some(vec![Some(2..4), Some(2..4)]));
+        }
+
+        #[test]
+        fn test_line_terminator() {
+            let regex = RegexBuilder::new("^a.+$").multi_line(true).line_terminator(b'\0').build().unwrap();
+            let found: Vec<String> = regex.find_all("ab\ncd\0ae\0").into_iter().map(|a| a.matched_string).collect();
+            assert_eq!(found, vec!["ab\ncd", "ae"]);
+            // . stops at the terminator even when ^ and $ aren't in play
+            let regex = RegexBuilder::new("a.*").line_terminator(b';').build().unwrap();
+            assert_eq!(regex.find("xab\nc;d").map(|a| a.matched_string), Some("ab\nc".to_string()));
+        }
+
+        #[test]
+        fn test_captures_get() {
+            let regex = Regex::new("(?<user>[a-z]+)@(?<host>[a-z]+)(\\.(?<tld>[a-z]+))?").unwrap();
+            let captures = regex.captures("mail bob@example now").unwrap();
+            assert_eq!(captures.get(0).map(|a| a.as_str()), Some("bob@example"));
+            assert_eq!(captures.name("host").map(|a| (a.start(), a.end(), a.as_str())), Some((9, 16, "example")));
+            assert_eq!(captures.get(1), captures.name("user"));
+            // neither a group that didn't match nor one that doesn't exist is a panic
+            assert_eq!(captures.name("tld"), None);
+            assert_eq!(captures.get(3), None);
+            assert_eq!(captures.get(9), None);
+            assert_eq!(captures.name("nope"), None);
+            assert_eq!(format!("{:?}", captures), r#"[Some("bob@example"), Some("bob"), Some("example"), None, None]"#);
+        }
+
+        #[test]
+        fn test_expand() {
+            let regex = Regex::new("(?<user>[a-z]+)@([a-z]+)(x)?").unwrap();
+            let captures = regex.captures("bob@example").unwrap();
+            let mut out = String::from("> ");
+            captures.expand("$2 has ${user}s, $user_ is $3. $$1 ${1}x $9 $ ${", &mut out);
+            assert_eq!(out, "> example has bobs,  is . $1 bobx  $ ${");
+        }
+
+        #[test]
+        fn test_group_metadata() {
+            let regex = Regex::new("(?<key>[a-z]+)=((?<num>[0-9])+|(x))?").unwrap();
+            assert_eq!(regex.captures_len(), 5);
+            assert_eq!(regex.group_names().collect::<Vec<_>>(), vec![None, Some("key"), None, Some("num"), None]);
+            let flags: Vec<(bool, bool)> = regex.group_info().iter().map(|a| (a.in_alternation, a.in_repetition)).collect();
+            assert_eq!(flags, vec![(false, false), (false, false), (false, true), (true, true), (true, true)]);
+            assert_eq!(regex.captures("k=12").unwrap().len(), regex.captures_len());
+            assert_eq!(RegexBuilder::new("(a)").fixed_string(true).build().unwrap().captures_len(), 1);
+            assert_eq!(regex.static_captures_len(), None);
+            let regex = Regex::new("(?<key>[a-z]+)=([0-9]+|(?:x))").unwrap();
+            assert_eq!(regex.static_captures_len(), Some(regex.captures_len()));
+            assert!(regex.captures("k=x").unwrap().iter().all(|a| a.is_some()));
+        }
+
+        #[test]
+        fn test_count() {
+            let haystack = "abbc acd é日本 aaab ";
+            for pattern in ["ab*", "a*", "日本|é", "x", " "] {
+                for fixed_string in [false, true] {
+                    let regex = RegexBuilder::new(pattern).fixed_string(fixed_string).build().unwrap();
+                    assert_eq!(regex.count(haystack), regex.find_all(haystack).len(), "{}", pattern);
+                }
+            }
+            assert_eq!(Regex::new("a").unwrap().count("banana"), 3);
+        }
+
+        #[test]
+        fn test_fixed_string() {
+            let regex = RegexBuilder::new("a.b(").fixed_string(true).build().unwrap();
+            assert!(matches!(regex.compiled.program, Program::Literal(_)));
+            let found: Vec<usize> = regex.find_all("axb( a.b(a.b(").into_iter().map(|a| a.location).collect();
+            assert_eq!(found, vec![5, 9]);
+            assert_eq!(spans(regex.captures("xa.b(")), Some(vec![Some(1..5)]));
+            // options that change what the literal matches still go through the graph
+            let regex = RegexBuilder::new("A.B").fixed_string(true).case_insensitive(true).build().unwrap();
+            assert!(!matches!(regex.compiled.program, Program::Literal(_)));
+            assert_eq!(regex.find("xa.b").map(|a| a.location), Some(1));
+            assert!(!regex.is_match("axb"));
+            let regex = RegexBuilder::new("").fixed_string(true).build().unwrap();
+            assert_eq!(regex.find_all("ab").len(), 3);
+        }
+
+        #[test]
+        fn test_literal_patterns() {
+            for pattern in ["a\\.b", "(ab)c", r"\Qa.b(\E", r"x\Q*\"] {
+                assert!(matches!(Regex::new(pattern).unwrap().compiled.program, Program::Literal(_)), "{}", pattern);
+            }
+            let regex = Regex::new(r"\Qa.b(\E+").unwrap();
+            assert_eq!(regex.find_all("a.b(((a.b").len(), 1);
+            assert_eq!(Regex::new("(ab)c").unwrap().split("xabcyabc"), ["x", "y", ""]);
+            assert_eq!(spans(Regex::new("(ab)c").unwrap().captures("xabc")), Some(vec![Some(1..4), Some(1..3)]));
+        }
+
+        #[test]
+        fn test_haystacks() {
+            for kind in [DfaKind::Off, DfaKind::Dense, DfaKind::Sparse, DfaKind::Lazy] {
+                let regex = RegexBuilder::new("bc+é").dfa(kind).build().unwrap();
+                assert_eq!(regex.find_in(&Chunked::new(["ab", "c", "cé!"])), Some(1..6));
+                assert_eq!(regex.find_in(b"\xffbc\xc3\xa9".as_slice()), Some(1..5));
+                assert!(!regex.is_match_in(&Chunked::new(["ab", "c", "!é"])));
+            }
+            let regex = Regex::new(r"\bcat$").unwrap();
+            assert_eq!(regex.find_in(&Chunked::new(["a c", "a", "t"])), Some(2..5));
+            assert_eq!(regex.find_in(b"\xffcat".as_slice()), Some(1..4));
+            let regex = RegexBuilder::new("ca.t").fixed_string(true).build().unwrap();
+            assert_eq!(regex.find_in(&Chunked::new(["xc", "a.", "t"])), Some(1..5));
+        }
+
+        #[test]
+        fn test_metrics() {
+            let mut metrics = Metrics::default();
+            let regex = RegexBuilder::new(r"\bb").dfa(DfaKind::Dense).build().unwrap();
+            assert_eq!(regex.search_counted("ab b", &SearchConfig::default(), &mut metrics), Some(3..4));
+            // only the positions holding a b get tried
+            assert_eq!((metrics.positions, metrics.skipped_positions, metrics.dfa_fallbacks), (2, 2, 2));
+            let mut metrics = Metrics::default();
+            Regex::new("b+").unwrap().search_counted("ab b", &SearchConfig::default(), &mut metrics);
+            assert_eq!((metrics.positions, metrics.skipped_positions, metrics.transitions, metrics.dfa_fallbacks), (1, 1, 1, 0));
+            // the same search over bytes has no str to look ahead through
+            let mut metrics = Metrics::default();
+            Regex::new("b+").unwrap().search_counted("ab b".as_bytes(), &SearchConfig::default(), &mut metrics);
+            assert_eq!((metrics.positions, metrics.skipped_positions), (2, 0));
+            let mut metrics = Metrics::default();
+            let regex = Regex::new("needle").unwrap();
+            assert_eq!(regex.search_counted("haystack needle", &SearchConfig::default(), &mut metrics), Some(9..15));
+            assert_eq!((metrics.positions, metrics.skipped_positions), (1, 9));
+        }
+
+        #[test]
+        fn test_dfa_size_limit() {
+            let pattern = "(a|b)*a(a|b)(a|b)(a|b)(a|b)";
+            let regex = RegexBuilder::new(pattern).dfa(DfaKind::Dense).dfa_size_limit(500).build().unwrap();
+            assert!(matches!(regex.compiled.program, Program::Nfa(_)));
+            assert_eq!(regex.find("ccbaabab").map(|a| a.location), Some(2));
+            let regex = RegexBuilder::new(pattern).dfa(DfaKind::Dense).build().unwrap();
+            assert!(matches!(regex.compiled.program, Program::Dense(_)));
+        }
+
+        #[test]
+        fn test_counted_repetition() {
+            // a DFA can't keep the counts, so this one is simulated even though it asked for one
+            let regex = RegexBuilder::new("\\d{3}-\\d{2,}").dfa(DfaKind::Dense).build().unwrap();
+            let found: Vec<String> = regex.find_all("12-345 123-4 123-4567").into_iter().map(|a| a.matched_string).collect();
+            assert_eq!(found, vec!["123-4567"]);
+            let mut cache = regex.create_cache();
+            assert_eq!(regex.search_with(&mut cache, "x 999-00").map(|a| a.range()), Some(2..8));
+            assert_eq!(Regex::new("a{1,100000000}").err(), Some(ParseError::RepeatTooLarge(1000)));
+            assert!(RegexBuilder::new("[ab]{2000}").max_repeat(2000).build().is_ok());
+            // the inner factor's search reads the counts backwards
+            assert_eq!(Regex::new("[a-z]{2,3}@x\\.com").unwrap().find("a@x.com abcd@x.com").map(|a| a.location), Some(9));
+        }
+
+        #[test]
+        fn test_search_with_cache() {
+            let regex = Regex::new("a(b|c)*d").unwrap();
+            let mut cache = regex.create_cache();
+            let pooled = |cache: &mut Cache| with_spares(&mut cache.spares, || ScratchPool::take().len());
+            assert_eq!(pooled(&mut cache), 2);
+            assert_eq!(regex.search_with(&mut cache, "xxabcbd").map(|a| a.range()), Some(2..7));
+            let sets = pooled(&mut cache);
+            assert!(sets >= 2);
+            assert_eq!(regex.search_with(&mut cache, "abd ad").map(|a| a.as_str()), Some("abd"));
+            assert_eq!(pooled(&mut cache), sets);
+            assert_eq!(Regex::new("x").unwrap().search_with(&mut cache, "abd"), None);
+        }
+
+        // counts what each thread allocates, for checking that searches which shouldn't allocate don't
+        struct CountingAllocator;
+
+        thread_local! {
+            static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+        }
+
+        unsafe impl GlobalAlloc for CountingAllocator {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                ALLOCATIONS.set(ALLOCATIONS.get() + 1);
+                System.alloc(layout)
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                System.dealloc(ptr, layout)
+            }
+        }
+
+        #[global_allocator]
+        static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+        fn allocations<R>(f: impl FnOnce() -> R) -> (R, usize) {
+            let before = ALLOCATIONS.get();
+            let result = f();
+            (result, ALLOCATIONS.get() - before)
+        }
+
+        #[test]
+        fn test_search_with_allocates_nothing() {
+            let haystack = format!("{}abcbd", "x".repeat(1000));
+            // with the start bytes to search ahead for, and with nothing to, where every position is tried
+            for pattern in ["a(b|c)*d", "(a|x)(b|c)*d"] {
+                let regex = Regex::new(pattern).unwrap();
+                let mut cache = regex.create_cache();
+                let expected = regex.find(&haystack).map(|a| a.range());
+                assert_eq!(regex.search_with(&mut cache, &haystack).map(|a| a.range()), expected);
+                let (found, count) = allocations(|| regex.search_with(&mut cache, &haystack).map(|a| a.range()));
+                assert_eq!((found, count), (expected, 0), "{}", pattern);
+            }
+        }
+
+        #[test]
+        fn test_try_search() {
+            let haystack = format!("{}c", "a".repeat(200));
+            let limited = RegexBuilder::new("(a|aa)*b").step_limit(Some(1000)).build().unwrap();
+            assert!(matches!(limited.try_find(&haystack), Err(MatchError::StepLimit { limit: 1000, offset: _ })));
+            assert!(!limited.is_match(&haystack));
+            assert_eq!(Regex::new("(a|aa)*b").unwrap().try_is_match(&haystack), Ok(false));
+            assert_eq!(limited.try_find("aab").map(|a| a.map(|x| x.range())), Ok(Some(0..3)));
+            assert_eq!(MatchError::StepLimit { limit: 5, offset: 2 }.to_string(), "gave up at offset 2 after more than 5 steps");
+        }
+
+        #[test]
+        fn test_find_lines() {
+            let haystack = "an apple\nno\npear and plum\n";
+            let lines = Regex::new("p[a-z]").unwrap().find_lines(haystack);
+            let found: Vec<(usize, &str, Vec<&str>)> = lines.iter().map(|a| (a.number, &haystack[a.span.clone()], a.matches.iter().map(|x| x.as_str()).collect())).collect();
+            assert_eq!(found, vec![(1, "an apple", vec!["pp"]), (3, "pear and plum", vec!["pe", "pl"])]);
+            assert_eq!(lines[1].matches[1].range(), 21..23);
+            // anchors hold at the ends of each line
+            let lines = Regex::new("^n|e$").unwrap().find_lines(haystack);
+            assert_eq!(lines.iter().map(|a| a.number).collect::<Vec<_>>(), vec![1, 2]);
+            // lines end at the terminator the regex was built with
+            let regex = RegexBuilder::new("^a$").multi_line(true).line_terminator(b'\0').build().unwrap();
+            let lines = regex.find_lines("a\0a\0");
+            assert_eq!(lines.iter().map(|a| (a.number, a.span.clone())).collect::<Vec<_>>(), vec![(1, 0..1), (2, 2..3)]);
+            assert!(regex.find_lines("a\na\0").is_empty());
+        }
+
+        // hands out its bytes a few at a time, so matches and chars straddle reads
+        struct Trickle<'a>(&'a [u8], usize);
+
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+                let length = self.1.min(self.0.len()).min(buffer.len());
+                buffer[..length].copy_from_slice(&self.0[..length]);
+                self.0 = &self.0[length..];
+                Ok(length)
+            }
+        }
+
+        #[test]
+        fn test_replace_stream() {
+            let replaced = |pattern: &str, haystack: &str, replacement: &str| -> (String, usize) {
+                let mut out = vec![];
+                let count = Regex::new(pattern).unwrap().replace_stream(Trickle(haystack.as_bytes(), 3), &mut out, replacement).unwrap();
+                (String::from_utf8(out).unwrap(), count)
+            };
+            assert_eq!(replaced("ab+c", "xabbbbc abc ab", "[$0]"), ("x[abbbbc] [abc] ab".to_string(), 2));
+            assert_eq!(replaced("(é+)(x?)", "aééé béx", "$2$1"), ("aééé bxé".to_string(), 2));
+            assert_eq!(replaced(r"\bcat$", "cat cat", "dog"), ("cat dog".to_string(), 1));
+            assert_eq!(replaced("a|abcd", "abcabcd", "-"), ("-bc-".to_string(), 2));
+            assert_eq!(replaced("x*", "abxxc", "-"), ("-a-b--c-".to_string(), 5));
+            let mut out = vec![];
+            let invalid = Regex::new("a").unwrap().replace_stream(Trickle(b"a\xff", 1), &mut out, "b");
+            assert_eq!(invalid.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+
+        #[test]
+        fn test_split() {
+            let regex = Regex::new(" *[,;] *").unwrap();
+            let haystack = "a, b;c ,";
+            assert_eq!(regex.split(haystack), vec!["a", "b", "c", ""]);
+            assert_eq!(regex.split_inclusive(haystack), vec!["a, ", "b;", "c ,"]);
+            assert_eq!(regex.split_inclusive("a,b"), vec!["a,", "b"]);
+            let spans = regex.split_spans(haystack);
+            assert_eq!(spans[2], (5..6, Some(6..8)));
+            let rejoined: String = spans.iter().flat_map(|(a, x)| [Some(a.clone()), x.clone()]).flatten().map(|a| &haystack[a]).collect();
+            assert_eq!(rejoined, haystack);
+            assert_eq!(regex.split(""), vec![""]);
+        }
+
+        #[test]
+        fn test_parse_captures() {
+            let regex = Regex::new(r"(?<name>[a-z]+)=(\d+)(?:/(-?\d+))?").unwrap();
+            let captures = regex.captures("x width=640/-2").unwrap();
+            assert_eq!(captures.parse::<(String, u32, i8)>(), Ok(("width".to_string(), 640, -2)));
+            assert_eq!(captures.parse_group::<u16>(2), Ok(640));
+            let error = regex.captures("depth=300").unwrap().parse::<(String, u8)>().unwrap_err();
+            assert_eq!(error.to_string(), "group 2 matched \"300\", which isn't a valid u8: number too large to fit in target type");
+            let error = regex.captures("depth=3").unwrap().parse::<(String, u8, u8)>().unwrap_err();
+            assert_eq!((error.group, error.problem), (3, CaptureProblem::Missing));
+            let error = regex.captures("abc=3").unwrap().parse::<(u8,)>().unwrap_err();
+            assert_eq!(error.name.as_deref(), Some("name"));
+        }
+
+        #[test]
+        fn test_full_match() {
+            for kind in [DfaKind::Off, DfaKind::Dense] {
+                let regex = RegexBuilder::new("[a-z]+@[a-z]+|id[0-9]*").dfa(kind).build().unwrap();
+                assert!(regex.is_full_match("me@example") && regex.is_full_match("id42") && regex.is_full_match("id"));
+                assert!(!regex.is_full_match("me@example!") && !regex.is_full_match(" id42") && !regex.is_full_match(""));
+                assert_eq!(regex.full_match("id7").map(|a| a.range()), Some(0..3));
+            }
+            assert!(Regex::new("a*").unwrap().is_full_match(""));
+        }
+
+        #[test]
+        fn test_prefix_match() {
+            let regex = RegexBuilder::new("[a-z]+@[a-z]+").case_insensitive(true).build().unwrap();
+            assert_eq!(regex.prefix_match("Me@"), PrefixMatch::Partial);
+            assert_eq!(regex.prefix_match("Me@Example"), PrefixMatch::Match);
+            assert_eq!(regex.prefix_match("Me@@"), PrefixMatch::Dead);
+        }
+
+        #[test]
+        fn test_match_at() {
+            let regex = Regex::new(r"\b[a-z]+").unwrap();
+            let haystack = "foo bar";
+            assert_eq!(regex.match_at(haystack, 4).map(|a| a.as_str()), Some("bar"));
+            // inside a word there's no boundary, even though "oo" alone would start with one
+            assert!(!regex.is_match_at(haystack, 1) && regex.is_match(&haystack[1..]));
+            assert!(!regex.is_match_at(haystack, 3));
+            assert!(!Regex::new("^b").unwrap().is_match_at(haystack, 4));
+            assert_eq!(Regex::new("é").unwrap().match_at("aé", 2), None);
+        }
+
+        #[test]
+        fn test_earliest() {
+            let regex = RegexBuilder::new("[0-9]+").earliest(true).build().unwrap();
+            assert_eq!(regex.find_in("ab 123 45"), Some(3..4));
+            assert_eq!(regex.count("ab 123 45"), 5);
+            assert!(regex.is_full_match("123"));
+            let dense = RegexBuilder::new("[0-9]+").earliest(true).dfa(DfaKind::Dense).build().unwrap();
+            assert_eq!(dense.split("1a22"), ["", "a", "", ""]);
+        }
+
+        #[test]
+        fn test_suffix_prefilter() {
+            let regex = Regex::new("[0-9]+px").unwrap();
+            assert!(regex.compiled.factor.is_some());
+            assert_eq!(regex.find_all("12px, 3em, 4px, px").iter().map(|a| a.location).collect::<Vec<_>>(), [0, 11]);
+            assert_eq!(regex.count("no lengths here, px"), 0);
+            // the first px a match could end at isn't the one the leftmost match ends at
+            let regex = Regex::new("x.*ypx|px").unwrap();
+            assert_eq!(regex.find("x px ypx").map(|a| a.matched_string), Some("x px ypx".to_string()));
+            assert_eq!(Regex::new("é+x|ax").unwrap().count("aéx é"), 1);
+            // so it's only there that the search can't skip to the start the factor gives
+            assert!(!regex.compiled.factor.as_ref().unwrap().skips);
+            let regex = RegexBuilder::new(r"[a-z]+@example\.com").dfa(DfaKind::Off).build().unwrap();
+            assert!(regex.compiled.factor.as_ref().unwrap().skips);
+            let haystack = format!("{}me@example.com", "abcdefghij ".repeat(1000));
+            let mut metrics = Metrics::default();
+            assert_eq!(regex.search_counted(haystack.as_str(), &SearchConfig::default(), &mut metrics), Some(11000..11014));
+            assert_eq!((metrics.positions, metrics.skipped_positions), (1, 11000));
+        }
+
+        #[test]
+        fn test_inner_factor_prefilter() {
+            let regex = Regex::new(r"[a-z]+@example\.com\b").unwrap();
+            assert_eq!(regex.compiled.factor.as_ref().map(|a| a.literal.as_str()), Some("@example.com"));
+            let found: Vec<String> = regex.find_all("bob@example.com, @example.com eve@example.comx amy@example.com").into_iter().map(|a| a.matched_string).collect();
+            assert_eq!(found, ["bob@example.com", "amy@example.com"]);
+            assert_eq!(regex.count("bob@example.org"), 0);
+            // a look before the factor would have to be read backwards, so there's none
+            assert!(Regex::new(r"\bx+@y\w").unwrap().compiled.factor.is_none());
+            // the automaton only runs from where a match through the factor could start, not from
+            // every position before it
+            let haystack = format!("{}me@example.com", "abcdefghij ".repeat(1000));
+            for pattern in [r"\w+@example\.com", r"\w+@example\.(com|org)"] {
+                let regex = RegexBuilder::new(pattern).dfa(DfaKind::Off).build().unwrap();
+                let mut metrics = Metrics::default();
+                assert_eq!(regex.search_counted(haystack.as_str(), &SearchConfig::default(), &mut metrics), Some(11000..11014));
+                assert_eq!((metrics.positions, metrics.skipped_positions), (1, 11000), "{}", pattern);
+            }
+        }
+
+        #[test]
+        fn test_start_bytes() {
+            let regex = Regex::new("[xy][0-9]+|z").unwrap();
+            assert_eq!(regex.compiled.start_bytes.as_deref(), Some(&b"xyz"[..]));
+            let found: Vec<usize> = regex.find_all("aax12 y z x bby9").into_iter().map(|a| a.location).collect();
+            assert_eq!(found, [2, 8, 14]);
+            assert!(Regex::new("[a-e]x").unwrap().compiled.start_bytes.is_none());
+        }
+
+        #[test]
+        fn test_empty_matches() {
+            let found = |regex: &Regex| -> Vec<(String, usize)> { regex.find_all("baab").into_iter().map(|a| (a.matched_string, a.location)).collect() };
+            let all = Regex::new("a*").unwrap();
+            assert_eq!(found(&all), vec![("".into(), 0), ("aa".into(), 1), ("".into(), 3), ("".into(), 4)]);
+            let skip = RegexBuilder::new("a*").empty_matches(EmptyMatches::SkipAdjacent).build().unwrap();
+            assert_eq!(found(&skip), vec![("".into(), 0), ("aa".into(), 1), ("".into(), 4)]);
+            assert_eq!(skip.count("baab"), 3);
+            // only an empty match touching the previous one goes, never a non-empty one
+            let skip = RegexBuilder::new("a|b*").empty_matches(EmptyMatches::SkipAdjacent).build().unwrap();
+            assert_eq!(found(&skip).len(), 4);
+        }
+
+        #[test]
+        fn test_whole_word() {
+            let found = |regex: &Regex, haystack: &str| -> Vec<String> { regex.find_all(haystack).into_iter().map(|a| a.matched_string).collect() };
+            let regex = RegexBuilder::new("cat|dog").whole_word(true).build().unwrap();
+            assert_eq!(found(&regex, "cat catalog hotdog dog_ dog."), vec!["cat", "dog"]);
+            let regex = RegexBuilder::new("(a+)b").whole_word(true).build().unwrap();
+            assert_eq!(spans(regex.captures("ab aab")), Some(vec![Some(0..2), Some(0..1)]));
+            let regex = Regex::new("\\Bat\\b").unwrap();
+            assert_eq!(found(&regex, "at cat attic"), vec!["at"]);
+        }
+
+        #[test]
+        fn test_unicode_word_boundary() {
+            #[cfg(feature = "unicode")]
+            {
+                let found = |regex: &Regex, haystack: &str| -> Vec<String> { regex.find_all(haystack).into_iter().map(|a| a.matched_string).collect() };
+                // the apostrophe doesn't end a word the way it ends a run of \w
+                assert_eq!(found(&Regex::new("\\b\\w+\\b").unwrap(), "can't go"), vec!["can", "t", "go"]);
+                assert_eq!(found(&Regex::new("\\b{wb}\\w+\\b{wb}").unwrap(), "can't go"), vec!["go"]);
+                assert_eq!(found(&Regex::new("\\d\\B{wb}.").unwrap(), "3.14 5 6"), vec!["3.", "14"]);
+            }
+            #[cfg(not(feature = "unicode"))]
+            assert_eq!(Regex::new("\\b{wb}").err(), Some(ParseError::UnknownBoundary("wb".to_string())));
+            assert_eq!(Regex::new("a\\B{sentence}").err(), Some(ParseError::UnknownBoundary("sentence".to_string())));
+        }
+
+        #[test]
+        fn test_ascii_words() {
+            let found = |regex: &Regex, haystack: &str| -> Vec<String> { regex.find_all(haystack).into_iter().map(|a| a.matched_string).collect() };
+            let regex = RegexBuilder::new("\\b\\w+\\b").ascii_words(true).build().unwrap();
+            assert_eq!(found(&regex, "naïve"), vec!["na", "ve"]);
+            let regex = RegexBuilder::new("[^\\W\\d]+|\\W").ascii_words(true).build().unwrap();
+            assert_eq!(found(&regex, "aï2"), vec!["a", "ï"]);
+            let regex = RegexBuilder::new("ve").whole_word(true).ascii_words(true).build().unwrap();
+            assert_eq!(found(&regex, "naïve"), vec!["ve"]);
+            #[cfg(feature = "unicode")]
+            assert_eq!(found(&Regex::new("\\b\\w+\\b").unwrap(), "naïve"), vec!["naïve"]);
+        }
+
+        #[test]
+        #[cfg(feature = "unicode")]
+        fn test_normalization_insensitive() {
+            let haystack = "café cafe\u{301} cafe";
+            for pattern in ["café", "cafe\u{301}", "caf[é]", "caf[à-ÿ]"] {
+                let regex = RegexBuilder::new(pattern).normalization_insensitive(true).build().unwrap();
+                let found: Vec<String> = regex.find_all(haystack).into_iter().map(|a| a.matched_string).collect();
+                assert_eq!(found, vec!["café", "cafe\u{301}"], "{}", pattern);
+            }
+            assert_eq!(Regex::new("café").unwrap().find_all(haystack).len(), 1);
+        }
+
+        #[test]
+        fn test_clones_are_shared() {
+            fn shareable<T: Send + Sync>(_: &T) {}
+            let regex = Regex::new("a+").unwrap();
+            shareable(&regex);
+            let clone = regex.clone();
+            assert!(Arc::ptr_eq(&regex.compiled, &clone.compiled));
+            let found = std::thread::spawn(move || clone.find_all("aa b a").len()).join().unwrap();
+            assert_eq!(found, 2);
+        }
+
+        #[test]
+        fn test_regex_cache() {
+            let mut cache = RegexCache::new(2);
+            let first = cache.get("a+").unwrap();
+            assert!(Arc::ptr_eq(&first.compiled, &cache.get("a+").unwrap().compiled));
+            // same pattern, different engine, different entry
+            let sparse = cache.get_with(RegexBuilder::new("a+").dfa(DfaKind::Sparse)).unwrap();
+            assert!(!Arc::ptr_eq(&first.compiled, &sparse.compiled));
+            assert_eq!(cache.len(), 2);
+
+            // "a+" was used more recently than the sparse one, so that's what gets evicted
+            cache.get("a+").unwrap();
+            cache.get("b").unwrap();
+            assert_eq!(cache.len(), 2);
+            assert!(Arc::ptr_eq(&first.compiled, &cache.get("a+").unwrap().compiled));
+            assert!(!Arc::ptr_eq(&sparse.compiled, &cache.get_with(RegexBuilder::new("a+").dfa(DfaKind::Sparse)).unwrap().compiled));
+            assert!(cache.get("(").is_err());
+        }
+    }
+}
@@ -0,0 +1,65 @@
+pub mod scanner {
+    use std::ops::Range;
+
+    use crate::automata::automata::{epsilon_closures, Closures, longest_pattern_match_at};
+    use crate::graph::graph::Program;
+    use crate::parser::parser::{graph_from_patterns, CharCost, ParseError};
+
+    // a tokenizer: one pattern per kind of token, and at every position the longest match of any
+    // of them wins. A tie goes to whichever pattern was listed first, so keywords listed before
+    // identifiers beat them.
+    #[derive(Debug, Clone)]
+    pub struct Scanner {
+        program: Program<CharCost>,
+        closures: Closures
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Token {
+        // the index of the pattern in the list the Scanner was made from
+        pub pattern: usize,
+        pub span: Range<usize>
+    }
+
+    impl Scanner {
+        pub fn new(patterns: &[&str]) -> Result<Self, ParseError> {
+            let program = graph_from_patterns(patterns)?.compile().freeze();
+            let closures = epsilon_closures(&program);
+            Ok(Scanner { program, closures })
+        }
+
+        // an empty match is no token, since taking it would never move the scan on
+        pub fn token_at(&self, haystack: &str, position: usize) -> Option<Token> {
+            let (end, pattern) = longest_pattern_match_at(&self.program, &self.closures, haystack, position)?;
+            (end > position).then_some(Token { pattern, span: position..end })
+        }
+
+        // the tokens from the start of haystack, one straight after another. Where no pattern
+        // matches, that offset comes out as an error and the scan stops.
+        pub fn tokens<'a>(&'a self, haystack: &'a str) -> impl Iterator<Item = Result<Token, usize>> + 'a {
+            let mut position = Some(0);
+            std::iter::from_fn(move || {
+                let start = position.filter(|a| *a < haystack.len())?;
+                let token = self.token_at(haystack, start);
+                position = token.as_ref().map(|a| a.span.end);
+                Some(token.ok_or(start))
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_tokens() {
+            let scanner = Scanner::new(&["if", "[a-z]+", "[0-9]+", " +", "==|="]).unwrap();
+            let haystack = "if iffy == 42";
+            let tokens: Vec<(usize, &str)> = scanner.tokens(haystack).map(|a| a.unwrap()).map(|a| (a.pattern, &haystack[a.span])).collect();
+            assert_eq!(tokens, vec![(0, "if"), (3, " "), (1, "iffy"), (3, " "), (4, "=="), (3, " "), (2, "42")]);
+            let found: Vec<Result<usize, usize>> = scanner.tokens("a+b").map(|a| a.map(|x| x.pattern)).collect();
+            assert_eq!(found, vec![Ok(1), Err(1)]);
+            assert_eq!(Scanner::new(&["a*"]).unwrap().token_at("b", 0), None);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+pub mod segment {
+    use std::cmp::Ordering;
+
+    use crate::haystack::haystack::Haystack;
+    use crate::unicode::unicode::{WordBreak, EXTENDED_PICTOGRAPHIC, WORD_BREAK};
+
+    fn word_break(letter: char) -> Option<WordBreak> {
+        let letter = letter as u32;
+        let found = WORD_BREAK.binary_search_by(|(start, end, _)| {
+            if *end < letter {
+                Ordering::Less
+            } else if *start > letter {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        });
+        found.ok().map(|a| WORD_BREAK[a].2)
+    }
+
+    fn is_extended_pictographic(letter: char) -> bool {
+        let letter = letter as u32;
+        EXTENDED_PICTOGRAPHIC.binary_search_by(|(start, end)| {
+            if *end < letter {
+                Ordering::Less
+            } else if *start > letter {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        }).is_ok()
+    }
+
+    // what rule WB4 sees through: these stick to whatever they follow
+    fn is_ignored(value: Option<WordBreak>) -> bool {
+        matches!(value, Some(WordBreak::Extend | WordBreak::Format | WordBreak::Zwj))
+    }
+
+    fn is_letter(value: Option<WordBreak>) -> bool {
+        matches!(value, Some(WordBreak::ALetter | WordBreak::HebrewLetter))
+    }
+
+    fn is_mid_letter(value: Option<WordBreak>) -> bool {
+        matches!(value, Some(WordBreak::MidLetter | WordBreak::MidNumLet | WordBreak::SingleQuote))
+    }
+
+    fn is_mid_num(value: Option<WordBreak>) -> bool {
+        matches!(value, Some(WordBreak::MidNum | WordBreak::MidNumLet | WordBreak::SingleQuote))
+    }
+
+    // the Word_Break values before offset, nearest first, with the ones WB4 sees through skipped
+    fn values_before<H: Haystack + ?Sized>(code: &H, mut offset: usize) -> impl Iterator<Item = Option<WordBreak>> + '_ {
+        std::iter::from_fn(move || {
+            let letter = code.char_before(offset)?;
+            offset -= letter.len_utf8();
+            Some(word_break(letter))
+        }).filter(|a| !is_ignored(*a))
+    }
+
+    fn values_after<H: Haystack + ?Sized>(code: &H, mut offset: usize) -> impl Iterator<Item = Option<WordBreak>> + '_ {
+        std::iter::from_fn(move || {
+            let (letter, length) = code.char_at(offset)?;
+            offset += length;
+            Some(word_break(letter))
+        }).filter(|a| !is_ignored(*a))
+    }
+
+    // whether offset is a word boundary as UAX #29 has it, going through its rules in order. Unlike
+    // \b that's between any two words, spaces and punctuation included, and it keeps together
+    // things like can't, 3.14 and a flag's pair of regional indicators.
+    pub(crate) fn is_word_break<H: Haystack + ?Sized>(code: &H, offset: usize) -> bool {
+        let (before, after) = match (code.char_before(offset), code.char_at(offset)) {
+            (Some(before), Some((after, _))) => (before, after),
+            // WB1 and WB2, though an empty haystack has no boundaries at all
+            (before, after) => return before.is_some() || after.is_some()
+        };
+        let (left, right) = (word_break(before), word_break(after));
+        use WordBreak::*;
+        match (left, right) {
+            (Some(CR), Some(LF)) => return false,
+            (Some(Newline | CR | LF), _) | (_, Some(Newline | CR | LF)) => return true,
+            (Some(Zwj), _) if is_extended_pictographic(after) => return false,
+            (Some(WSegSpace), Some(WSegSpace)) => return false,
+            (_, right) if is_ignored(right) => return false,
+            _ => {}
+        }
+        let mut lefts = values_before(code, offset);
+        let (left, left2) = (lefts.next().flatten(), lefts.next().flatten());
+        let right2 = values_after(code, offset).nth(1).flatten();
+        let keep = match (left, right) {
+            (left, right) if is_letter(left) && is_letter(right) => true,
+            (left, right) if is_letter(left) && is_mid_letter(right) && is_letter(right2) => true,
+            (left, right) if is_letter(left2) && is_mid_letter(left) && is_letter(right) => true,
+            (Some(HebrewLetter), Some(SingleQuote)) => true,
+            (Some(HebrewLetter), Some(DoubleQuote)) => right2 == Some(HebrewLetter),
+            (Some(DoubleQuote), Some(HebrewLetter)) => left2 == Some(HebrewLetter),
+            (Some(Numeric), Some(Numeric)) => true,
+            (left, Some(Numeric)) if is_letter(left) => true,
+            (Some(Numeric), right) if is_letter(right) => true,
+            (left, Some(Numeric)) if is_mid_num(left) => left2 == Some(Numeric),
+            (Some(Numeric), right) if is_mid_num(right) => right2 == Some(Numeric),
+            (Some(Katakana), Some(Katakana)) => true,
+            (Some(Numeric | Katakana | ExtendNumLet), Some(ExtendNumLet)) => true,
+            (left, Some(ExtendNumLet)) if is_letter(left) => true,
+            (Some(ExtendNumLet), Some(Numeric | Katakana)) => true,
+            (Some(ExtendNumLet), right) if is_letter(right) => true,
+            // flags are pairs of regional indicators, so this one joins on if an odd number came before
+            (Some(RegionalIndicator), Some(RegionalIndicator)) => {
+                values_before(code, offset).take_while(|a| *a == Some(RegionalIndicator)).count() % 2 == 1
+            }
+            _ => false
+        };
+        !keep
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn words(text: &str) -> Vec<&str> {
+            let breaks: Vec<usize> = (0..=text.len()).filter(|a| text.is_char_boundary(*a) && is_word_break(text, *a)).collect();
+            breaks.windows(2).map(|a| &text[a[0]..a[1]]).collect()
+        }
+
+        #[test]
+        fn test_word_breaks() {
+            assert_eq!(words("can't stop, 3.14!"), vec!["can't", " ", "stop", ",", " ", "3.14", "!"]);
+            assert_eq!(words("e\u{301}t\u{e9}  \r\nx"), vec!["e\u{301}t\u{e9}", "  ", "\r\n", "x"]);
+            // two flags, not one run of four regional indicators
+            assert_eq!(words("🇫🇷🇩🇪"), vec!["🇫🇷", "🇩🇪"]);
+            assert_eq!(words("👩\u{200d}💻 a_1"), vec!["👩\u{200d}💻", " ", "a_1"]);
+            assert!(words("").is_empty());
+        }
+    }
+}
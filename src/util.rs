@@ -0,0 +1,226 @@
+pub mod util {
+    use std::cell::Cell;
+
+    // a set of ids below a fixed capacity with constant time insert, contains and clear. dense holds
+    // the members in insertion order and sparse[id] says where in dense id would be, so a lookup only
+    // has to check the two agree. Neither array ever needs zeroing, which is what makes clear cheap.
+    #[derive(Debug, Clone)]
+    pub struct SparseSet {
+        dense: Vec<usize>,
+        sparse: Vec<usize>
+    }
+
+    impl SparseSet {
+        pub fn new(capacity: usize) -> Self {
+            SparseSet {
+                dense: Vec::with_capacity(capacity),
+                sparse: vec![0; capacity]
+            }
+        }
+
+        pub fn capacity(&self) -> usize {
+            self.sparse.len()
+        }
+
+        pub fn len(&self) -> usize {
+            self.dense.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.dense.is_empty()
+        }
+
+        pub fn contains(&self, id: usize) -> bool {
+            self.sparse.get(id).is_some_and(|a| self.dense.get(*a) == Some(&id))
+        }
+
+        // false if id was already there
+        pub fn insert(&mut self, id: usize) -> bool {
+            assert!(id < self.capacity(), "{} doesn't fit in a SparseSet of capacity {}", id, self.capacity());
+            if self.contains(id) {
+                return false;
+            }
+            self.sparse[id] = self.dense.len();
+            self.dense.push(id);
+            true
+        }
+
+        pub fn clear(&mut self) {
+            self.dense.clear();
+        }
+
+        // members in the order they were inserted
+        pub fn as_slice(&self) -> &[usize] {
+            &self.dense
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+            self.dense.iter().copied()
+        }
+    }
+
+    // what searches leave behind for the next one to reuse: the sets they kept states in, and the
+    // list states were parked in by offset, emptied but with its room kept
+    #[derive(Debug, Default)]
+    pub struct Spares {
+        sets: Vec<SparseSet>,
+        pending: Vec<(usize, SparseSet)>
+    }
+
+    impl Spares {
+        pub fn with_sets(sets: Vec<SparseSet>) -> Self {
+            Spares { sets, pending: vec![] }
+        }
+    }
+
+    thread_local! {
+        static SPARES: Cell<Spares> = const { Cell::new(Spares { sets: Vec::new(), pending: Vec::new() }) };
+    }
+
+    // more than this many spare sets is more than any one search has needed at once
+    const MAX_SPARE_SETS: usize = 64;
+
+    // the Spares a search has to work with, kept per thread for the next search to reuse rather
+    // than allocate again. The whole pool is taken out while in use and put back when dropped, so
+    // a search started from inside another one just gets an empty pool.
+    #[derive(Debug, Default)]
+    pub struct ScratchPool {
+        spares: Spares
+    }
+
+    impl ScratchPool {
+        pub fn take() -> Self {
+            ScratchPool { spares: SPARES.take() }
+        }
+
+        // an empty set with room for at least capacity ids
+        pub fn set(&mut self, capacity: usize) -> SparseSet {
+            match self.spares.sets.iter().position(|a| a.capacity() >= capacity) {
+                Some(index) => self.spares.sets.swap_remove(index),
+                None => SparseSet::new(capacity)
+            }
+        }
+
+        pub fn give(&mut self, mut set: SparseSet) {
+            if self.spares.sets.len() < MAX_SPARE_SETS {
+                set.clear();
+                self.spares.sets.push(set);
+            }
+        }
+
+        // an empty list for parking sets by offset, to go back through give_pending
+        pub fn pending(&mut self) -> Vec<(usize, SparseSet)> {
+            std::mem::take(&mut self.spares.pending)
+        }
+
+        // takes back the list and whatever sets are still parked in it
+        pub fn give_pending(&mut self, mut pending: Vec<(usize, SparseSet)>) {
+            for (_, set) in pending.drain(..) {
+                self.give(set);
+            }
+            self.spares.pending = pending;
+        }
+
+        pub fn len(&self) -> usize {
+            self.spares.sets.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.spares.sets.is_empty()
+        }
+    }
+
+    // runs f with spares standing in for the thread's own, so the searches in it reuse those and
+    // leave theirs in spares afterwards, and the thread's own are left as they were
+    pub fn with_spares<R>(spares: &mut Spares, f: impl FnOnce() -> R) -> R {
+        let own = SPARES.replace(std::mem::take(spares));
+        let result = f();
+        *spares = SPARES.replace(own);
+        result
+    }
+
+    impl Drop for ScratchPool {
+        fn drop(&mut self) {
+            SPARES.set(std::mem::take(&mut self.spares));
+        }
+    }
+
+    // the first byte of haystack that's one of needles, of which there are one to three, looked
+    // for eight bytes at a time. A byte of word ^ splat(needle) is zero just where needle is, and
+    // (x - 0x01..01) & !x & 0x80..80 is nonzero just when some byte of x is zero.
+    pub fn memchr(needles: &[u8], haystack: &[u8]) -> Option<usize> {
+        assert!((1..=3).contains(&needles.len()), "memchr looks for one to three bytes, not {}", needles.len());
+        const LOW: u64 = 0x0101010101010101;
+        const HIGH: u64 = 0x8080808080808080;
+        let splats = [0, 1, 2].map(|a| LOW * needles[a.min(needles.len() - 1)] as u64);
+        let mut chunks = haystack.chunks_exact(8);
+        for (index, chunk) in chunks.by_ref().enumerate() {
+            let word = u64::from_le_bytes(chunk.try_into().unwrap());
+            if splats.iter().any(|a| (word ^ a).wrapping_sub(LOW) & !(word ^ a) & HIGH != 0) {
+                return chunk.iter().position(|a| needles.contains(a)).map(|a| index * 8 + a);
+            }
+        }
+        let rest = chunks.remainder();
+        rest.iter().position(|a| needles.contains(a)).map(|a| haystack.len() - rest.len() + a)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_sparse_set() {
+            let mut set = SparseSet::new(10);
+            assert!(set.is_empty());
+            assert!(set.insert(7));
+            assert!(set.insert(2));
+            assert!(!set.insert(7));
+            assert!(set.contains(2) && set.contains(7));
+            assert!(!set.contains(0) && !set.contains(42));
+            assert_eq!(set.iter().collect::<Vec<_>>(), vec![7, 2]);
+
+            set.clear();
+            assert!(set.is_empty());
+            assert!(!set.contains(7));
+            assert!(set.insert(2));
+            assert_eq!(set.len(), 1);
+        }
+
+        #[test]
+        fn test_scratch_pool() {
+            let mut pool = ScratchPool::take();
+            let mut set = pool.set(10);
+            set.insert(3);
+            pool.give(set);
+            drop(pool);
+            let mut pool = ScratchPool::take();
+            assert_eq!(pool.len(), 1);
+            let set = pool.set(5);
+            assert!(set.is_empty() && set.capacity() == 10);
+            assert_eq!(pool.set(20).capacity(), 20);
+        }
+
+        #[test]
+        fn test_scratch_pool_pending() {
+            let mut pool = ScratchPool::take();
+            let mut pending = pool.pending();
+            pending.reserve(4);
+            pending.push((3, pool.set(10)));
+            let sets = pool.len();
+            pool.give_pending(pending);
+            assert_eq!(pool.len(), sets + 1);
+            let pending = pool.pending();
+            assert!(pending.is_empty() && pending.capacity() >= 4);
+        }
+
+        #[test]
+        fn test_memchr() {
+            let haystack = b"the quick brown fox jumps";
+            assert_eq!(memchr(b"q", haystack), Some(4));
+            assert_eq!(memchr(b"xj", haystack), Some(18));
+            assert_eq!(memchr(b"zsp", haystack), Some(23));
+            assert_eq!(memchr(b"\x80\xff", haystack), None);
+            assert_eq!(memchr(b"a", b""), None);
+        }
+    }
+}